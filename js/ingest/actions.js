@@ -2,6 +2,14 @@ import * as s from './states.js';
 import DOM from './dom.js';
 import { state, setState, updateState, initialState } from './store.js';
 
+const UPLOAD_POLL_INTERVAL_MS = 500;
+
+// The file is sent in chunks, so an interrupted upload resumes from the last
+// chunk that made it to the server. See site/upload.rs
+const UPLOAD_CHUNK_BYTES = 1024 * 1024;
+const UPLOAD_RETRY_INTERVAL_MS = 2000;
+const UPLOAD_MAX_RETRIES = 10;
+
 // Safe aspect ratios:
 
 // From the author's tall, narrow phone:
@@ -36,6 +44,96 @@ function setDetails(details) {
     }
 }
 
+function sleep(ms) {
+    return new Promise(resolve => setTimeout(resolve, ms));
+}
+
+// Resolves to the status URL of the ingest job when the whole file has been
+// received
+async function uploadResumable(file) {
+    const created = await (
+        fetch('upload/', {
+            method: 'POST',
+            body: JSON.stringify({ media_type: file.type, size: file.size }),
+            headers: {
+                'Content-Type': 'application/json'
+            },
+            credentials: 'same-origin',
+            redirect: 'follow',
+        }).catch(function (err) {
+            // Low level error situation, such as network error
+            throw {
+                err: err,
+                hint: s.ERROR_CHECK_CONNECTIVITY,
+            };
+        })
+    );
+
+    if (created.status != 201 || !created.headers.get('Location')) {
+        throw {
+            err: "Unexpected status code: " + created.status + " " + created.statusText,
+            hint: s.ERROR_TRY_AGAIN,
+        };
+    }
+    const uploadUrl = created.headers.get('Location');
+
+    let offset = 0;
+    let retries = 0;
+    for (;;) {
+        const end = Math.min(offset + UPLOAD_CHUNK_BYTES, file.size);
+
+        let res;
+        try {
+            res = await fetch(uploadUrl, {
+                method: 'PUT',
+                body: file.slice(offset, end),
+                headers: {
+                    'Content-Range': 'bytes ' + offset + '-' + (end - 1) + '/' + file.size
+                },
+                credentials: 'same-origin',
+            });
+        }
+        catch (err) {
+            if (++retries > UPLOAD_MAX_RETRIES) {
+                throw {
+                    err: err,
+                    hint: s.ERROR_CHECK_CONNECTIVITY,
+                };
+            }
+
+            // Ask the server how much it got before continuing
+            await sleep(UPLOAD_RETRY_INTERVAL_MS);
+            res = await fetch(uploadUrl, { credentials: 'same-origin' }).catch(() => null);
+            if (!res) continue;
+        }
+
+        let progress;
+        try {
+            if (res.status != 200 && res.status != 202 && res.status != 409) {
+                throw "Unexpected status code: " + res.status + " " + res.statusText;
+            }
+
+            progress = await res.json();
+        }
+        catch (err) {
+            // Unexpected error
+            throw {
+                err: err,
+                hint: s.ERROR_TRY_AGAIN,
+            };
+        }
+
+        if (progress.status_url) {
+            return progress.status_url;
+        }
+
+        if (progress.offset > offset) {
+            retries = 0;
+        }
+        offset = progress.offset;
+    }
+}
+
 export const actions = {
     selectFile: function (file) {
         if (file) {
@@ -88,6 +186,7 @@ export const actions = {
             },
             savedComment: "",
             comment: "",
+            duplicates: [],
         });
     },
     reset: function () {
@@ -97,6 +196,7 @@ export const actions = {
         updateState({
             phase: s.PHASE_DETAILS,
             uploadPhase: s.UPLOAD_PHASE_IN_PROGRESS,
+            uploadStage: null,
             uploadResult: null,
             uploadError: null,
             pixurUrl: null,
@@ -105,40 +205,53 @@ export const actions = {
         });
 
         async function async_upload(file) {
-            const res = await (
-                fetch('img/', {
-                    method: 'POST',
-                    body: file,
-                    credentials: 'same-origin',
-                    redirect: 'follow',
-                }).catch(function (err) {
-                    // Low level error situation, such as network error
+            const statusUrl = await uploadResumable(file);
+
+            // The upload is processed in the background
+            for (;;) {
+                await sleep(UPLOAD_POLL_INTERVAL_MS);
+
+                const status = await (
+                    fetch(statusUrl, {
+                        credentials: 'same-origin',
+                        redirect: 'follow',
+                    }).catch(function (err) {
+                        throw {
+                            err: err,
+                            hint: s.ERROR_CHECK_CONNECTIVITY,
+                        };
+                    })
+                );
+
+                let json;
+                try {
+                    if (!status.ok) {
+                        throw "Unexpected status code: " + status.status + " " + status.statusText;
+                    }
+
+                    json = await status.json();
+
+                    if (json.stage == "failed") {
+                        throw "Processing failed: " + json.error;
+                    }
+
+                    if (json.stage == "done" && (!json.url || !json.series_url)) {
+                        throw "Malformed response from server";
+                    }
+                }
+                catch (err) {
+                    // Unexpected error
                     throw {
                         err: err,
-                        hint: s.ERROR_CHECK_CONNECTIVITY,
+                        hint: s.ERROR_TRY_AGAIN,
                     };
-                })
-            );
-
-            try {
-                if (!res.ok) {
-                    throw "Unexpected status code: " + res.status + " " + res.statusText;
                 }
 
-                const json = await res.json();
-
-                if (!json.url || !json.series_url) {
-                    throw "Malformed response from server";
+                if (json.stage == "done") {
+                    return json;
                 }
 
-                return json;
-            }
-            catch (err) {
-                // Unexpected error
-                throw {
-                    err: err,
-                    hint: s.ERROR_TRY_AGAIN,
-                };
+                updateState({ uploadStage: json.stage });
             }
         }
 
@@ -149,6 +262,7 @@ export const actions = {
             .catch(function (err) {
                 updateState({
                     uploadPhase: s.UPLOAD_PHASE_FINISHED,
+                    uploadStage: null,
                     uploadResult: s.UPLOAD_STATE_FAILURE,
                     uploadError: err,
                 });
@@ -157,9 +271,11 @@ export const actions = {
     uploadFinished: function (locations) {
         updateState({
             uploadPhase: s.UPLOAD_PHASE_FINISHED,
+            uploadStage: null,
             uploadResult: s.UPLOAD_STATE_SUCCESS,
             pixurUrl: locations.url,
             seriesUrl: locations.series_url,
+            duplicates: locations.duplicates || [],
         });
     },
     submitDetails: function () {
@@ -302,6 +418,7 @@ export const actions = {
             pixurUrl,
             seriesUrl: null,
             previewUrl: thumb,
+            duplicates: [],
             loadDetailsState: s.LOAD_DETAILS_PENDING,
             saveDetailsState: s.SAVE_DETAILS_INITIAL,
         });
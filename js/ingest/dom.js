@@ -12,8 +12,11 @@ const DOM = {
         errorMessage: document.querySelector('.uploader-form--error-message'),
         uploadError: document.querySelector('.uploader-form--upload-error'),
         statusUploading: document.querySelector('.uploader-form--status__uploading'),
+        stage: document.querySelector('.uploader-form--stage'),
         statusUploaded: document.querySelector('.uploader-form--status__uploaded'),
         url: document.querySelector('.uploader-form--url'),
+        duplicates: document.querySelector('.uploader-form--duplicates'),
+        duplicateList: document.querySelector('.uploader-form--duplicate-list'),
     },
     crop: {
         horizontal: {
@@ -47,6 +47,11 @@ function renderUpload(prev, next) {
         DOM.uploader.statusUploaded.style.display = (next.uploadPhase == s.UPLOAD_PHASE_FINISHED ? 'block' : 'none');
     }
 
+    if (next.uploadStage != prev.uploadStage) {
+        DOM.uploader.stage.textContent =
+            next.uploadStage ? "(" + s.UPLOAD_STAGES[next.uploadStage] + ")" : "";
+    }
+
     if (next.uploadError != prev.uploadError) {
         if (next.uploadError) {
             DOM.uploader.errorMessage.textContent = next.uploadError.hint;
@@ -61,6 +66,35 @@ function renderUpload(prev, next) {
     }
 }
 
+function renderDuplicates(prev, next) {
+    if (next.duplicates === prev.duplicates) return;
+
+    DOM.uploader.duplicates.style.display = next.duplicates.length ? 'block' : 'none';
+
+    const list = DOM.uploader.duplicateList;
+    list.textContent = "";
+    for (let duplicate of next.duplicates) {
+        const img = document.createElement("img");
+        img.className = "uploader-form--duplicate-thumbnail";
+        img.src = duplicate.thumb_url;
+        img.alt = "Tidligere opplastet bilde";
+
+        const button = document.createElement("button");
+        button.type = "button";
+        button.className = "uploader-form--duplicate";
+        button.appendChild(img);
+        button.addEventListener('click', function (ev) {
+            ev.preventDefault();
+            ev.stopPropagation();
+            actions.selectExistingImage(duplicate.url, duplicate.thumb_url, duplicate.image_url);
+        });
+
+        const li = document.createElement("li");
+        li.appendChild(button);
+        list.appendChild(li);
+    }
+}
+
 let submitHandlerChanged = false;
 let submitHandlerAttached = false;
 
@@ -207,6 +241,7 @@ function render(prev, next) {
 
     renderPreview(prev, next);
     renderUpload(prev, next);
+    renderDuplicates(prev, next);
     renderMetadataForm(prev, next);
     crop.render(prev, next);
     renderEmailForm(prev, next);
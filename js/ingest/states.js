@@ -6,6 +6,16 @@ export const UPLOAD_PHASE_INACTIVE = 0;
 export const UPLOAD_PHASE_IN_PROGRESS = 1;
 export const UPLOAD_PHASE_FINISHED = 2;
 
+// Stages of processing an upload on the server, see ingest_job::Stage
+export const UPLOAD_STAGES = {
+    queued: "venter i kø",
+    decoding: "leser bildet",
+    linearizing: "konverterer farger",
+    downscaling: "skalerer ned",
+    encoding: "komprimerer",
+    storing: "lagrer",
+};
+
 export const UPLOAD_STATE_FAILURE = false;
 export const UPLOAD_STATE_SUCCESS = true;
 
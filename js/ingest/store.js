@@ -7,6 +7,7 @@ import viewModel from './viewmodel.js';
 export const initialState = {
     phase: s.PHASE_INITIAL,
     uploadPhase: s.UPLOAD_PHASE_INACTIVE,
+    uploadStage: null,
     loadDetailsState: s.LOAD_DETAILS_READY,
     saveDetailsState: s.SAVE_DETAILS_INITIAL,
     previewUrl: "",
@@ -18,6 +19,7 @@ export const initialState = {
     recipients: [],
     cropHorizontal: {},
     cropVertical: {},
+    duplicates: [],
 };
 export let state = initialState;
 
@@ -1,8 +1,8 @@
-async function saveSeries(series, recipients) {
+async function saveSeries(series, recipients, allow_original_download) {
     let res =
         await fetch("", {
             method: 'POST',
-            body: JSON.stringify({ series, recipients }),
+            body: JSON.stringify({ series, recipients, allow_original_download }),
             headers: {
                 'Content-Type': 'application/json'
             },
@@ -38,7 +38,9 @@ document.getElementById("form").addEventListener('submit', function (ev) {
         recipients.push(rec[i].value);
     }
 
-    saveSeries(series, recipients)
+    const allowOriginalDownload = document.getElementById("allow-original-download").checked;
+
+    saveSeries(series, recipients, allowOriginalDownload)
         .catch(err => alert(err));
 });
 
@@ -78,6 +80,24 @@ document.getElementById("add-photo").addEventListener('click', function (ev) {
 });
 
 
+document.getElementById("sort-by-time").addEventListener('click', function (ev) {
+    ev.preventDefault();
+    ev.stopPropagation();
+
+    const list = document.querySelector(".series");
+    const items = Array.from(list.querySelectorAll(".series--item"));
+
+    // Items without a known time, such as newly added ones, go last. The
+    // ISO 8601 timestamps sort correctly as strings
+    const key = item => item.getAttribute("data-sort-time") || "\uffff";
+    items.sort((a, b) => key(a) < key(b) ? -1 : key(a) > key(b) ? 1 : 0);
+
+    for (let item of items) {
+        list.appendChild(item);
+    }
+});
+
+
 
 // Drag sorting due to https://stackoverflow.com/a/28962290
 let listRoot = document.querySelector("ul");
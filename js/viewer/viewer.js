@@ -22,6 +22,16 @@ function handlePendingResize() {
     }
 }
 
+function loadLarge(el) {
+    // The srcset is held back until the photo is almost in view, so only
+    // photos the user is about to see are downloaded
+    const img = el.querySelector(".photo--large[data-srcset]");
+    if (!img) return;
+
+    img.srcset = img.getAttribute("data-srcset");
+    img.removeAttribute("data-srcset");
+}
+
 function updateInView() {
     if (pendingUpdate) return;
     pendingUpdate = true;
@@ -54,8 +64,12 @@ function updateInView() {
             }
 
             const inView = (bottom >= viewTop - paddingTop) && (top <= viewBottom + paddingBottom);
-            if (inView) el.classList.add("in-view");
-            else el.classList.remove("in-view");
+            if (inView) {
+                el.classList.add("in-view");
+                loadLarge(el);
+            } else {
+                el.classList.remove("in-view");
+            }
         }
     });
 }
@@ -69,3 +83,410 @@ window.addEventListener('scroll', updateInView);
 window.addEventListener('resize', handleResize);
 
 updateInView();
+
+function formatExposureTime(seconds) {
+    if (seconds >= 1) return seconds + " s";
+    return "1/" + Math.round(1 / seconds) + " s";
+}
+
+function formatCoordinates(lat, lon) {
+    return lat.toFixed(5) + ", " + lon.toFixed(5);
+}
+
+function metadataEntries(m) {
+    const entries = [];
+
+    if (m.taken_at) {
+        entries.push(["Tatt", m.taken_at.replace("T", " ")]);
+    }
+    const camera = [m.camera_make, m.camera_model].filter(x => x).join(" ");
+    if (camera) entries.push(["Kamera", camera]);
+    if (m.lens) entries.push(["Objektiv", m.lens]);
+    if (m.exposure_time) entries.push(["Lukkertid", formatExposureTime(m.exposure_time)]);
+    if (m.f_number) entries.push(["Blender", "f/" + m.f_number.toFixed(1)]);
+    if (m.iso) entries.push(["ISO", String(m.iso)]);
+    if (m.focal_length) entries.push(["Brennvidde", Math.round(m.focal_length) + " mm"]);
+    if (m.gps_latitude != null && m.gps_longitude != null) {
+        entries.push(["Posisjon", formatCoordinates(m.gps_latitude, m.gps_longitude)]);
+    }
+
+    return entries;
+}
+
+function renderInfo(panel, metadata) {
+    const entries = metadataEntries(metadata);
+    if (!metadata.recorded) {
+        entries.push(["", "Bildet ble lastet opp før informasjon om bildene ble tatt vare på"]);
+    } else if (entries.length == 0) {
+        entries.push(["", "Ingen informasjon"]);
+    }
+
+    panel.textContent = "";
+    for (let [key, value] of entries) {
+        const dt = document.createElement("dt");
+        dt.textContent = key;
+        const dd = document.createElement("dd");
+        dd.textContent = value;
+        panel.appendChild(dt);
+        panel.appendChild(dd);
+    }
+}
+
+document.querySelector(".photo-list--list").addEventListener('click', function (ev) {
+    const button = ev.target.closest(".photo--info-button");
+    if (!button) return;
+
+    ev.preventDefault();
+    ev.stopPropagation();
+
+    const panel = button.parentNode.querySelector(".photo--info");
+    panel.hidden = !panel.hidden;
+
+    if (panel.hidden || panel.hasChildNodes()) return;
+
+    panel.textContent = "…";
+    fetch(button.getAttribute("data-metadata-url"), {
+        credentials: 'same-origin',
+        redirect: 'follow',
+    })
+        .then(function (res) {
+            if (!res.ok) {
+                throw "Unexpected status code: " + res.status + " " + res.statusText;
+            }
+            return res.json();
+        })
+        .then(function (metadata) {
+            renderInfo(panel, metadata);
+        })
+        .catch(function (err) {
+            console.error(err);
+            panel.textContent = "Kunne ikke hente informasjon om bildet";
+        });
+});
+
+const LONG_PRESS_MS = 400;
+let longPressTimer = null;
+
+function loadVideo(video) {
+    // The clip is fetched in full instead of being streamed, since the
+    // server does not support range requests, which some browsers require
+    // for playing video
+    if (!video.hasAttribute("data-src")) return Promise.resolve();
+
+    const url = video.getAttribute("data-src");
+    video.removeAttribute("data-src");
+
+    return fetch(url, {
+        credentials: 'same-origin',
+        redirect: 'follow',
+    })
+        .then(function (res) {
+            if (!res.ok) {
+                throw "Unexpected status code: " + res.status + " " + res.statusText;
+            }
+            return res.blob();
+        })
+        .then(function (blob) {
+            video.src = URL.createObjectURL(blob);
+        })
+        .catch(function (err) {
+            video.setAttribute("data-src", url);
+            throw err;
+        });
+}
+
+function playVideo(video) {
+    video.setAttribute("data-playing", "");
+    loadVideo(video)
+        .then(function () {
+            // The press may have ended while the clip was downloading
+            if (!video.hasAttribute("data-playing")) return;
+
+            video.currentTime = 0;
+            video.hidden = false;
+            return video.play();
+        })
+        .catch(function (err) {
+            console.error(err);
+            stopVideo(video);
+        });
+}
+
+function stopVideo(video) {
+    video.removeAttribute("data-playing");
+    video.pause();
+    video.hidden = true;
+}
+
+function endLongPress() {
+    window.clearTimeout(longPressTimer);
+    longPressTimer = null;
+
+    for (let video of document.querySelectorAll(".photo--video[data-playing]")) {
+        stopVideo(video);
+    }
+}
+
+const photoList = document.querySelector(".photo-list--list");
+
+photoList.addEventListener('pointerdown', function (ev) {
+    if (ev.target.closest(".photo--info-button, .photo--info, .photo--zoom-button")) return;
+
+    const container = ev.target.closest(".photo--img-container__motion");
+    if (!container) return;
+
+    const video = container.querySelector(".photo--video");
+    endLongPress();
+    longPressTimer = window.setTimeout(function () {
+        longPressTimer = null;
+        playVideo(video);
+    }, LONG_PRESS_MS);
+});
+
+// Scrolling cancels the pointer, so a swipe never starts the clip
+for (let type of ['pointerup', 'pointercancel', 'pointerleave']) {
+    photoList.addEventListener(type, endLongPress);
+}
+
+photoList.addEventListener('contextmenu', function (ev) {
+    if (ev.target.closest(".photo--img-container__motion")) ev.preventDefault();
+});
+
+// Photos with a tile pyramid can be zoomed into at full resolution. The
+// pyramid is laid out as a Deep Zoom Image, see deep_zoom.rs. The largest
+// rendition is shown underneath while the tiles load
+const MAX_ZOOM = 2;
+const WHEEL_ZOOM_SPEED = 0.002;
+
+const zoomElement = document.querySelector(".zoom");
+const zoomBase = zoomElement.querySelector(".zoom--base");
+let zoom = null;
+let pendingZoomRender = false;
+
+function parseDzi(text) {
+    const doc = new DOMParser().parseFromString(text, "application/xml");
+    const image = doc.documentElement;
+    const size = doc.getElementsByTagName("Size")[0];
+
+    return {
+        format: image.getAttribute("Format"),
+        overlap: Number(image.getAttribute("Overlap")),
+        tileSize: Number(image.getAttribute("TileSize")),
+        width: Number(size.getAttribute("Width")),
+        height: Number(size.getAttribute("Height")),
+    };
+}
+
+function fitScale() {
+    return Math.min(
+        zoomElement.clientWidth / zoom.dzi.width,
+        zoomElement.clientHeight / zoom.dzi.height
+    );
+}
+
+// Center the photo along each axis where it is smaller than the screen, and
+// otherwise keep it covering the screen
+function clampZoom() {
+    zoom.scale = Math.min(Math.max(zoom.scale, fitScale()), Math.max(MAX_ZOOM, fitScale()));
+
+    const clamp = function (pos, size, screen) {
+        if (size <= screen) return (screen - size) / 2;
+        return Math.min(Math.max(pos, screen - size), 0);
+    };
+    zoom.x = clamp(zoom.x, zoom.dzi.width * zoom.scale, zoomElement.clientWidth);
+    zoom.y = clamp(zoom.y, zoom.dzi.height * zoom.scale, zoomElement.clientHeight);
+}
+
+function renderZoom() {
+    pendingZoomRender = false;
+    if (!zoom) return;
+
+    const { dzi, maxLevel, scale, x, y } = zoom;
+    const screenWidth = zoomElement.clientWidth, screenHeight = zoomElement.clientHeight;
+
+    zoomBase.style.width = dzi.width + "px";
+    zoomBase.style.height = dzi.height + "px";
+    zoomBase.style.transform = `translate(${x}px, ${y}px) scale(${scale})`;
+
+    // The smallest level with at least one pixel per device pixel
+    const wanted = maxLevel + Math.ceil(Math.log2(scale * window.devicePixelRatio));
+    const level = Math.min(Math.max(wanted, 0), maxLevel);
+    const levelScale = Math.pow(2, level - maxLevel);
+    const levelWidth = Math.ceil(dzi.width * levelScale);
+    const levelHeight = Math.ceil(dzi.height * levelScale);
+
+    const toLevel = levelScale / scale;
+    const firstCol = Math.floor(Math.max(-x * toLevel, 0) / dzi.tileSize);
+    const lastCol = Math.floor(Math.min((screenWidth - x) * toLevel, levelWidth - 1) / dzi.tileSize);
+    const firstRow = Math.floor(Math.max(-y * toLevel, 0) / dzi.tileSize);
+    const lastRow = Math.floor(Math.min((screenHeight - y) * toLevel, levelHeight - 1) / dzi.tileSize);
+
+    const visible = new Set();
+    for (let row = firstRow; row <= lastRow; row++) {
+        for (let col = firstCol; col <= lastCol; col++) {
+            const key = level + "/" + col + "_" + row;
+            visible.add(key);
+            if (zoom.tiles.has(key)) continue;
+
+            const tile = document.createElement("img");
+            tile.className = "zoom--tile";
+            tile.alt = "";
+            tile.hidden = true;
+            tile.onload = function () { tile.hidden = false; };
+            tile.src = zoom.tilesUrl + key + "." + dzi.format;
+            tile.setAttribute("data-left", col * dzi.tileSize - (col > 0 ? dzi.overlap : 0));
+            tile.setAttribute("data-top", row * dzi.tileSize - (row > 0 ? dzi.overlap : 0));
+            tile.setAttribute("data-scale", 1 / levelScale);
+            zoomElement.insertBefore(tile, zoomBase.nextSibling);
+            zoom.tiles.set(key, tile);
+        }
+    }
+
+    for (let [key, tile] of zoom.tiles) {
+        if (!visible.has(key)) {
+            tile.remove();
+            zoom.tiles.delete(key);
+            continue;
+        }
+
+        const tileScale = Number(tile.getAttribute("data-scale")) * scale;
+        const left = x + Number(tile.getAttribute("data-left")) * tileScale;
+        const top = y + Number(tile.getAttribute("data-top")) * tileScale;
+        tile.style.transform = `translate(${left}px, ${top}px) scale(${tileScale})`;
+    }
+}
+
+function scheduleZoomRender() {
+    if (pendingZoomRender) return;
+    pendingZoomRender = true;
+    window.requestAnimationFrame(renderZoom);
+}
+
+function zoomAt(screenX, screenY, factor) {
+    const scale = zoom.scale;
+    zoom.scale *= factor;
+    clampZoom();
+
+    const ratio = zoom.scale / scale;
+    zoom.x = screenX - (screenX - zoom.x) * ratio;
+    zoom.y = screenY - (screenY - zoom.y) * ratio;
+    clampZoom();
+    scheduleZoomRender();
+}
+
+function openZoom(url, baseSrc) {
+    fetch(url, {
+        credentials: 'same-origin',
+        redirect: 'follow',
+    })
+        .then(function (res) {
+            if (!res.ok) {
+                throw "Unexpected status code: " + res.status + " " + res.statusText;
+            }
+            return res.text();
+        })
+        .then(function (text) {
+            const dzi = parseDzi(text);
+            zoom = {
+                dzi,
+                maxLevel: Math.ceil(Math.log2(Math.max(dzi.width, dzi.height))),
+                tilesUrl: url.replace(/\.dzi$/, "_files/"),
+                tiles: new Map(),
+                pointers: new Map(),
+                scale: 0,
+                x: 0,
+                y: 0,
+            };
+
+            zoomBase.src = baseSrc;
+            zoomElement.hidden = false;
+            clampZoom();
+            renderZoom();
+        })
+        .catch(function (err) {
+            console.error(err);
+        });
+}
+
+function closeZoom() {
+    zoomElement.hidden = true;
+    zoomBase.removeAttribute("src");
+    for (let tile of zoom.tiles.values()) tile.remove();
+    zoom = null;
+}
+
+photoList.addEventListener('click', function (ev) {
+    const button = ev.target.closest(".photo--zoom-button");
+    if (!button) return;
+
+    ev.preventDefault();
+    ev.stopPropagation();
+
+    const large = button.parentNode.querySelector(".photo--large");
+    openZoom(button.getAttribute("data-zoom-url"), large.currentSrc || large.src);
+});
+
+zoomElement.querySelector(".zoom--close").addEventListener('click', closeZoom);
+
+window.addEventListener('keydown', function (ev) {
+    if (zoom && ev.key == "Escape") closeZoom();
+});
+
+window.addEventListener('resize', function () {
+    if (!zoom) return;
+    clampZoom();
+    scheduleZoomRender();
+});
+
+zoomElement.addEventListener('wheel', function (ev) {
+    ev.preventDefault();
+    zoomAt(ev.clientX, ev.clientY, Math.exp(-ev.deltaY * WHEEL_ZOOM_SPEED));
+}, { passive: false });
+
+zoomElement.addEventListener('dblclick', function (ev) {
+    zoomAt(ev.clientX, ev.clientY, 2);
+});
+
+// One pointer pans, and two pointers pinch to zoom around their midpoint
+function pinch(pointers) {
+    const [a, b] = pointers.values();
+    return {
+        x: (a.x + b.x) / 2,
+        y: (a.y + b.y) / 2,
+        distance: Math.hypot(a.x - b.x, a.y - b.y),
+    };
+}
+
+zoomElement.addEventListener('pointerdown', function (ev) {
+    if (!zoom || ev.target.closest(".zoom--close")) return;
+
+    zoomElement.setPointerCapture(ev.pointerId);
+    zoom.pointers.set(ev.pointerId, { x: ev.clientX, y: ev.clientY });
+});
+
+zoomElement.addEventListener('pointermove', function (ev) {
+    if (!zoom || !zoom.pointers.has(ev.pointerId)) return;
+
+    const before = zoom.pointers.size == 2 ? pinch(zoom.pointers) : null;
+    const last = zoom.pointers.get(ev.pointerId);
+    const dx = ev.clientX - last.x, dy = ev.clientY - last.y;
+    zoom.pointers.set(ev.pointerId, { x: ev.clientX, y: ev.clientY });
+
+    if (before) {
+        const after = pinch(zoom.pointers);
+        zoom.x += after.x - before.x;
+        zoom.y += after.y - before.y;
+        zoomAt(after.x, after.y, before.distance > 0 ? after.distance / before.distance : 1);
+    } else if (zoom.pointers.size == 1) {
+        zoom.x += dx;
+        zoom.y += dy;
+        clampZoom();
+        scheduleZoomRender();
+    }
+});
+
+for (let type of ['pointerup', 'pointercancel']) {
+    zoomElement.addEventListener(type, function (ev) {
+        if (zoom) zoom.pointers.delete(ev.pointerId);
+    });
+}
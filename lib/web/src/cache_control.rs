@@ -34,7 +34,11 @@ pub struct Revalidation {
 #[derive(Default)]
 pub struct CacheControl {
     pub cacheability: Cacheability,
+
     // expiration, see https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cache-Control#Expiration
+    /// In seconds. Implied by `Revalidation::immutable`
+    pub max_age: Option<u32>,
+
     pub revalidation: Revalidation,
     // other, see https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cache-Control#Other
 }
@@ -53,6 +57,10 @@ impl std::fmt::Display for CacheControl {
             CacheabilityPolicy::NoStore => write!(fmt, ", no-store")?,
         };
 
+        if let Some(max_age) = self.max_age {
+            write!(fmt, ", max-age={}", max_age)?;
+        }
+
         if self.revalidation.must_revalidate {
             write!(fmt, ", must-revalidate")?;
         }
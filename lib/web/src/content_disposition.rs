@@ -0,0 +1,59 @@
+use std::fmt;
+
+// Offers the response as a file to save rather than to show, see
+// https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Disposition
+
+pub struct ContentDisposition {
+    pub filename: String,
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // The plain filename is a fallback for clients that do not support
+        // the RFC 5987 encoding in filename*
+        let fallback: String = self
+            .filename
+            .chars()
+            .map(|c| match c {
+                ' ' => ' ',
+                '"' | '\\' | '%' => '_',
+                c if c.is_ascii_graphic() => c,
+                _ => '_',
+            })
+            .collect();
+
+        write!(
+            fmt,
+            "attachment; filename=\"{}\"; filename*=UTF-8''",
+            fallback
+        )?;
+
+        for b in self.filename.bytes() {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                write!(fmt, "{}", b as char)?;
+            } else {
+                write!(fmt, "%{:02X}", b)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filename_is_escaped() {
+        let disposition = ContentDisposition {
+            filename: "Blåbær \"tur\".jpg".to_string(),
+        };
+
+        assert_eq!(
+            disposition.to_string(),
+            "attachment; filename=\"Bl_b_r _tur_.jpg\"; \
+             filename*=UTF-8''Bl%C3%A5b%C3%A6r%20%22tur%22.jpg"
+        );
+    }
+}
@@ -7,6 +7,27 @@ pub enum ETag {
     Strong(String),
 }
 
+impl ETag {
+    fn tag(&self) -> &str {
+        match self {
+            ETag::Weak(tag) | ETag::Strong(tag) => tag,
+        }
+    }
+
+    /// Whether the ETag is listed in the value of an If-None-Match header.
+    /// This is the weak comparison, so `W/"1"` matches `"1"`
+    pub fn matches(&self, if_none_match: &str) -> bool {
+        if_none_match.split(',').map(str::trim).any(|candidate| {
+            let candidate = candidate.trim_start_matches("W/");
+            candidate == "*"
+                || (candidate.len() >= 2
+                    && candidate.starts_with('"')
+                    && candidate.ends_with('"')
+                    && &candidate[1..candidate.len() - 1] == self.tag())
+        })
+    }
+}
+
 impl fmt::Display for ETag {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         // TODO Escape. Better typing for validating ETags? (IntoETag?)
@@ -17,3 +38,19 @@ impl fmt::Display for ETag {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn weak_comparison() {
+        let etag = ETag::Weak("3".to_string());
+        assert!(etag.matches("W/\"3\""));
+        assert!(etag.matches("\"3\""));
+        assert!(etag.matches("W/\"2\", W/\"3\""));
+        assert!(etag.matches("*"));
+        assert!(!etag.matches("W/\"2\""));
+        assert!(!etag.matches("3"));
+    }
+}
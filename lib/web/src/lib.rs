@@ -3,14 +3,17 @@ use hyper::http;
 use hyper::{Body, Request};
 
 mod cache_control;
+mod content_disposition;
 mod cookie_handler;
 mod etag;
 mod media_type;
+mod negotiation;
 mod query_handler;
 mod representation;
 mod resource;
 
 pub use self::cache_control::*;
+pub use self::content_disposition::ContentDisposition;
 pub use self::cookie_handler::CookieHandler;
 pub use self::etag::ETag;
 pub use self::media_type::MediaType;
@@ -135,9 +138,7 @@ async fn try_handle_request<'a>(
         */
     }
 
-    if let Some(_if_none_match) = req.headers.get_ascii(http::header::IF_NONE_MATCH)? {
-        unimplemented!();
-
+    if let Some(if_none_match) = req.headers.get_ascii(http::header::IF_NONE_MATCH)? {
         /*
         https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-None-Match
 
@@ -150,9 +151,15 @@ async fn try_handle_request<'a>(
         fields that would have been sent in a 200 (OK) response to the same request: Cache-Control,
         Content-Location, Date, ETag, Expires, and Vary.
         */
-    }
 
-    let _accept = req.headers.get_ascii(http::header::ACCEPT)?;
+        // TODO 412 Precondition Failed for the other methods
+        let matches = etag.as_ref().map_or(false, |x| x.matches(if_none_match));
+        if matches && req.method == hyper::Method::GET {
+            let cache_control = resource.get.as_ref().map(|get| get.cache_control());
+            let response = resource::Response::new(Status::NotModified, vec![]);
+            return Ok((etag, response, cache_control));
+        }
+    }
 
     match req.method {
         // TODO: Implement HEAD and OPTIONS in library
@@ -173,6 +180,10 @@ async fn try_handle_request<'a>(
                 return Ok((etag, bad_request(), None));
             }
         }
+        hyper::Method::PUT => {
+            let response = resource.put(req.headers, body).await;
+            return Ok((etag, response, None));
+        }
         _ => return Ok((etag, resource.method_not_allowed(), None)),
     };
 }
@@ -180,6 +191,7 @@ async fn try_handle_request<'a>(
 use hyper::http::StatusCode;
 
 async fn build_response(
+    accept: Option<&str>,
     etag: Option<ETag>,
     response: resource::Response,
     cache_control: Option<CacheControl>,
@@ -188,6 +200,7 @@ async fn build_response(
         status,
         mut representations,
         cookies,
+        content_disposition,
     } = response;
 
     let mut response = hyper::Response::builder();
@@ -201,6 +214,10 @@ async fn build_response(
             response.status(StatusCode::CREATED);
             response.header("location", location);
         }
+        Status::Accepted(location) => {
+            response.status(StatusCode::ACCEPTED);
+            response.header("location", location);
+        }
 
         // 3__
         Status::MovedPermanently(location) => {
@@ -213,6 +230,10 @@ async fn build_response(
             response.header("location", location);
         }
 
+        Status::NotModified => {
+            response.status(StatusCode::NOT_MODIFIED);
+        }
+
         // 4__
         Status::BadRequest => {
             response.status(StatusCode::BAD_REQUEST);
@@ -232,6 +253,14 @@ async fn build_response(
             response.status(StatusCode::NOT_FOUND);
         }
 
+        Status::Conflict => {
+            response.status(StatusCode::CONFLICT);
+        }
+
+        Status::PayloadTooLarge => {
+            response.status(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
         // 5__
         Status::InternalServerError => {
             response.status(StatusCode::INTERNAL_SERVER_ERROR);
@@ -242,11 +271,16 @@ async fn build_response(
         response.header("vary", "accept");
     }
 
-    // Implement content type negotiation via Accept
-    let (content_type, rep_builder) = representations.pop().unwrap(); // FIXME: Stub
-    let representation = rep_builder();
+    // Only a 304 Not Modified has no representation, and so no body
+    let body = if representations.is_empty() {
+        Body::empty()
+    } else {
+        let selected = negotiation::negotiate(accept, representations.iter().map(|(x, _)| x));
+        let (content_type, rep_builder) = representations.swap_remove(selected);
+        response.header("content-type", content_type.to_string());
 
-    response.header("content-type", content_type.to_string());
+        rep_builder().body()
+    };
 
     if let Some(etag) = etag {
         response.header("etag", etag.to_string());
@@ -256,6 +290,10 @@ async fn build_response(
         response.header("cache-control", cache_control.to_string());
     }
 
+    if let Some(content_disposition) = content_disposition {
+        response.header("content-disposition", content_disposition.to_string());
+    }
+
     if cookies.len() > 0 {
         response.header(
             "set-cookie",
@@ -268,7 +306,7 @@ async fn build_response(
     }
 
     response
-        .body(representation.body())
+        .body(body)
         .expect("Success should be guaranteed at type level")
 }
 
@@ -276,6 +314,13 @@ async fn handle_request_core<'a>(
     site: &'a (dyn Lookup + 'a + Send + Sync),
     req: Request<Body>,
 ) -> hyper::Response<Body> {
+    // Non-ASCII Accept headers are ignored, as if no preference was given
+    let accept = req
+        .headers()
+        .get_ascii(http::header::ACCEPT)
+        .unwrap_or(None)
+        .map(|x| x.to_string());
+
     let (etag, response, cache_control) =
         try_handle_request(site, req)
             .await
@@ -285,7 +330,7 @@ async fn handle_request_core<'a>(
                 Error::BlanketResponse(r) => (None, r, None),
             });
 
-    build_response(etag, response, cache_control).await
+    build_response(accept.as_deref(), etag, response, cache_control).await
 }
 
 // This exists merely to allow use of .compat() layer for futures 0.1 support
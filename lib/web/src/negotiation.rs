@@ -0,0 +1,146 @@
+use super::media_type::MediaType;
+
+// Content negotiation via the Accept request header, see
+// https://developer.mozilla.org/en-US/docs/Web/HTTP/Content_negotiation
+
+struct MediaRange<'a> {
+    type_category: &'a str,
+    subtype: &'a str,
+    q: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(src: &'a str) -> Option<MediaRange<'a>> {
+        let mut params = src.split(';');
+
+        let mut parts = params.next()?.trim().splitn(2, '/');
+        let type_category = parts.next()?.trim();
+        let subtype = parts.next()?.trim();
+
+        let q = params
+            .filter_map(|param| {
+                let mut key_value = param.splitn(2, '=');
+                match (key_value.next()?.trim(), key_value.next()) {
+                    ("q", Some(value)) => value.trim().parse().ok(),
+                    _ => None,
+                }
+            })
+            .next()
+            .unwrap_or(1.);
+
+        Some(MediaRange {
+            type_category,
+            subtype,
+            q,
+        })
+    }
+
+    // Higher is more specific. None when the range does not match
+    fn specificity(&self, media_type: &MediaType) -> Option<u8> {
+        let type_matches = self
+            .type_category
+            .eq_ignore_ascii_case(&media_type.type_category);
+        let subtype_matches = self.subtype.eq_ignore_ascii_case(&media_type.subtype);
+
+        match (self.type_category, self.subtype) {
+            ("*", "*") => Some(0),
+            (_, "*") if type_matches => Some(1),
+            _ if type_matches && subtype_matches => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// Select the index of the media type that best matches the given Accept
+/// header
+///
+/// Media types are weighted by the q-value of the most specific matching
+/// media range. Ties are broken by specificity, so `image/webp` beats
+/// `image/*`, and finally by the given order. List the most widely supported
+/// media type first to serve it to clients that only accept wildcards.
+///
+/// A media type with a q-value of 0 is not acceptable, and is only selected
+/// when nothing is, as the first one.
+pub fn negotiate<'a>(
+    accept: Option<&str>,
+    media_types: impl IntoIterator<Item = &'a MediaType>,
+) -> usize {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return 0,
+    };
+
+    let ranges: Vec<_> = accept.split(',').filter_map(MediaRange::parse).collect();
+
+    let mut best = 0;
+    let mut best_score = (0., 0);
+
+    for (index, media_type) in media_types.into_iter().enumerate() {
+        let score = ranges
+            .iter()
+            .filter_map(|range| range.specificity(media_type).map(|s| (s, range.q)))
+            .max_by_key(|&(specificity, _)| specificity)
+            .map(|(specificity, q)| (q, specificity))
+            .unwrap_or((0., 0));
+
+        // A q-value of 0 means "not acceptable", even when a less specific
+        // range would accept the media type
+        if score.0 > 0. && score > best_score {
+            best = index;
+            best_score = score;
+        }
+    }
+
+    // TODO Respond with 406 Not Acceptable when nothing is acceptable
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn jpeg_and_webp() -> Vec<MediaType> {
+        vec![
+            MediaType::new("image", "jpeg", vec![]),
+            MediaType::new("image", "webp", vec![]),
+        ]
+    }
+
+    #[test]
+    fn missing_accept_selects_first() {
+        assert_eq!(negotiate(None, &jpeg_and_webp()), 0);
+    }
+
+    #[test]
+    fn explicit_match_beats_wildcard() {
+        let accept = "image/avif,image/webp,image/apng,image/*,*/*;q=0.8";
+        assert_eq!(negotiate(Some(accept), &jpeg_and_webp()), 1);
+    }
+
+    #[test]
+    fn wildcard_only_selects_first() {
+        let accept = "image/png,image/svg+xml,image/*;q=0.8,*/*;q=0.5";
+        assert_eq!(negotiate(Some(accept), &jpeg_and_webp()), 0);
+    }
+
+    #[test]
+    fn q_value_is_respected() {
+        let accept = "image/webp;q=0.5, image/jpeg";
+        assert_eq!(negotiate(Some(accept), &jpeg_and_webp()), 0);
+
+        let accept = "image/webp, image/jpeg;q=0.5";
+        assert_eq!(negotiate(Some(accept), &jpeg_and_webp()), 1);
+    }
+
+    #[test]
+    fn q_zero_is_not_acceptable() {
+        let accept = "image/webp;q=0";
+        assert_eq!(negotiate(Some(accept), &jpeg_and_webp()), 0);
+
+        let accept = "image/jpeg;q=0, */*;q=0.1";
+        assert_eq!(negotiate(Some(accept), &jpeg_and_webp()), 1);
+
+        let accept = "image/jpeg, image/webp;q=0, */*";
+        assert_eq!(negotiate(Some(accept), &jpeg_and_webp()), 0);
+    }
+}
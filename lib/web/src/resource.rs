@@ -4,6 +4,7 @@ use std::pin::Pin;
 use async_trait::async_trait;
 use cookie::Cookie;
 
+use super::content_disposition::ContentDisposition;
 use super::etag::ETag;
 use super::media_type::MediaType;
 use super::representation::Representation;
@@ -18,16 +19,20 @@ pub enum Status {
     // 2__
     Ok,
     Created(String),
+    Accepted(String),
 
     // 3__
     MovedPermanently(String),
     SeeOther(String),
+    NotModified,
 
     // 4__
     BadRequest,
     Unauthorized, // TODO: `WWW-Authenticate` header
     NotFound,
     MethodNotAllowed { allow: String },
+    Conflict,
+    PayloadTooLarge,
 
     // 5__
     InternalServerError,
@@ -37,6 +42,7 @@ pub struct Response {
     pub status: Status,
     pub representations: RepresentationsVec,
     pub cookies: Vec<Cookie<'static>>,
+    pub content_disposition: Option<ContentDisposition>,
 }
 
 impl Response {
@@ -45,6 +51,7 @@ impl Response {
             status,
             representations,
             cookies: vec![],
+            content_disposition: None,
         }
     }
 }
@@ -63,10 +70,16 @@ pub trait Post {
     async fn post(self: Box<Self>, content_type: String, body: hyper::Body) -> Response;
 }
 
+#[async_trait]
+pub trait Put {
+    async fn put(self: Box<Self>, headers: hyper::HeaderMap, body: hyper::Body) -> Response;
+}
+
 pub struct Resource {
     pub etag: Option<ETag>,
     pub get: Option<Box<dyn Get + Send>>,
     pub post: Option<Box<dyn Post + Send>>,
+    pub put: Option<Box<dyn Put + Send>>,
 }
 
 impl Resource {
@@ -78,6 +91,9 @@ impl Resource {
         if self.post.is_some() {
             allow.push_str(", POST");
         }
+        if self.put.is_some() {
+            allow.push_str(", PUT");
+        }
 
         Response::new(
             Status::MethodNotAllowed { allow },
@@ -104,4 +120,11 @@ impl Resource {
             None => self.method_not_allowed(),
         }
     }
+
+    pub async fn put(self, headers: hyper::HeaderMap, body: hyper::Body) -> Response {
+        match self.put {
+            Some(put) => put.put(headers, body).await,
+            None => self.method_not_allowed(),
+        }
+    }
 }
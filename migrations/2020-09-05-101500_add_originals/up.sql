@@ -0,0 +1,16 @@
+-- The uploaded file, unmodified. Kept so all renditions can be regenerated
+-- when the processing pipeline changes
+CREATE TABLE originals (
+    id INTEGER PRIMARY KEY NOT NULL,
+
+    pixurs_id INTEGER NOT NULL UNIQUE,
+
+    media_type TEXT NOT NULL,
+    data BLOB NOT NULL,
+
+    -- Set when the pixur was uploaded before originals were kept, and its
+    -- largest rendition was taken as the original, see image::largest_image
+    adopted BOOLEAN NOT NULL DEFAULT 0,
+
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
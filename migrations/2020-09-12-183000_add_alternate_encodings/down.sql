@@ -0,0 +1,2 @@
+DROP TABLE thumb_encodings;
+DROP TABLE image_encodings;
@@ -0,0 +1,22 @@
+-- Alternative encodings of the same rendition as the corresponding row in
+-- images or thumbs, in formats that are more efficient than JPEG but not
+-- universally supported. Selected by content negotiation
+CREATE TABLE image_encodings (
+    images_id INTEGER NOT NULL,
+    media_type TEXT NOT NULL,
+
+    data BLOB NOT NULL,
+
+    PRIMARY KEY (images_id, media_type),
+    FOREIGN KEY (images_id) REFERENCES images(id)
+);
+
+CREATE TABLE thumb_encodings (
+    thumbs_id INTEGER NOT NULL,
+    media_type TEXT NOT NULL,
+
+    data BLOB NOT NULL,
+
+    PRIMARY KEY (thumbs_id, media_type),
+    FOREIGN KEY (thumbs_id) REFERENCES thumbs(id)
+);
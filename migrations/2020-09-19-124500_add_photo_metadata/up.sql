@@ -0,0 +1,28 @@
+-- Capture details from the EXIF data of the original. Every pixur that has
+-- been examined gets a row, even when there was nothing to extract
+CREATE TABLE photo_metadata (
+    pixurs_id INTEGER PRIMARY KEY NOT NULL,
+
+    -- Local time at the camera, the time zone is generally unknown
+    taken_at TIMESTAMP,
+
+    camera_make TEXT,
+    camera_model TEXT,
+    lens TEXT,
+
+    -- Seconds
+    exposure_time REAL,
+    f_number REAL,
+    iso INTEGER,
+    -- Millimeters
+    focal_length REAL,
+
+    -- Degrees, positive towards north and east
+    gps_latitude DOUBLE,
+    gps_longitude DOUBLE,
+
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
+
+-- Existing pixurs are filled in from their originals by the application on
+-- startup, see photo_metadata::backfill
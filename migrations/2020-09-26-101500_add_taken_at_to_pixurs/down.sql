@@ -0,0 +1,82 @@
+-- Create the new before renaming the old, otherwise the foreign keys pointing
+-- into this table would follow along to the renamed table
+
+CREATE TABLE pixurs_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+
+    average_color INTEGER NOT NULL,
+    thumbs_id INTEGER NOT NULL,
+
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    image_aspect_ratio REAL NOT NULL,
+
+    crop_left REAL NOT NULL,
+    crop_right REAL NOT NULL,
+    crop_top REAL NOT NULL,
+    crop_bottom REAL NOT NULL,
+
+    FOREIGN KEY (thumbs_id) REFERENCES thumbs(id),
+
+    CHECK (image_aspect_ratio > 0),
+
+    CHECK (0 <= crop_left),
+    CHECK (crop_left <= crop_right),
+    CHECK (crop_right <= 1),
+
+    CHECK (0 <= crop_top),
+    CHECK (crop_top <= crop_bottom),
+    CHECK (crop_bottom <= 1)
+);
+
+INSERT INTO pixurs_new SELECT
+    id,
+    average_color,
+    thumbs_id,
+    created,
+    image_aspect_ratio,
+    crop_left,
+    crop_right,
+    crop_top,
+    crop_bottom
+FROM pixurs;
+
+DROP TABLE pixurs;
+ALTER TABLE pixurs_new RENAME TO pixurs;
+
+CREATE TABLE photo_metadata_new (
+    pixurs_id INTEGER PRIMARY KEY NOT NULL,
+
+    taken_at TIMESTAMP,
+
+    camera_make TEXT,
+    camera_model TEXT,
+    lens TEXT,
+
+    exposure_time REAL,
+    f_number REAL,
+    iso INTEGER,
+    focal_length REAL,
+
+    gps_latitude DOUBLE,
+    gps_longitude DOUBLE,
+
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
+
+INSERT INTO photo_metadata_new SELECT
+    pixurs_id,
+    taken_at,
+    camera_make,
+    camera_model,
+    lens,
+    exposure_time,
+    f_number,
+    iso,
+    focal_length,
+    gps_latitude,
+    gps_longitude
+FROM photo_metadata;
+
+DROP TABLE photo_metadata;
+ALTER TABLE photo_metadata_new RENAME TO photo_metadata;
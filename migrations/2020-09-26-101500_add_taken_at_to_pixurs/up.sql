@@ -0,0 +1,11 @@
+-- Time of capture, for sorting. In UTC when the time zone offset is known
+-- from EXIF, otherwise in the local time at the camera
+ALTER TABLE pixurs ADD COLUMN taken_at TIMESTAMP;
+
+-- Minutes east of UTC, from the EXIF OffsetTimeOriginal tag
+ALTER TABLE photo_metadata ADD COLUMN taken_at_offset INTEGER;
+
+-- The offset is not known for the pixurs that have already been examined
+UPDATE pixurs SET taken_at = (
+    SELECT taken_at FROM photo_metadata WHERE photo_metadata.pixurs_id = pixurs.id
+);
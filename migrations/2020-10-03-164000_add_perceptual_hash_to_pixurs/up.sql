@@ -0,0 +1,3 @@
+-- dHash of the photo, for finding duplicates. NULL for pixurs uploaded
+-- before this was introduced, until they are rerendered
+ALTER TABLE pixurs ADD COLUMN perceptual_hash BIGINT;
@@ -0,0 +1,3 @@
+-- Compact placeholder shown while the photo loads, see blurhash.rs. Filled
+-- in from the thumbnails by the application on startup for existing pixurs
+ALTER TABLE pixurs ADD COLUMN blurhash TEXT;
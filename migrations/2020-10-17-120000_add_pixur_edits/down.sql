@@ -0,0 +1 @@
+DROP TABLE pixur_edits;
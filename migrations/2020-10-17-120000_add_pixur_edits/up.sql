@@ -0,0 +1,9 @@
+-- Adjustments applied on top of the original when rendering, as a JSON list
+-- in the order they are applied, see image_edit::Edit. Pixurs without edits
+-- have no row
+CREATE TABLE pixur_edits (
+    pixurs_id INTEGER PRIMARY KEY NOT NULL,
+    edits TEXT NOT NULL,
+
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
@@ -0,0 +1,22 @@
+-- Uploads that are processed in the background, see ingest_job. The upload
+-- itself is dropped once it has been ingested
+CREATE TABLE ingest_jobs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    media_type TEXT NOT NULL,
+    data BLOB,
+
+    -- See ingest_job::Stage
+    stage TEXT NOT NULL,
+
+    -- Set when done. Duplicates is a JSON list of pixurs IDs
+    pixurs_id INTEGER,
+    pixur_series_id INTEGER,
+    duplicates TEXT,
+
+    -- Set when failed
+    error TEXT,
+
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
@@ -0,0 +1,10 @@
+-- The video clip of a motion photo, which is an MP4 appended to the JPEG and
+-- described in its XMP metadata, see motion_photo.rs. Pixurs that are not
+-- motion photos have no row
+CREATE TABLE motion_videos (
+    pixurs_id INTEGER PRIMARY KEY NOT NULL,
+    media_type TEXT NOT NULL,
+    data BLOB NOT NULL,
+
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
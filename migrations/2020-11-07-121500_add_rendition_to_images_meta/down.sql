@@ -0,0 +1,21 @@
+CREATE TABLE images_meta_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+
+    pixurs_id INTEGER NOT NULL,
+
+    FOREIGN KEY (id) REFERENCES images(id),
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
+
+INSERT INTO images_meta_new SELECT
+    id,
+    width,
+    height,
+    pixurs_id
+FROM images_meta;
+
+DROP TABLE images_meta;
+ALTER TABLE images_meta_new RENAME TO images_meta;
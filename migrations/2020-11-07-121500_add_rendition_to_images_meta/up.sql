@@ -0,0 +1,5 @@
+-- The rendition in the policy in config.toml that an image was rendered for,
+-- see rendition_policy.rs. Images rendered before the policy was introduced
+-- have no name, and are all display renditions
+ALTER TABLE images_meta ADD COLUMN rendition TEXT;
+ALTER TABLE images_meta ADD COLUMN role TEXT NOT NULL DEFAULT 'display';
@@ -0,0 +1,7 @@
+-- Dominant colors, see palette.rs. The palette is a JSON list of CSS colors,
+-- the most common first. The edge colors are like average_color, for the
+-- top and bottom edges of the photo. Filled in from the thumbnails by the
+-- application on startup for existing pixurs
+ALTER TABLE pixurs ADD COLUMN palette TEXT;
+ALTER TABLE pixurs ADD COLUMN top_color INTEGER;
+ALTER TABLE pixurs ADD COLUMN bottom_color INTEGER;
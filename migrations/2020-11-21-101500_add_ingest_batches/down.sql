@@ -0,0 +1,34 @@
+CREATE TABLE ingest_jobs_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    media_type TEXT NOT NULL,
+    data BLOB,
+
+    stage TEXT NOT NULL,
+
+    pixurs_id INTEGER,
+    pixur_series_id INTEGER,
+    duplicates TEXT,
+
+    error TEXT,
+
+    FOREIGN KEY (pixurs_id) REFERENCES pixurs(id)
+);
+
+INSERT INTO ingest_jobs_new SELECT
+    id,
+    created,
+    media_type,
+    data,
+    stage,
+    pixurs_id,
+    pixur_series_id,
+    duplicates,
+    error
+FROM ingest_jobs;
+
+DROP TABLE ingest_jobs;
+ALTER TABLE ingest_jobs_new RENAME TO ingest_jobs;
+
+DROP TABLE ingest_batches;
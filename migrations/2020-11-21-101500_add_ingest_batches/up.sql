@@ -0,0 +1,20 @@
+-- Multi-file uploads, see ingest_job. Each file is processed as a separate
+-- job, and the series is created when the last of them is finished
+CREATE TABLE ingest_batches (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    pixur_series_id INTEGER NOT NULL,
+
+    -- See ingest_job::SeriesOrder
+    series_order TEXT NOT NULL,
+
+    -- Set when the series of the batch is created, or found to be empty
+    -- because none of the files could be ingested, see
+    -- ingest_job::finish_batch
+    finished TIMESTAMP
+);
+
+-- The position is the order of the file in the upload
+ALTER TABLE ingest_jobs ADD COLUMN batch_id INTEGER REFERENCES ingest_batches(id);
+ALTER TABLE ingest_jobs ADD COLUMN batch_position INTEGER;
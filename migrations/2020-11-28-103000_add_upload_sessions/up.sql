@@ -0,0 +1,30 @@
+-- Resumable uploads, see upload_session. The file is received in chunks,
+-- which are handed over to ingest_jobs when the upload is complete
+CREATE TABLE upload_sessions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    -- The uploader who started the session, and who alone may continue it
+    sub TEXT NOT NULL,
+
+    media_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    received BIGINT NOT NULL DEFAULT 0,
+
+    -- Set when the upload is complete
+    ingest_jobs_id INTEGER,
+
+    FOREIGN KEY (ingest_jobs_id) REFERENCES ingest_jobs(id)
+);
+
+-- The chunks of an upload are contiguous, so each starts where the
+-- previous one ended
+CREATE TABLE upload_chunks (
+    upload_sessions_id INTEGER NOT NULL,
+    start BIGINT NOT NULL,
+    data BLOB NOT NULL,
+
+    PRIMARY KEY (upload_sessions_id, start),
+    FOREIGN KEY (upload_sessions_id) REFERENCES upload_sessions(id)
+);
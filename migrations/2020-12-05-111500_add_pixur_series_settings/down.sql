@@ -0,0 +1 @@
+DROP TABLE pixur_series_settings;
@@ -0,0 +1,9 @@
+-- Settings of a series, see pixur_series_meta. Series without a row here
+-- have the default settings
+CREATE TABLE pixur_series_settings (
+    pixur_series_id INTEGER PRIMARY KEY NOT NULL,
+
+    -- Recipients may download the original upload, see site/download.rs.
+    -- Otherwise, they get the largest rendition
+    allow_original_download BOOLEAN NOT NULL DEFAULT 0
+);
@@ -0,0 +1,2 @@
+DROP TABLE deep_zoom_tiles;
+DROP TABLE deep_zoom_images;
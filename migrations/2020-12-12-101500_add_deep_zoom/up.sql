@@ -0,0 +1,25 @@
+-- Tile pyramids of large photos, see deep_zoom.rs. Only photos that fit the
+-- deep zoom policy have a row here
+CREATE TABLE deep_zoom_images (
+    pixurs_id INTEGER PRIMARY KEY NOT NULL REFERENCES pixurs(id),
+
+    -- The size of the photo, which is the size of the last level
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+
+    tile_size INTEGER NOT NULL,
+    overlap INTEGER NOT NULL,
+
+    -- All the tiles of a pyramid are in the same format
+    media_type TEXT NOT NULL
+);
+
+CREATE TABLE deep_zoom_tiles (
+    pixurs_id INTEGER NOT NULL REFERENCES deep_zoom_images(pixurs_id),
+    level INTEGER NOT NULL,
+    col INTEGER NOT NULL,
+    row INTEGER NOT NULL,
+    data BLOB NOT NULL,
+
+    PRIMARY KEY (pixurs_id, level, col, row)
+);
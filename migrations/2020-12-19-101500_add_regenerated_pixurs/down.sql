@@ -0,0 +1 @@
+DROP TABLE regenerated_pixurs;
@@ -0,0 +1,6 @@
+-- The progress of the regenerate command, see regenerate.rs. Lists the
+-- pixurs that are done in the current run, so an interrupted run can resume
+-- where it left off. Emptied when a run completes
+CREATE TABLE regenerated_pixurs (
+    pixurs_id INTEGER PRIMARY KEY NOT NULL REFERENCES pixurs(id)
+);
@@ -0,0 +1,31 @@
+CREATE TABLE images_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+
+    media_type TEXT NOT NULL,
+    data BLOB NOT NULL
+);
+
+INSERT INTO images_new SELECT
+    id,
+    media_type,
+    data
+FROM images;
+
+DROP TABLE images;
+ALTER TABLE images_new RENAME TO images;
+
+CREATE TABLE thumbs_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+
+    media_type TEXT NOT NULL,
+    data BLOB NOT NULL
+);
+
+INSERT INTO thumbs_new SELECT
+    id,
+    media_type,
+    data
+FROM thumbs;
+
+DROP TABLE thumbs;
+ALTER TABLE thumbs_new RENAME TO thumbs;
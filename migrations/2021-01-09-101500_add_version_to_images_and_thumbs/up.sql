@@ -0,0 +1,4 @@
+-- Incremented whenever the rendition is replaced in place, as when the pixur
+-- is rerendered. Served as the ETag, so clients revalidate their cached copy
+ALTER TABLE images ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE thumbs ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
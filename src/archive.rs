@@ -0,0 +1,356 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use zip::result::ZipError;
+
+use crate::image;
+
+/// The media type and data of a supported image, or the reason a file is
+/// skipped
+pub type Content = Result<(&'static str, Vec<u8>), String>;
+
+/// A file in an archive, see `read_zip`
+pub struct File {
+    pub name: String,
+    pub content: Content,
+}
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+/// Accept the given file if it is an image that can be ingested, judging by
+/// its contents and the limits
+pub fn supported_image(data: Vec<u8>, limits: &image::Limits) -> Content {
+    let format = ::image::guess_format(&data).map_err(|_| "Not an image".to_string())?;
+    let media_type = image::media_type(format).ok_or("Unsupported image format")?;
+
+    if data.len() > limits.max_upload_bytes {
+        return Err("The file exceeds the size limit".to_string());
+    }
+    image::check_limits(&data, format, limits).map_err(|e| e.to_string())?;
+
+    Ok((media_type, data))
+}
+
+/// Unpack the files of a ZIP archive, in archive order, leaving out
+/// directories. Files that are not images, are refused by the limits, or
+/// are encrypted or compressed with an unsupported method, are skipped
+/// rather than failing the whole archive
+///
+/// The decompressed images together are limited by
+/// `max_series_upload_bytes`, so the archive cannot take up more memory than
+/// a multi-file upload.
+pub fn read_zip(data: &[u8], limits: &image::Limits) -> zip::result::ZipResult<Vec<File>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    let mut remaining = limits.max_series_upload_bytes;
+    let mut files = vec![];
+
+    for i in 0..archive.len() {
+        // The raw entry can be had even when it cannot be decompressed, so
+        // such files are skipped by name
+        let (name, is_dir) = {
+            let file = archive.by_index_raw(i)?;
+            (file.name().to_string(), file.is_dir())
+        };
+        if is_dir {
+            continue;
+        }
+
+        let file = match archive.by_index(i) {
+            Ok(file) => file,
+            Err(err) => {
+                let reason = match err {
+                    ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => {
+                        "The file is encrypted"
+                    }
+                    ZipError::UnsupportedArchive(_) => "The file uses an unsupported compression",
+                    _ => "Unable to read the file",
+                };
+                files.push(File {
+                    name,
+                    content: Err(reason.to_string()),
+                });
+                continue;
+            }
+        };
+
+        // The declared size is not to be trusted, so reading is cut off
+        // just past the limit
+        let limit = limits.max_upload_bytes.min(remaining);
+        let mut data = vec![];
+        let content = match file.take(limit as u64 + 1).read_to_end(&mut data) {
+            Err(_) => Err("Unable to decompress the file".to_string()),
+            Ok(_) if data.len() > limit => Err(if limit < limits.max_upload_bytes {
+                "The archive exceeds the size limit".to_string()
+            } else {
+                "The file exceeds the size limit".to_string()
+            }),
+            Ok(_) => supported_image(data, limits),
+        };
+
+        if let Ok((_, data)) = &content {
+            remaining -= data.len();
+        }
+
+        files.push(File { name, content });
+    }
+
+    Ok(files)
+}
+
+/// Writes a ZIP archive front to back, so it can be sent while it is being
+/// made. `zip::ZipWriter` instead seeks back to fill in the sizes
+///
+/// The files are stored as they are, since photos do not compress any
+/// further. Archives beyond the limits of the original ZIP format, 4 GiB or
+/// 65535 files, are refused rather than written as ZIP64.
+pub struct StreamingZip<W: Write> {
+    out: W,
+    offset: u32,
+    entries: u16,
+    central_directory: Vec<u8>,
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "The archive is too large")
+}
+
+/// The MS-DOS date and time used in ZIP headers. It cannot represent times
+/// before 1980
+fn dos_date_time(time: chrono::NaiveDateTime) -> (u16, u16) {
+    use chrono::{Datelike, Timelike};
+
+    if time.year() < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+
+    let date =
+        (((time.year() - 1980) as u16) << 9) | ((time.month() as u16) << 5) | (time.day() as u16);
+    let time =
+        ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | ((time.second() as u16) / 2);
+
+    (date, time)
+}
+
+impl<W: Write> StreamingZip<W> {
+    pub fn new(out: W) -> StreamingZip<W> {
+        StreamingZip {
+            out,
+            offset: 0,
+            entries: 0,
+            central_directory: vec![],
+        }
+    }
+
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        modified: chrono::NaiveDateTime,
+        data: &[u8],
+    ) -> io::Result<()> {
+        const VERSION: u16 = 20;
+        const FLAG_UTF8_NAME: u16 = 1 << 11;
+        const METHOD_STORED: u16 = 0;
+
+        let (date, time) = dos_date_time(modified);
+        let crc = crc32fast::hash(data);
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        let entries = self.entries.checked_add(1).ok_or_else(too_large)?;
+
+        let mut header = vec![];
+        header.write_u32::<LittleEndian>(0x0403_4b50)?;
+        header.write_u16::<LittleEndian>(VERSION)?;
+        header.write_u16::<LittleEndian>(FLAG_UTF8_NAME)?;
+        header.write_u16::<LittleEndian>(METHOD_STORED)?;
+        header.write_u16::<LittleEndian>(time)?;
+        header.write_u16::<LittleEndian>(date)?;
+        header.write_u32::<LittleEndian>(crc)?;
+        header.write_u32::<LittleEndian>(size)?;
+        header.write_u32::<LittleEndian>(size)?;
+        header.write_u16::<LittleEndian>(name_len)?;
+        header.write_u16::<LittleEndian>(0)?;
+        header.write_all(name.as_bytes())?;
+
+        let next_offset = u32::try_from(header.len())
+            .ok()
+            .and_then(|x| x.checked_add(size))
+            .and_then(|x| x.checked_add(self.offset))
+            .ok_or_else(too_large)?;
+
+        let cd = &mut self.central_directory;
+        cd.write_u32::<LittleEndian>(0x0201_4b50)?;
+        cd.write_u16::<LittleEndian>(VERSION)?;
+        cd.write_u16::<LittleEndian>(VERSION)?;
+        cd.write_u16::<LittleEndian>(FLAG_UTF8_NAME)?;
+        cd.write_u16::<LittleEndian>(METHOD_STORED)?;
+        cd.write_u16::<LittleEndian>(time)?;
+        cd.write_u16::<LittleEndian>(date)?;
+        cd.write_u32::<LittleEndian>(crc)?;
+        cd.write_u32::<LittleEndian>(size)?;
+        cd.write_u32::<LittleEndian>(size)?;
+        cd.write_u16::<LittleEndian>(name_len)?;
+        cd.write_u16::<LittleEndian>(0)?; // Extra field length
+        cd.write_u16::<LittleEndian>(0)?; // Comment length
+        cd.write_u16::<LittleEndian>(0)?; // Disk number
+        cd.write_u16::<LittleEndian>(0)?; // Internal attributes
+        cd.write_u32::<LittleEndian>(0)?; // External attributes
+        cd.write_u32::<LittleEndian>(self.offset)?;
+        cd.write_all(name.as_bytes())?;
+
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+
+        self.offset = next_offset;
+        self.entries = entries;
+
+        Ok(())
+    }
+
+    /// Write the central directory, which ends the archive
+    pub fn finish(mut self) -> io::Result<W> {
+        let cd_size = u32::try_from(self.central_directory.len()).map_err(|_| too_large())?;
+
+        self.out.write_all(&self.central_directory)?;
+
+        let out = &mut self.out;
+        out.write_u32::<LittleEndian>(0x0605_4b50)?;
+        out.write_u16::<LittleEndian>(0)?; // Number of this disk
+        out.write_u16::<LittleEndian>(0)?; // Disk where the central directory starts
+        out.write_u16::<LittleEndian>(self.entries)?;
+        out.write_u16::<LittleEndian>(self.entries)?;
+        out.write_u32::<LittleEndian>(cd_size)?;
+        out.write_u32::<LittleEndian>(self.offset)?;
+        out.write_u16::<LittleEndian>(0)?; // Comment length
+        out.flush()?;
+
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = vec![];
+        ::image::png::PNGEncoder::new(&mut png)
+            .encode(
+                &vec![0; (width * height * 3) as usize],
+                width,
+                height,
+                ::image::ColorType::RGB(8),
+            )
+            .unwrap();
+        png
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        zip.add_directory("photos/", Default::default()).unwrap();
+        for (name, data) in files {
+            zip.start_file(*name, Default::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn images_are_unpacked_and_the_rest_skipped() {
+        let small = png(20, 10);
+        let large = png(40, 10);
+        let data = zip(&[
+            ("photos/a.png", &small),
+            ("photos/notes.txt", b"Hello"),
+            ("photos/b.png", &large),
+        ]);
+        assert!(is_zip(&data));
+
+        let limits = image::Limits {
+            max_dimension: 20,
+            ..Default::default()
+        };
+        let files = read_zip(&data, &limits).unwrap();
+
+        let names: Vec<&str> = files.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["photos/a.png", "photos/notes.txt", "photos/b.png"]);
+
+        assert_eq!(files[0].content, Ok(("image/png", small)));
+        assert!(files[1].content.is_err());
+        assert!(files[2].content.is_err());
+    }
+
+    #[test]
+    fn archive_size_is_limited() {
+        let a = png(20, 10);
+        let data = zip(&[("a.png", &a), ("b.png", &a), ("c.png", &a)]);
+
+        let limits = image::Limits {
+            max_series_upload_bytes: a.len() * 2,
+            ..Default::default()
+        };
+        let files = read_zip(&data, &limits).unwrap();
+
+        assert!(files[0].content.is_ok());
+        assert!(files[1].content.is_ok());
+        assert_eq!(
+            files[2].content,
+            Err("The archive exceeds the size limit".to_string())
+        );
+    }
+
+    #[test]
+    fn unreadable_files_are_skipped() {
+        let a = png(20, 10);
+        let modified = chrono::NaiveDate::from_ymd(2020, 9, 5).and_hms(10, 15, 42);
+
+        let mut zip = StreamingZip::new(vec![]);
+        zip.add_file("a.png", modified, &a).unwrap();
+        zip.add_file("b.png", modified, &a).unwrap();
+        zip.add_file("c.png", modified, &a).unwrap();
+        let mut data = zip.finish().unwrap();
+
+        // Mark b.png as encrypted and c.png as compressed with bzip2 in the
+        // central directory
+        let entries: Vec<usize> = (0..data.len() - 4)
+            .filter(|&i| data[i..].starts_with(b"PK\x01\x02"))
+            .collect();
+        data[entries[1] + 8] |= 1;
+        data[entries[2] + 10] = 12;
+
+        let files = read_zip(&data, &Default::default()).unwrap();
+
+        assert_eq!(files[0].content, Ok(("image/png", a)));
+        assert_eq!(files[1].name, "b.png");
+        assert_eq!(files[1].content, Err("The file is encrypted".to_string()));
+        assert_eq!(files[2].name, "c.png");
+        assert_eq!(
+            files[2].content,
+            Err("The file uses an unsupported compression".to_string())
+        );
+    }
+
+    #[test]
+    fn streaming_zip_can_be_read_back() {
+        let modified = chrono::NaiveDate::from_ymd(2020, 9, 5).and_hms(10, 15, 42);
+
+        let mut zip = StreamingZip::new(vec![]);
+        zip.add_file("1_blåbær.jpg", modified, b"JPEG").unwrap();
+        zip.add_file("1_blåbær.txt", modified, b"Tur i skogen")
+            .unwrap();
+        let data = zip.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(archive.len(), 2);
+
+        let mut file = archive.by_index(1).unwrap();
+        assert_eq!(file.name(), "1_blåbær.txt");
+        assert_eq!(file.last_modified().second(), 42);
+
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "Tur i skogen");
+    }
+}
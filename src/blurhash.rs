@@ -0,0 +1,242 @@
+// BlurHash, a compact string representation of a blurred version of an
+// image, see https://github.com/woltapp/blurhash/blob/master/Algorithm.md
+
+use image::{ImageBuffer, Rgb, RgbImage};
+use std::f32::consts::PI;
+
+const BASE83: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn encode83(value: u32, length: usize, dest: &mut String) {
+    for i in (0..length).rev() {
+        let digit = (value / 83u32.pow(i as u32)) % 83;
+        dest.push(BASE83[digit as usize] as char);
+    }
+}
+
+fn decode83(src: &str) -> Option<u32> {
+    src.bytes().try_fold(0, |acc, c| {
+        let digit = BASE83.iter().position(|&x| x == c)?;
+        Some(acc * 83 + digit as u32)
+    })
+}
+
+fn linear_to_srgb(l: f32) -> u32 {
+    let l = l.max(0.).min(1.);
+    let s = if l <= 0.0031308 {
+        l * 12.92
+    } else {
+        1.055 * l.powf(1. / 2.4) - 0.055
+    };
+    (s * 255. + 0.5) as u32
+}
+
+fn srgb_to_linear(s: u32) -> f32 {
+    let s = s as f32 / 255.;
+    if s <= 0.04045 {
+        s / 12.92
+    } else {
+        ((s + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn sign_pow(value: f32, exp: f32) -> f32 {
+    value.abs().powf(exp).copysign(value)
+}
+
+/// Encode an image in linear light, using the given number of horizontal
+/// and vertical components, each between 1 and 9
+///
+/// A small input image is sufficient, the result is blurred anyway.
+pub fn encode(
+    img: &ImageBuffer<Rgb<f32>, Vec<f32>>,
+    x_components: u32,
+    y_components: u32,
+) -> String {
+    assert!((1..=9).contains(&x_components) && (1..=9).contains(&y_components));
+
+    let (width, height) = img.dimensions();
+
+    let factors: Vec<[f32; 3]> = (0..y_components)
+        .flat_map(|j| (0..x_components).map(move |i| (i, j)))
+        .map(|(i, j)| {
+            let normalisation = if i == 0 && j == 0 { 1. } else { 2. };
+
+            let mut acc = [0f32; 3];
+            for (x, y, px) in img.enumerate_pixels() {
+                let basis = normalisation
+                    * (PI * i as f32 * x as f32 / width as f32).cos()
+                    * (PI * j as f32 * y as f32 / height as f32).cos();
+                for c in 0..3 {
+                    acc[c] += basis * px[c];
+                }
+            }
+
+            let scale = 1. / (width * height) as f32;
+            [acc[0] * scale, acc[1] * scale, acc[2] * scale]
+        })
+        .collect();
+
+    let (dc, ac) = factors.split_first().unwrap();
+
+    let mut hash = String::new();
+    encode83((x_components - 1) + (y_components - 1) * 9, 1, &mut hash);
+
+    let actual_max = ac
+        .iter()
+        .flat_map(|x| x.iter())
+        .fold(0f32, |max, x| max.max(x.abs()));
+    let quantised_max = if ac.is_empty() {
+        0
+    } else {
+        ((actual_max * 166. - 0.5).floor().max(0.) as u32).min(82)
+    };
+    let max_value = (quantised_max + 1) as f32 / 166.;
+    encode83(quantised_max, 1, &mut hash);
+
+    let dc_value =
+        (linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]);
+    encode83(dc_value, 4, &mut hash);
+
+    for factor in ac {
+        let quant = |x: f32| {
+            (sign_pow(x / max_value, 0.5) * 9. + 9.5)
+                .floor()
+                .max(0.)
+                .min(18.) as u32
+        };
+        encode83(
+            quant(factor[0]) * 19 * 19 + quant(factor[1]) * 19 + quant(factor[2]),
+            2,
+            &mut hash,
+        );
+    }
+
+    hash
+}
+
+/// Render a BlurHash as an sRGB image of the given size
+///
+/// Returns `None` for malformed input.
+pub fn decode(hash: &str, width: u32, height: u32) -> Option<RgbImage> {
+    if !hash.is_ascii() || hash.len() < 6 {
+        return None;
+    }
+
+    let size_flag = decode83(&hash[0..1])?;
+    let x_components = size_flag % 9 + 1;
+    let y_components = size_flag / 9 + 1;
+
+    if hash.len() != (4 + 2 * x_components * y_components) as usize {
+        return None;
+    }
+
+    let max_value = (decode83(&hash[1..2])? + 1) as f32 / 166.;
+
+    let dc = decode83(&hash[2..6])?;
+    let mut colors = vec![[
+        srgb_to_linear(dc >> 16),
+        srgb_to_linear((dc >> 8) & 255),
+        srgb_to_linear(dc & 255),
+    ]];
+
+    for i in 1..(x_components * y_components) as usize {
+        let value = decode83(&hash[4 + i * 2..6 + i * 2])?;
+        let unquant = |q: u32| sign_pow((q as f32 - 9.) / 9., 2.) * max_value;
+        colors.push([
+            unquant(value / (19 * 19)),
+            unquant((value / 19) % 19),
+            unquant(value % 19),
+        ]);
+    }
+
+    Some(RgbImage::from_fn(width, height, |x, y| {
+        let mut px = [0f32; 3];
+        for j in 0..y_components {
+            for i in 0..x_components {
+                let basis = (PI * x as f32 * i as f32 / width as f32).cos()
+                    * (PI * y as f32 * j as f32 / height as f32).cos();
+                let color = colors[(i + j * x_components) as usize];
+                for c in 0..3 {
+                    px[c] += color[c] * basis;
+                }
+            }
+        }
+
+        Rgb([
+            linear_to_srgb(px[0]) as u8,
+            linear_to_srgb(px[1]) as u8,
+            linear_to_srgb(px[2]) as u8,
+        ])
+    }))
+}
+
+/// Render a BlurHash as a tiny PNG in a data URI, for inlining into HTML
+///
+/// The image is meant to be scaled up by the browser, which smooths it out.
+pub fn to_data_uri(hash: &str, aspect_ratio: f32) -> Option<String> {
+    const SIZE: f32 = 16.;
+
+    let (width, height) = if aspect_ratio >= 1. {
+        (SIZE, (SIZE / aspect_ratio).max(1.))
+    } else {
+        ((SIZE * aspect_ratio).max(1.), SIZE)
+    };
+    let img = decode(hash, width.round() as u32, height.round() as u32)?;
+
+    let mut png = vec![];
+    image::png::PNGEncoder::new(&mut png)
+        .encode(&img, img.width(), img.height(), image::ColorType::RGB(8))
+        .ok()?;
+
+    Some(format!("data:image/png;base64,{}", base64::encode(&png)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn base83_roundtrip() {
+        let mut s = String::new();
+        encode83(123456, 4, &mut s);
+        assert_eq!(s.len(), 4);
+        assert_eq!(decode83(&s), Some(123456));
+    }
+
+    #[test]
+    fn solid_color_roundtrip() {
+        let color = srgb_to_linear(200);
+        let img = ImageBuffer::from_pixel(32, 24, Rgb([color, color, color]));
+
+        let hash = encode(&img, 1, 1);
+        assert_eq!(hash.len(), 6);
+
+        let decoded = decode(&hash, 8, 6).unwrap();
+        for px in decoded.pixels() {
+            assert_eq!(px, &Rgb([200, 200, 200]));
+        }
+    }
+
+    #[test]
+    fn gradient_roundtrip() {
+        let img = ImageBuffer::from_fn(32, 24, |x, _| {
+            let v = x as f32 / 31.;
+            Rgb([v, v, v])
+        });
+
+        let hash = encode(&img, 4, 3);
+        assert_eq!(hash.len(), 4 + 2 * 4 * 3);
+
+        let decoded = decode(&hash, 8, 6).unwrap();
+        let row: Vec<u8> = (0..8).map(|x| decoded.get_pixel(x, 3)[0]).collect();
+        assert!(row.windows(2).all(|w| w[0] < w[1]), "{:?}", row);
+        assert!(row[0] < 100 && row[7] > 200, "{:?}", row);
+    }
+
+    #[test]
+    fn malformed_hash() {
+        assert!(decode("", 4, 4).is_none());
+        assert!(decode("LEHV6nWB2yk8", 4, 4).is_none());
+    }
+}
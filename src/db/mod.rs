@@ -18,6 +18,12 @@ impl CustomizeConnection<SqliteConnection, r2d2_diesel::Error> for SqliteInitial
             .execute(conn)
             .map_err(|x| r2d2_diesel::Error::QueryError(x))?;
 
+        // Wait for other writers, such as the workers of the regenerate
+        // command, rather than failing right away
+        sql::<Integer>("PRAGMA busy_timeout = 30000")
+            .execute(conn)
+            .map_err(|x| r2d2_diesel::Error::QueryError(x))?;
+
         Ok(())
     }
 }
@@ -40,7 +46,7 @@ pub fn create_pool(
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
     use super::*;
 
     #[cfg(test)]
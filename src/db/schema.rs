@@ -1,8 +1,38 @@
+table! {
+    deep_zoom_images (pixurs_id) {
+        pixurs_id -> Integer,
+        width -> Integer,
+        height -> Integer,
+        tile_size -> Integer,
+        overlap -> Integer,
+        media_type -> Text,
+    }
+}
+
+table! {
+    deep_zoom_tiles (pixurs_id, level, col, row) {
+        pixurs_id -> Integer,
+        level -> Integer,
+        col -> Integer,
+        row -> Integer,
+        data -> Binary,
+    }
+}
+
+table! {
+    image_encodings (images_id, media_type) {
+        images_id -> Integer,
+        media_type -> Text,
+        data -> Binary,
+    }
+}
+
 table! {
     images (id) {
         id -> Integer,
         media_type -> Text,
         data -> Binary,
+        version -> Integer,
     }
 }
 
@@ -12,6 +42,76 @@ table! {
         width -> Integer,
         height -> Integer,
         pixurs_id -> Integer,
+        rendition -> Nullable<Text>,
+        role -> Text,
+    }
+}
+
+table! {
+    ingest_batches (id) {
+        id -> Integer,
+        created -> Timestamp,
+        pixur_series_id -> Integer,
+        series_order -> Text,
+        finished -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    ingest_jobs (id) {
+        id -> Integer,
+        created -> Timestamp,
+        media_type -> Text,
+        data -> Nullable<Binary>,
+        stage -> Text,
+        pixurs_id -> Nullable<Integer>,
+        pixur_series_id -> Nullable<Integer>,
+        duplicates -> Nullable<Text>,
+        error -> Nullable<Text>,
+        batch_id -> Nullable<Integer>,
+        batch_position -> Nullable<Integer>,
+    }
+}
+
+table! {
+    motion_videos (pixurs_id) {
+        pixurs_id -> Integer,
+        media_type -> Text,
+        data -> Binary,
+    }
+}
+
+table! {
+    originals (id) {
+        id -> Integer,
+        pixurs_id -> Integer,
+        media_type -> Text,
+        data -> Binary,
+        adopted -> Bool,
+    }
+}
+
+table! {
+    photo_metadata (pixurs_id) {
+        pixurs_id -> Integer,
+        taken_at -> Nullable<Timestamp>,
+        camera_make -> Nullable<Text>,
+        camera_model -> Nullable<Text>,
+        lens -> Nullable<Text>,
+        exposure_time -> Nullable<Float>,
+        f_number -> Nullable<Float>,
+        iso -> Nullable<Integer>,
+        focal_length -> Nullable<Float>,
+        gps_latitude -> Nullable<Double>,
+        gps_longitude -> Nullable<Double>,
+        taken_at_offset -> Nullable<Integer>,
+    }
+}
+
+table! {
+    pixur_edits (pixurs_id) {
+        pixurs_id -> Integer,
+        edits -> Text,
     }
 }
 
@@ -32,6 +132,13 @@ table! {
     }
 }
 
+table! {
+    pixur_series_settings (pixur_series_id) {
+        pixur_series_id -> Integer,
+        allow_original_download -> Bool,
+    }
+}
+
 table! {
     pixurs (id) {
         id -> Integer,
@@ -43,6 +150,12 @@ table! {
         crop_right -> Float,
         crop_top -> Float,
         crop_bottom -> Float,
+        taken_at -> Nullable<Timestamp>,
+        perceptual_hash -> Nullable<BigInt>,
+        blurhash -> Nullable<Text>,
+        palette -> Nullable<Text>,
+        top_color -> Nullable<Integer>,
+        bottom_color -> Nullable<Integer>,
     }
 }
 
@@ -51,6 +164,42 @@ table! {
         id -> Integer,
         media_type -> Text,
         data -> Binary,
+        version -> Integer,
+    }
+}
+
+table! {
+    regenerated_pixurs (pixurs_id) {
+        pixurs_id -> Integer,
+    }
+}
+
+table! {
+    thumb_encodings (thumbs_id, media_type) {
+        thumbs_id -> Integer,
+        media_type -> Text,
+        data -> Binary,
+    }
+}
+
+table! {
+    upload_chunks (upload_sessions_id, start) {
+        upload_sessions_id -> Integer,
+        start -> BigInt,
+        data -> Binary,
+    }
+}
+
+table! {
+    upload_sessions (id) {
+        id -> Integer,
+        created -> Timestamp,
+        updated -> Timestamp,
+        sub -> Text,
+        media_type -> Text,
+        size -> BigInt,
+        received -> BigInt,
+        ingest_jobs_id -> Nullable<Integer>,
     }
 }
 
@@ -60,17 +209,44 @@ table! {
     }
 }
 
+joinable!(deep_zoom_images -> pixurs (pixurs_id));
+joinable!(deep_zoom_tiles -> deep_zoom_images (pixurs_id));
+joinable!(image_encodings -> images (images_id));
 joinable!(images_meta -> images (id));
 joinable!(images_meta -> pixurs (pixurs_id));
+joinable!(ingest_jobs -> ingest_batches (batch_id));
+joinable!(ingest_jobs -> pixurs (pixurs_id));
+joinable!(motion_videos -> pixurs (pixurs_id));
+joinable!(originals -> pixurs (pixurs_id));
+joinable!(photo_metadata -> pixurs (pixurs_id));
+joinable!(pixur_edits -> pixurs (pixurs_id));
 joinable!(pixur_series -> pixurs (pixurs_id));
 joinable!(pixurs -> thumbs (thumbs_id));
+joinable!(regenerated_pixurs -> pixurs (pixurs_id));
+joinable!(thumb_encodings -> thumbs (thumbs_id));
+joinable!(upload_chunks -> upload_sessions (upload_sessions_id));
+joinable!(upload_sessions -> ingest_jobs (ingest_jobs_id));
 
 allow_tables_to_appear_in_same_query!(
+    deep_zoom_images,
+    deep_zoom_tiles,
+    image_encodings,
     images,
     images_meta,
+    ingest_batches,
+    ingest_jobs,
+    motion_videos,
+    originals,
+    photo_metadata,
+    pixur_edits,
     pixur_series,
     pixur_series_authorizations,
+    pixur_series_settings,
     pixurs,
+    regenerated_pixurs,
+    thumb_encodings,
     thumbs,
+    upload_chunks,
+    upload_sessions,
     uploaders,
 );
@@ -0,0 +1,286 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use serde_derive::Deserialize;
+
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::rendition_policy::Format;
+
+fn default_min_pixels() -> u64 {
+    24_000_000
+}
+
+fn default_min_aspect_ratio() -> f32 {
+    3.
+}
+
+fn default_tile_size() -> u32 {
+    510
+}
+
+fn default_overlap() -> u32 {
+    1
+}
+
+fn default_format() -> Format {
+    Format::Jpeg
+}
+
+/// Which photos get a tile pyramid in addition to their renditions, so the
+/// viewer can zoom into them at full resolution, as configured in the
+/// `[deep_zoom]` section of config.toml
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeepZoom {
+    /// Photos with at least this many pixels get a pyramid
+    #[serde(default = "default_min_pixels")]
+    pub min_pixels: u64,
+
+    /// As do photos with at least this ratio of the long side to the short
+    /// side, such as panoramas
+    #[serde(default = "default_min_aspect_ratio")]
+    pub min_aspect_ratio: f32,
+
+    /// The size of the tiles, not counting the overlap
+    #[serde(default = "default_tile_size")]
+    pub tile_size: u32,
+
+    /// Tiles extend this far into their neighbours, to avoid visible seams
+    #[serde(default = "default_overlap")]
+    pub overlap: u32,
+
+    #[serde(default = "default_format")]
+    pub format: Format,
+
+    /// 1-100
+    pub quality: u8,
+}
+
+impl DeepZoom {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.quality < 1 || self.quality > 100 {
+            return Err("Deep zoom: quality must be 1-100".to_string());
+        }
+        if self.tile_size == 0 {
+            return Err("Deep zoom: tile size must be positive".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Whether a photo with the given size gets a pyramid
+    pub fn applies(&self, width: u32, height: u32) -> bool {
+        let long = width.max(height) as f32;
+        let short = width.min(height).max(1) as f32;
+
+        width as u64 * height as u64 >= self.min_pixels || long / short >= self.min_aspect_ratio
+    }
+}
+
+/// The layout of a tile pyramid, as in the Deep Zoom Image (DZI) format
+///
+/// Level 0 is the photo scaled down to a single pixel. Each following level
+/// is twice the size of the one before it, rounding up, and the last level
+/// is the photo at full size. Each level is cut into tiles, numbered by
+/// column and row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pyramid {
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: u32,
+    pub overlap: u32,
+}
+
+impl Pyramid {
+    pub fn new(width: u32, height: u32, deep_zoom: &DeepZoom) -> Pyramid {
+        Pyramid {
+            width,
+            height,
+            tile_size: deep_zoom.tile_size,
+            overlap: deep_zoom.overlap,
+        }
+    }
+
+    pub fn max_level(&self) -> u32 {
+        let long = self.width.max(self.height).max(1);
+        32 - (long - 1).leading_zeros()
+    }
+
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        let scale = 1u64 << (self.max_level() - level);
+        let scaled = |x: u32| ((x as u64 + scale - 1) / scale).max(1) as u32;
+
+        (scaled(self.width), scaled(self.height))
+    }
+
+    /// The number of columns and rows of tiles in a level
+    pub fn tile_count(&self, level: u32) -> (u32, u32) {
+        let (width, height) = self.level_size(level);
+        let count = |x: u32| (x + self.tile_size - 1) / self.tile_size;
+
+        (count(width), count(height))
+    }
+
+    /// The part of a level that a tile covers, as x, y, width and height,
+    /// including the overlap
+    pub fn tile_rect(&self, level: u32, col: u32, row: u32) -> (u32, u32, u32, u32) {
+        let (width, height) = self.level_size(level);
+        let span = |i: u32, len: u32| {
+            let start = (i * self.tile_size).saturating_sub(self.overlap);
+            let end = ((i + 1) * self.tile_size + self.overlap).min(len);
+            (start, end - start)
+        };
+
+        let (x, w) = span(col, width);
+        let (y, h) = span(row, height);
+        (x, y, w, h)
+    }
+
+    /// The DZI descriptor, naming the tiles with the given extension
+    pub fn descriptor(&self, extension: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" \
+             Format=\"{}\" Overlap=\"{}\" TileSize=\"{}\">\n\
+             <Size Width=\"{}\" Height=\"{}\"/>\n\
+             </Image>\n",
+            extension, self.overlap, self.tile_size, self.width, self.height
+        )
+    }
+}
+
+pub struct Tile {
+    pub level: u32,
+    pub col: u32,
+    pub row: u32,
+    pub data: Vec<u8>,
+}
+
+/// The encoded tiles of a photo, see `image::render_tiles`
+pub struct Tiles {
+    pub pyramid: Pyramid,
+    pub media_type: &'static str,
+    pub tiles: Vec<Tile>,
+}
+
+/// Replace the tile pyramid of a pixur, or remove it if the photo no longer
+/// gets one
+pub fn store(
+    db_connection: &SqliteConnection,
+    pixurs_id: Id30,
+    tiles: Option<&Tiles>,
+) -> Result<(), diesel::result::Error> {
+    diesel::delete(deep_zoom_tiles::table.filter(deep_zoom_tiles::pixurs_id.eq(pixurs_id)))
+        .execute(db_connection)?;
+    diesel::delete(deep_zoom_images::table.filter(deep_zoom_images::pixurs_id.eq(pixurs_id)))
+        .execute(db_connection)?;
+
+    let tiles = match tiles {
+        Some(tiles) => tiles,
+        None => return Ok(()),
+    };
+
+    let pyramid = tiles.pyramid;
+    diesel::insert_into(deep_zoom_images::table)
+        .values((
+            deep_zoom_images::pixurs_id.eq(pixurs_id),
+            deep_zoom_images::width.eq(pyramid.width as i32),
+            deep_zoom_images::height.eq(pyramid.height as i32),
+            deep_zoom_images::tile_size.eq(pyramid.tile_size as i32),
+            deep_zoom_images::overlap.eq(pyramid.overlap as i32),
+            deep_zoom_images::media_type.eq(tiles.media_type),
+        ))
+        .execute(db_connection)?;
+
+    for tile in &tiles.tiles {
+        diesel::insert_into(deep_zoom_tiles::table)
+            .values((
+                deep_zoom_tiles::pixurs_id.eq(pixurs_id),
+                deep_zoom_tiles::level.eq(tile.level as i32),
+                deep_zoom_tiles::col.eq(tile.col as i32),
+                deep_zoom_tiles::row.eq(tile.row as i32),
+                deep_zoom_tiles::data.eq(&tile.data),
+            ))
+            .execute(db_connection)?;
+    }
+
+    Ok(())
+}
+
+/// The layout and media type of the tile pyramid of a pixur, if it has one
+pub fn load(
+    db_connection: &SqliteConnection,
+    pixurs_id: Id30,
+) -> Result<Option<(Pyramid, String)>, diesel::result::Error> {
+    let stored: Option<(i32, i32, i32, i32, String)> = deep_zoom_images::table
+        .filter(deep_zoom_images::pixurs_id.eq(pixurs_id))
+        .select((
+            deep_zoom_images::width,
+            deep_zoom_images::height,
+            deep_zoom_images::tile_size,
+            deep_zoom_images::overlap,
+            deep_zoom_images::media_type,
+        ))
+        .first(db_connection)
+        .optional()?;
+
+    Ok(
+        stored.map(|(width, height, tile_size, overlap, media_type)| {
+            let pyramid = Pyramid {
+                width: width as u32,
+                height: height as u32,
+                tile_size: tile_size as u32,
+                overlap: overlap as u32,
+            };
+            (pyramid, media_type)
+        }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn deep_zoom() -> DeepZoom {
+        toml::from_str("quality = 80").unwrap()
+    }
+
+    #[test]
+    fn photos_that_get_a_pyramid() {
+        let deep_zoom = deep_zoom();
+        assert!(deep_zoom.validate().is_ok());
+
+        assert!(deep_zoom.applies(6000, 4000));
+        assert!(deep_zoom.applies(6000, 2000));
+        assert!(deep_zoom.applies(900, 3000));
+        assert!(!deep_zoom.applies(4032, 3024));
+    }
+
+    #[test]
+    fn levels_halve_down_to_a_pixel() {
+        let pyramid = Pyramid::new(1500, 700, &deep_zoom());
+
+        assert_eq!(pyramid.max_level(), 11);
+        assert_eq!(pyramid.level_size(11), (1500, 700));
+        assert_eq!(pyramid.level_size(10), (750, 350));
+        assert_eq!(pyramid.level_size(9), (375, 175));
+        assert_eq!(pyramid.level_size(8), (188, 88));
+        assert_eq!(pyramid.level_size(0), (1, 1));
+
+        assert_eq!(Pyramid::new(1, 1, &deep_zoom()).max_level(), 0);
+        assert_eq!(Pyramid::new(512, 3, &deep_zoom()).max_level(), 9);
+    }
+
+    #[test]
+    fn tiles_overlap_their_neighbours() {
+        let pyramid = Pyramid::new(1500, 700, &deep_zoom());
+
+        assert_eq!(pyramid.tile_count(11), (3, 2));
+        assert_eq!(pyramid.tile_rect(11, 0, 0), (0, 0, 511, 511));
+        assert_eq!(pyramid.tile_rect(11, 1, 1), (509, 509, 512, 191));
+        assert_eq!(pyramid.tile_rect(11, 2, 0), (1019, 0, 481, 511));
+
+        assert_eq!(pyramid.tile_count(10), (2, 1));
+        assert_eq!(pyramid.tile_rect(10, 1, 0), (509, 0, 241, 350));
+    }
+}
@@ -0,0 +1,127 @@
+use byteorder::{BigEndian, ByteOrder};
+
+const MARKER_SOI: u8 = 0xd8;
+const MARKER_SOS: u8 = 0xda;
+const MARKER_EOI: u8 = 0xd9;
+const MARKER_APP2: u8 = 0xe2;
+
+const ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+
+/// Extract the embedded ICC profile from a JPEG file
+///
+/// The profile is stored in one or more APP2 segments, each prefixed with
+/// a sequence number and the total number of segments, see section B.4 of
+/// the ICC specification. Returns `None` if there is no profile or if the
+/// segments are inconsistent.
+pub fn from_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(0..2)? != [0xff, MARKER_SOI] {
+        return None;
+    }
+
+    let mut chunks: Vec<(u8, &[u8])> = vec![];
+    let mut count = None;
+
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+
+        match marker {
+            // Fill bytes
+            0xff => {
+                pos += 1;
+                continue;
+            }
+            // The ICC profile must precede the image data
+            MARKER_SOS | MARKER_EOI => break,
+            _ => (),
+        }
+
+        let len = BigEndian::read_u16(data.get(pos + 2..pos + 4)?) as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+
+        if marker == MARKER_APP2 && segment.starts_with(ICC_SIGNATURE) {
+            let header = segment.get(ICC_SIGNATURE.len()..ICC_SIGNATURE.len() + 2)?;
+            let (seq_no, num_markers) = (header[0], header[1]);
+
+            if count.get_or_insert(num_markers) != &num_markers {
+                return None;
+            }
+
+            chunks.push((seq_no, &segment[ICC_SIGNATURE.len() + 2..]));
+        }
+
+        pos += 2 + len;
+    }
+
+    // Sequence numbers are 1-based and must all be present exactly once
+    chunks.sort_by_key(|&(seq_no, _)| seq_no);
+    let complete = count? as usize == chunks.len()
+        && chunks
+            .iter()
+            .enumerate()
+            .all(|(i, &(seq_no, _))| seq_no as usize == i + 1);
+
+    if !complete {
+        return None;
+    }
+
+    Some(chunks.into_iter().flat_map(|(_, x)| x).cloned().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let len = (payload.len() + 2) as u16;
+        let mut seg = vec![0xff, marker, (len >> 8) as u8, len as u8];
+        seg.extend_from_slice(payload);
+        seg
+    }
+
+    fn icc_segment(seq_no: u8, num_markers: u8, data: &[u8]) -> Vec<u8> {
+        let mut payload = ICC_SIGNATURE.to_vec();
+        payload.extend_from_slice(&[seq_no, num_markers]);
+        payload.extend_from_slice(data);
+        segment(MARKER_APP2, &payload)
+    }
+
+    fn jpeg(segments: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0xff, MARKER_SOI];
+        for seg in segments {
+            data.extend_from_slice(seg);
+        }
+        data.extend_from_slice(&[0xff, MARKER_SOS]);
+        data
+    }
+
+    #[test]
+    fn no_profile() {
+        let data = jpeg(&[segment(0xe0, b"JFIF\0")]);
+        assert_eq!(from_jpeg(&data), None);
+    }
+
+    #[test]
+    fn profile_split_over_segments_out_of_order() {
+        let data = jpeg(&[
+            segment(0xe0, b"JFIF\0"),
+            icc_segment(2, 2, b"def"),
+            icc_segment(1, 2, b"abc"),
+        ]);
+        assert_eq!(from_jpeg(&data), Some(b"abcdef".to_vec()));
+    }
+
+    #[test]
+    fn incomplete_profile() {
+        let data = jpeg(&[icc_segment(1, 2, b"abc")]);
+        assert_eq!(from_jpeg(&data), None);
+    }
+
+    #[test]
+    fn not_a_jpeg() {
+        assert_eq!(from_jpeg(b"\x89PNG"), None);
+    }
+}
@@ -1,17 +1,33 @@
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
-use image::{ImageBuffer, Pixel, Rgb, RgbImage};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgb, RgbImage, RgbaImage};
 use r2d2::Pool;
 use r2d2_diesel::ConnectionManager;
 use rayon::prelude::*;
 use std::convert::TryInto;
 use stopwatch::Stopwatch;
 
+use crate::blurhash;
 use crate::db::schema::*;
+use crate::deep_zoom::{self, DeepZoom, Pyramid, Tile, Tiles};
+use crate::icc_profile;
 use crate::id30::Id30;
+use crate::image_edit::{self, Edit};
+use crate::ingest_job::Stage;
+use crate::motion_photo;
+use crate::palette::{self, Palette};
+use crate::perceptual_hash;
+use crate::photo_metadata;
+use crate::rendition_policy::{Format, Rendition, RenditionPolicy, Role};
+use crate::resize::lanczos3_resize;
+use crate::unsharp_mask::{unsharp_mask, UnsharpMask};
 
 type RgbImageF32 = ImageBuffer<Rgb<f32>, Vec<f32>>;
 
+/// Called when processing reaches a stage. Renditions are processed in
+/// parallel, so stages may be reported repeatedly and out of order
+pub type Progress<'a> = dyn Fn(Stage) + Sync + 'a;
+
 include!(concat!(env!("OUT_DIR"), "/srgb.rs"));
 
 fn srgb_to_linear(s: u8) -> f32 {
@@ -80,11 +96,10 @@ fn image_srgb_to_linear(src: RgbImage) -> RgbImageF32 {
     RgbImageF32::from_raw(width, height, data).unwrap()
 }
 
-fn image_linear_to_srgb(src: RgbImageF32) -> RgbImage {
+fn image_linear_to_srgb(src: &RgbImageF32) -> RgbImage {
     let (width, height) = src.dimensions();
-    let data = src.into_raw();
 
-    let data: Vec<_> = data.into_par_iter().map(|x| linear_to_srgb(x)).collect();
+    let data: Vec<_> = src.par_iter().map(|&x| linear_to_srgb(x)).collect();
 
     RgbImage::from_raw(width, height, data).unwrap()
 }
@@ -100,12 +115,41 @@ fn avg_color(src: &RgbImageF32) -> Rgb<f32> {
     acc.map(|x| x / pixels)
 }
 
-fn encode_jpeg(img: RgbImage, quality: u8) -> std::io::Result<Vec<u8>> {
-    let mut buf = Vec::new();
-    let mut encoder = image::jpeg::JPEGEncoder::new_with_quality(&mut buf, quality);
-    let (width, height) = (img.width(), img.height());
-    encoder.encode(&img.into_raw(), width, height, image::ColorType::RGB(8))?;
-    Ok(buf)
+fn encode_webp(img: &RgbImage, quality: u8) -> Vec<u8> {
+    let (width, height) = img.dimensions();
+    webp::Encoder::from_rgb(img, width, height)
+        .encode(quality as f32)
+        .to_vec()
+}
+
+/// Encode as a progressive JPEG with optimized Huffman tables
+///
+/// Progressive JPEGs show a full-frame preview early while loading, and
+/// are typically smaller than baseline JPEGs at the same quality.
+fn encode_jpeg(img: &RgbImage, quality: u8, chroma_subsampling: bool) -> std::io::Result<Vec<u8>> {
+    // mozjpeg reports errors by panicking
+    std::panic::catch_unwind(|| {
+        let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+        comp.set_size(img.width() as usize, img.height() as usize);
+        comp.set_quality(quality as f32);
+        comp.set_progressive_mode();
+        comp.set_optimize_coding(true);
+
+        // Chroma subsampling is expressed by sampling luma more densely
+        let luma_samples = if chroma_subsampling { 2 } else { 1 };
+        let luma = &mut comp.components_mut()[0];
+        luma.h_samp_factor = luma_samples;
+        luma.v_samp_factor = luma_samples;
+
+        comp.set_mem_dest();
+        comp.start_compress();
+        assert!(comp.write_scanlines(img));
+        comp.finish_compress();
+        comp.data_to_vec()
+    })
+    .ok()
+    .and_then(|x| x.ok())
+    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "Failed to encode JPEG"))
 }
 
 fn transform_by_orientation(img: image::RgbImage, orientation: u32) -> image::RgbImage {
@@ -181,112 +225,1201 @@ fn transform_by_orientation(img: image::RgbImage, orientation: u32) -> image::Rg
     image::RgbImage::from_vec(new_dim[0] as _, new_dim[1] as _, dest_buf).unwrap()
 }
 
-pub fn ingest_jpeg(
-    jpeg: &[u8],
-    db_pool: Pool<ConnectionManager<SqliteConnection>>,
-) -> Result<(Id30, Id30), Box<dyn std::error::Error>> {
+/// Composite an image with an alpha channel onto its own average color
+///
+/// The average is weighted by alpha and both it and the compositing is
+/// computed in linear light, so transparent areas blend in with the photo.
+fn image_srgba_to_linear_composited(src: RgbaImage) -> RgbImageF32 {
+    let (width, height) = src.dimensions();
+    let data = src.into_raw();
+
+    let linear_with_alpha = |px: &[u8]| -> [f32; 4] {
+        let a = px[3] as f32 / 255.;
+        [
+            srgb_to_linear(px[0]),
+            srgb_to_linear(px[1]),
+            srgb_to_linear(px[2]),
+            a,
+        ]
+    };
+
+    let acc = data
+        .par_chunks(4)
+        .map(|px| {
+            let [r, g, b, a] = linear_with_alpha(px);
+            [r * a, g * a, b * a, a]
+        })
+        .reduce(
+            || [0., 0., 0., 0.],
+            |x, y| [x[0] + y[0], x[1] + y[1], x[2] + y[2], x[3] + y[3]],
+        );
+
+    let background = if acc[3] > 0. {
+        [acc[0] / acc[3], acc[1] / acc[3], acc[2] / acc[3]]
+    } else {
+        [0., 0., 0.]
+    };
+
+    let mut composited = vec![0f32; data.len() / 4 * 3];
+    composited
+        .par_chunks_mut(3)
+        .zip(data.par_chunks(4))
+        .for_each(|(dest, px)| {
+            let [r, g, b, a] = linear_with_alpha(px);
+            dest[0] = r * a + background[0] * (1. - a);
+            dest[1] = g * a + background[1] * (1. - a);
+            dest[2] = b * a + background[2] * (1. - a);
+        });
+
+    RgbImageF32::from_raw(width, height, composited).unwrap()
+}
+
+/// Convert an image in the color space described by the given ICC profile to
+/// linear light with sRGB primaries, which is what the rest of the pipeline
+/// works in
+///
+/// Colors outside of the sRGB gamut, as found in Display P3 photos from
+/// phones, are clipped when the renditions are encoded. Since the renditions
+/// are plain sRGB they need no embedded profile to be displayed correctly.
+fn image_icc_to_linear(src: &RgbImage, icc: &[u8]) -> Result<RgbImageF32, lcms2::Error> {
+    use lcms2::{CIExyY, CIExyYTRIPLE, Intent, PixelFormat, Profile, ToneCurve, Transform};
+
+    let xy = |x, y| CIExyY { x, y, Y: 1. };
+    let d65 = xy(0.3127, 0.3290);
+    let srgb_primaries = CIExyYTRIPLE {
+        Red: xy(0.64, 0.33),
+        Green: xy(0.30, 0.60),
+        Blue: xy(0.15, 0.06),
+    };
+    let linear = ToneCurve::new(1.);
+
+    let input = Profile::new_icc(icc)?;
+    let output = Profile::new_rgb(&d65, &srgb_primaries, &[&linear, &linear, &linear])?;
+
+    let transform: Transform<[u8; 3], [f32; 3]> = Transform::new(
+        &input,
+        PixelFormat::RGB_8,
+        &output,
+        PixelFormat::RGB_FLT,
+        Intent::Perceptual,
+    )?;
+
+    let (width, height) = src.dimensions();
+    let pixels: Vec<[u8; 3]> = src
+        .chunks_exact(3)
+        .map(|px| [px[0], px[1], px[2]])
+        .collect();
+    let mut linear_pixels = vec![[0f32; 3]; pixels.len()];
+    transform.transform_pixels(&pixels, &mut linear_pixels);
+
+    let data = linear_pixels
+        .iter()
+        .flat_map(|px| px.iter().cloned())
+        .collect();
+    Ok(RgbImageF32::from_raw(width, height, data).unwrap())
+}
+
+/// Map the media type of an upload to the image format used for decoding
+///
+/// Returns `None` for unsupported media types.
+pub fn image_format(media_type: &str) -> Option<image::ImageFormat> {
+    match media_type {
+        "image/jpeg" => Some(image::ImageFormat::JPEG),
+        "image/png" => Some(image::ImageFormat::PNG),
+        "image/gif" => Some(image::ImageFormat::GIF),
+        "image/tiff" => Some(image::ImageFormat::TIFF),
+        _ => None,
+    }
+}
+
+/// The inverse of `image_format`
+pub fn media_type(format: image::ImageFormat) -> Option<&'static str> {
+    match format {
+        image::ImageFormat::JPEG => Some("image/jpeg"),
+        image::ImageFormat::PNG => Some("image/png"),
+        image::ImageFormat::GIF => Some("image/gif"),
+        image::ImageFormat::TIFF => Some("image/tiff"),
+        _ => None,
+    }
+}
+
+/// Limits on uploads, to protect against running out of memory. Decoding
+/// takes 12 bytes per pixel in linear light
+#[derive(Debug, Clone, Copy, serde_derive::Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Size of the uploaded file
+    pub max_upload_bytes: usize,
+
+    /// Size of a multi-file upload as a whole. Each file is also limited by
+    /// `max_upload_bytes`
+    pub max_series_upload_bytes: usize,
+
+    /// Width and height, as declared in the header of the uploaded file
+    pub max_dimension: u32,
+
+    /// Width times height
+    pub max_pixels: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_upload_bytes: 64 * 1024 * 1024,
+            max_series_upload_bytes: 512 * 1024 * 1024,
+            max_dimension: 16384,
+            max_pixels: 64_000_000,
+        }
+    }
+}
+
+/// The reason an upload is refused before it is decoded
+#[derive(Debug)]
+pub enum Rejected {
+    UnreadableHeader,
+    TooLarge { width: u64, height: u64 },
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Rejected::UnreadableHeader => write!(f, "Unable to read image header"),
+            Rejected::TooLarge { width, height } => {
+                write!(f, "Image dimensions {}x{} exceed the limits", width, height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Check the dimensions declared in the header of an image against the
+/// limits, without decoding the image
+pub fn check_limits(
+    data: &[u8],
+    format: image::ImageFormat,
+    limits: &Limits,
+) -> Result<(), Rejected> {
+    use image::ImageDecoder;
+
+    let cursor = std::io::Cursor::new(data);
+    let dimensions = match format {
+        image::ImageFormat::JPEG => image::jpeg::JPEGDecoder::new(cursor).map(|x| x.dimensions()),
+        image::ImageFormat::PNG => image::png::PNGDecoder::new(cursor).map(|x| x.dimensions()),
+        image::ImageFormat::GIF => image::gif::Decoder::new(cursor).map(|x| x.dimensions()),
+        image::ImageFormat::TIFF => image::tiff::TIFFDecoder::new(cursor).map(|x| x.dimensions()),
+        _ => return Err(Rejected::UnreadableHeader),
+    };
+    let (width, height) = dimensions.map_err(|_| Rejected::UnreadableHeader)?;
+
+    let max_dimension = limits.max_dimension as u64;
+    if width > max_dimension || height > max_dimension || width * height > limits.max_pixels {
+        return Err(Rejected::TooLarge { width, height });
+    }
+
+    Ok(())
+}
+
+/// How many times smaller a photo of the given size, after orientation, can
+/// be decoded while still being at least as large as each rendition in the
+/// policy, as well as the analysis image. JPEGs can be scaled by 1/2, 1/4 and
+/// 1/8 while decoding
+///
+/// Photos that get a tile pyramid are always decoded at full size.
+fn scale_denominator(width: u32, height: u32, policy: &RenditionPolicy) -> u32 {
+    if policy.deep_zoom(width, height).is_some() {
+        return 1;
+    }
+
+    let thumb = policy.thumb(width, height);
+    let mut sizes: Vec<(u32, u32)> = policy
+        .targets(width, height)
+        .iter()
+        .map(|x| (x.width, x.height))
+        .collect();
+    sizes.push((thumb.width, thumb.height));
+    sizes.push((ANALYSIS_WIDTH.min(width), 1));
+
+    let fits = |d: u32| {
+        let (w, h) = ((width + d - 1) / d, (height + d - 1) / d);
+        sizes.iter().all(|&(tw, th)| tw <= w && th <= h)
+    };
+
+    [8, 4, 2].iter().cloned().find(|&d| fits(d)).unwrap_or(1)
+}
+
+/// Decode a JPEG as small as the rendition policy allows, see
+/// `scale_denominator`. The scaling is done by a smaller inverse DCT, which
+/// is much faster than decoding at full size and downscaling afterwards
+///
+/// Returns the image, the denominator of the scale and the size of the JPEG
+/// at full scale, or `None` if the JPEG should be decoded at full size. This
+/// includes CMYK JPEGs, which are left to the image crate.
+fn decode_jpeg_scaled(
+    data: &[u8],
+    orientation: u32,
+    policy: &RenditionPolicy,
+) -> Result<Option<(RgbImage, u32, (u32, u32))>, jpeg_decoder::Error> {
+    use jpeg_decoder::PixelFormat;
+
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    decoder.read_info()?;
+    let info = decoder.info().expect("The info has been read");
+    let (width, height) = (info.width as u32, info.height as u32);
+
+    let denominator = if orientation & 0b100 != 0 {
+        scale_denominator(height, width, policy)
+    } else {
+        scale_denominator(width, height, policy)
+    };
+    if denominator == 1 || info.pixel_format == PixelFormat::CMYK32 {
+        return Ok(None);
+    }
+
+    let expected = (
+        (width + denominator - 1) / denominator,
+        (height + denominator - 1) / denominator,
+    );
+    let (swidth, sheight) = decoder.scale(expected.0 as u16, expected.1 as u16)?;
+    if (swidth as u32, sheight as u32) != expected {
+        return Ok(None);
+    }
+
+    let pixels = decoder.decode()?;
+    let pixels = match info.pixel_format {
+        PixelFormat::L8 => pixels
+            .into_iter()
+            .flat_map(|x| std::iter::repeat(x).take(3))
+            .collect(),
+        _ => pixels,
+    };
+
+    let img = RgbImage::from_raw(expected.0, expected.1, pixels)
+        .ok_or_else(|| jpeg_decoder::Error::Format("Unexpected amount of pixel data".into()))?;
+
+    Ok(Some((img, denominator, (width, height))))
+}
+
+/// A decoded photo, in linear light
+struct Decoded {
+    img: RgbImageF32,
+
+    /// The size of the photo, after orientation. The image may have been
+    /// scaled down while decoding, see `decode_jpeg_scaled`, so this can be
+    /// larger than `img`
+    width: u32,
+    height: u32,
+}
+
+/// Decode a photo and convert it to linear light. If a rendition policy is
+/// given, JPEGs are scaled down while decoding as far as it allows
+fn decode(
+    data: &[u8],
+    format: image::ImageFormat,
+    policy: Option<&RenditionPolicy>,
+    progress: &Progress,
+) -> Result<Decoded, Box<dyn std::error::Error>> {
+    progress(Stage::Decoding);
+
+    // Only JPEG is expected to carry EXIF data with orientation
+    let orientation = if format == image::ImageFormat::JPEG {
+        let orientation = exif::Reader::new(&mut std::io::Cursor::new(data))
+            .ok()
+            .as_ref()
+            .and_then(|reader| reader.get_field(exif::Tag::Orientation, false))
+            .and_then(|x| x.value.get_uint(0))
+            .unwrap_or(1)
+            - 1;
+        eprintln!("ORG: Orientation: {:?}", orientation);
+        orientation
+    } else {
+        0
+    };
+
     let sw = Stopwatch::start_new();
-    let img = image::load_from_memory_with_format(jpeg, image::ImageFormat::JPEG)?.to_rgb();
+    let scaled = match policy {
+        Some(policy) if format == image::ImageFormat::JPEG => {
+            decode_jpeg_scaled(data, orientation, policy)?
+        }
+        _ => None,
+    };
+    let (img, scale, (width, height)) = match scaled {
+        Some((img, scale, size)) => (DynamicImage::ImageRgb8(img), scale, size),
+        None => {
+            let img = image::load_from_memory_with_format(data, format)?;
+            let size = img.dimensions();
+            (img, 1, size)
+        }
+    };
+    let (width, height) = if orientation & 0b100 != 0 {
+        (height, width)
+    } else {
+        (width, height)
+    };
     eprintln!(
-        "ORG: Decoded original jpeg {}x{} in {}ms",
+        "ORG: Decoded original {:?} at 1/{} scale to {}x{} in {}ms",
+        format,
+        scale,
         img.width(),
         img.height(),
         sw.elapsed_ms()
     );
 
-    let orientation = exif::Reader::new(&mut std::io::Cursor::new(jpeg))
-        .ok()
-        .as_ref()
-        .and_then(|reader| reader.get_field(exif::Tag::Orientation, false))
-        .and_then(|x| x.value.get_uint(0))
-        .unwrap_or(1)
-        - 1;
-    eprintln!("ORG: Orientation: {:?}", orientation);
+    let has_alpha = match img {
+        DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageRgba8(_)
+        | DynamicImage::ImageBgra8(_) => true,
+        _ => false,
+    };
+
+    progress(Stage::Linearizing);
 
-    let img = transform_by_orientation(img, orientation);
+    if has_alpha {
+        let sw = Stopwatch::start_new();
+        let img = image_srgba_to_linear_composited(img.to_rgba());
+        eprintln!(
+            "ORG: Converted original to linear color space and removed alpha in {}ms",
+            sw.elapsed_ms()
+        );
+
+        return Ok(Decoded { img, width, height });
+    }
+
+    let img = transform_by_orientation(img.to_rgb(), orientation);
+
+    let icc = if format == image::ImageFormat::JPEG {
+        icc_profile::from_jpeg(data)
+    } else {
+        None
+    };
 
     let sw = Stopwatch::start_new();
-    let img = image_srgb_to_linear(img);
-    eprintln!(
-        "ORG: Converted original to linear color space in {}ms",
-        sw.elapsed_ms()
-    );
+    let img = match icc {
+        Some(icc) => match image_icc_to_linear(&img, &icc) {
+            Ok(linear) => {
+                eprintln!(
+                    "ORG: Converted original from embedded ICC profile to linear color space in {}ms",
+                    sw.elapsed_ms()
+                );
+                linear
+            }
+            Err(err) => {
+                eprintln!("ORG: Ignoring unusable ICC profile: {}", err);
+                image_srgb_to_linear(img)
+            }
+        },
+        None => {
+            let linear = image_srgb_to_linear(img);
+            eprintln!(
+                "ORG: Converted original to linear color space in {}ms",
+                sw.elapsed_ms()
+            );
+            linear
+        }
+    };
+
+    Ok(Decoded { img, width, height })
+}
+
+fn downscale(img: &RgbImageF32, nwidth: u32, nheight: u32) -> RgbImageF32 {
+    if nwidth >= img.width() {
+        return img.clone();
+    }
+
+    lanczos3_resize(img, nwidth, nheight)
+}
 
-    // TODO Consider: Always store the original, to be able to render new sizes?
-    // Also: To be able to order photo prints based on collections in pixu.rs?
+/// Each size is downscaled from the smallest of the larger sizes that is at
+/// least this many times wider, rather than from the full photo. This is
+/// much faster, and the softness of the intermediate image does not survive
+/// being downscaled by this much
+const CASCADE_FACTOR: u32 = 2;
+
+/// Downscale the image to each of the given sizes, which are labelled for
+/// logging. The largest sizes are done first, so they can be used for the
+/// smaller ones, see `CASCADE_FACTOR`
+fn downscale_all(img: &RgbImageF32, sizes: &[(&str, u32, u32)]) -> Vec<RgbImageF32> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+    let mut scaled: Vec<Option<RgbImageF32>> = sizes.iter().map(|_| None).collect();
+    for i in order {
+        let (label, nwidth, nheight) = sizes[i];
+        let source = scaled
+            .iter()
+            .filter_map(|x| x.as_ref())
+            .filter(|x| x.width() >= nwidth * CASCADE_FACTOR)
+            .min_by_key(|x| x.width())
+            .unwrap_or(img);
 
-    let large = if img.width() > 2560 {
         let sw = Stopwatch::start_new();
-        let nwidth = 2560;
-        let nheight = nwidth * img.height() / img.width();
-        let img = image::imageops::resize(&img, nwidth, nheight, image::imageops::Lanczos3);
+        let result = downscale(source, nwidth, nheight);
         eprintln!(
-            "LRG: Downscaled to {}x{} in {}ms",
+            "{:>4}: Downscaled from {}x{} to {}x{} in {}ms",
+            label,
+            source.width(),
+            source.height(),
             nwidth,
             nheight,
             sw.elapsed_ms()
         );
-        img
-    } else {
-        img
-    };
 
-    let (large_jpeg, r2) = rayon::join(
-        || -> Result<Vec<u8>, std::io::Error> {
+        scaled[i] = Some(result);
+    }
+
+    scaled.into_iter().map(|x| x.unwrap()).collect()
+}
+
+/// The width of the image that the average color, perceptual hash and
+/// placeholder are computed from. It is independent of the thumbnail in the
+/// rendition policy, so perceptual hashes stay comparable
+const ANALYSIS_WIDTH: u32 = 160;
+
+/// A rendition encoded in one of its formats
+struct Encoding {
+    media_type: &'static str,
+    data: Vec<u8>,
+}
+
+/// The encodings of a rendition. The primary encoding is stored in `images`
+/// or `thumbs` and served to clients that accept none of the alternates
+struct Encodings {
+    primary: Encoding,
+    alternates: Vec<Encoding>,
+}
+
+struct EncodedImage {
+    /// The name of the rendition in the policy
+    name: String,
+    role: Role,
+    encodings: Encodings,
+    width: u32,
+    height: u32,
+}
+
+/// The sharpening of a rendition of the given width, of a photo of the
+/// given width, see `Rendition::sharpening`. A rendition at full size has not
+/// been softened by downscaling, and sharpening it would only add halos
+fn sharpening(rendition: &Rendition, width: u32, full_width: u32) -> Option<UnsharpMask> {
+    Some(rendition.sharpening()).filter(|x| width < full_width && x.amount != 0.)
+}
+
+struct Renditions {
+    /// The display and download renditions
+    images: Vec<EncodedImage>,
+    thumb: Encodings,
+
+    /// For photos that fit the deep zoom policy, see `render_tiles`
+    tiles: Option<Tiles>,
+
+    average_color: Rgb<u8>,
+    aspect_ratio: f32,
+    perceptual_hash: u64,
+    blurhash: String,
+    palette: StoredPalette,
+}
+
+impl Renditions {
+    fn average_color(&self) -> i32 {
+        pack_color(&self.average_color)
+    }
+}
+
+fn pack_color(col: &Rgb<u8>) -> i32 {
+    let ch = col.channels();
+    ((ch[0] as i32) << 16) + ((ch[1] as i32) << 8) + ((ch[2] as i32) << 0)
+}
+
+/// A palette as it is stored in `pixurs`
+struct StoredPalette {
+    /// JSON list of CSS colors, the most common first
+    colors: String,
+    top_color: i32,
+    bottom_color: i32,
+}
+
+impl StoredPalette {
+    fn new(palette: &Palette) -> StoredPalette {
+        let colors: Vec<Rgb<u8>> = palette
+            .colors
+            .iter()
+            .map(|&col| px_linear_to_srgb(&Rgb(col)))
+            .collect();
+        let css: Vec<String> = colors
+            .iter()
+            .map(|col| format!("#{:06x}", pack_color(col)))
+            .collect();
+
+        StoredPalette {
+            colors: serde_json::to_string(&css).expect("Strings are always serializable"),
+            top_color: pack_color(&colors[palette.top]),
+            bottom_color: pack_color(&colors[palette.bottom]),
+        }
+    }
+}
+
+fn encode(
+    img: RgbImageF32,
+    rendition: &Rendition,
+    sharpening: Option<UnsharpMask>,
+    label: &str,
+) -> Result<Encodings, std::io::Error> {
+    let img = match sharpening {
+        Some(sharpening) => {
             let sw = Stopwatch::start_new();
-            let large_srgb = image_linear_to_srgb(large.clone());
-            eprintln!("LRG: Converted to sRGB in {}ms", sw.elapsed_ms());
+            let img = unsharp_mask(img, sharpening);
+            eprintln!("{:>4}: Sharpened in {}ms", label, sw.elapsed_ms());
+            img
+        }
+        None => img,
+    };
 
+    let sw = Stopwatch::start_new();
+    let srgb = image_linear_to_srgb(&img);
+    eprintln!("{:>4}: Converted to sRGB in {}ms", label, sw.elapsed_ms());
+
+    let mut encodings = rendition
+        .formats()
+        .into_par_iter()
+        .map(|format| -> Result<_, std::io::Error> {
             let sw = Stopwatch::start_new();
-            let large_jpeg = encode_jpeg(large_srgb, 80)?;
+            let data = match format {
+                Format::Jpeg => {
+                    encode_jpeg(&srgb, rendition.quality, rendition.chroma_subsampling)?
+                }
+                Format::Webp => encode_webp(&srgb, rendition.quality),
+            };
             eprintln!(
-                "LRG: Encoded as JPEG in {}ms, {}b",
+                "{:>4}: Encoded as {:?} in {}ms, {}b",
+                label,
+                format,
                 sw.elapsed_ms(),
-                large_jpeg.len()
+                data.len()
             );
 
-            Ok(large_jpeg)
+            Ok(Encoding {
+                media_type: format.media_type(),
+                data,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter();
+
+    Ok(Encodings {
+        primary: encodings.next().expect("There is always a primary format"),
+        alternates: encodings.collect(),
+    })
+}
+
+/// Render the renditions in the policy, as well as the average color,
+/// perceptual hash and placeholder. The image may have been scaled down
+/// while decoding, see `Decoded`, so the size of the full photo is given
+/// separately
+fn render(
+    img: RgbImageF32,
+    (width, height): (u32, u32),
+    policy: &RenditionPolicy,
+    progress: &Progress,
+) -> Result<Renditions, std::io::Error> {
+    // The renditions are sized for the full photo. The decoded image is
+    // large enough for all of them
+    let aspect_ratio = width as f32 / height as f32;
+
+    let targets = policy.targets(width, height);
+    let thumb = policy.thumb(width, height);
+
+    let mut sizes: Vec<(&str, u32, u32)> = targets
+        .iter()
+        .map(|x| (x.name, x.width, x.height))
+        .collect();
+    sizes.push((thumb.name, thumb.width, thumb.height));
+    sizes.push((
+        "SML",
+        ANALYSIS_WIDTH,
+        (ANALYSIS_WIDTH * img.height() / img.width()).max(1),
+    ));
+
+    progress(Stage::Downscaling);
+    let mut scaled = downscale_all(&img, &sizes);
+    let small = scaled.pop().expect("The analysis image was downscaled");
+    let thumb_img = scaled.pop().expect("The thumbnail was downscaled");
+
+    progress(Stage::Encoding);
+
+    // A photo that fits the deep zoom policy has been decoded at full size,
+    // see `scale_denominator`
+    let tiles = match policy.deep_zoom(width, height) {
+        Some(deep_zoom) if img.dimensions() == (width, height) => {
+            Some(render_tiles(&img, deep_zoom)?)
+        }
+        _ => None,
+    };
+    drop(img);
+
+    let (images, r2) = rayon::join(
+        || -> Result<Vec<EncodedImage>, std::io::Error> {
+            targets
+                .par_iter()
+                .zip(scaled.into_par_iter())
+                .map(|(target, scaled)| {
+                    let sharpening = sharpening(target.rendition, target.width, width);
+                    let encodings = encode(scaled, target.rendition, sharpening, target.name)?;
+
+                    Ok(EncodedImage {
+                        name: target.name.to_string(),
+                        role: target.rendition.role,
+                        encodings,
+                        width: target.width,
+                        height: target.height,
+                    })
+                })
+                .collect()
         },
         || -> Result<_, std::io::Error> {
-            let sw = Stopwatch::start_new();
-            let nwidth = 160;
-            let nheight = nwidth * large.height() / large.width();
-            let small = image::imageops::resize(&large, nwidth, nheight, image::imageops::Lanczos3);
-            eprintln!(
-                "SML: Downscaled to {}x{} in {}ms",
-                nwidth,
-                nheight,
-                sw.elapsed_ms()
-            );
-
-            let (small_jpeg, col) = rayon::join(
-                || -> Result<_, std::io::Error> {
-                    let sw = Stopwatch::start_new();
-                    let small_srgb = image_linear_to_srgb(small.clone());
-                    let small_jpeg = encode_jpeg(small_srgb, 20)?;
-                    eprintln!(
-                        "SML: Converted and encoded as JPEG in {}ms, {}b",
-                        sw.elapsed_ms(),
-                        small_jpeg.len()
-                    );
-
-                    Ok(small_jpeg)
+            let (thumb, (col, hash, placeholder, palette)) = rayon::join(
+                || {
+                    let sharpening = sharpening(thumb.rendition, thumb.width, width);
+                    encode(thumb_img, thumb.rendition, sharpening, thumb.name)
                 },
                 || {
                     let sw = Stopwatch::start_new();
                     let col = px_linear_to_srgb(&avg_color(&small));
                     eprintln!("AVG: Found average color in {}ms", sw.elapsed_ms());
 
-                    col
+                    let sw = Stopwatch::start_new();
+                    let hash = perceptual_hash::dhash(&small);
+                    eprintln!("HSH: Computed perceptual hash in {}ms", sw.elapsed_ms());
+
+                    let sw = Stopwatch::start_new();
+                    let placeholder = encode_blurhash(&small, aspect_ratio);
+                    eprintln!("BLR: Computed BlurHash in {}ms", sw.elapsed_ms());
+
+                    let sw = Stopwatch::start_new();
+                    let palette = StoredPalette::new(&palette::palette(&small));
+                    eprintln!("PAL: Found dominant colors in {}ms", sw.elapsed_ms());
+
+                    (col, hash, placeholder, palette)
                 },
             );
 
-            Ok((small_jpeg?, col))
+            Ok((thumb?, col, hash, placeholder, palette))
         },
     );
 
-    let large_jpeg = large_jpeg?;
-    let (small_jpeg, col) = r2?;
+    let images = images?;
+    let (thumb, average_color, perceptual_hash, blurhash, palette) = r2?;
+
+    Ok(Renditions {
+        images,
+        thumb,
+        tiles,
+        average_color,
+        aspect_ratio,
+        perceptual_hash,
+        blurhash,
+        palette,
+    })
+}
+
+/// Cut a photo into the tiles of a deep zoom pyramid, see `deep_zoom::Pyramid`.
+/// Each level is downscaled from the one above it, starting from the photo
+/// at full size
+fn render_tiles(img: &RgbImageF32, deep_zoom: &DeepZoom) -> Result<Tiles, std::io::Error> {
+    let pyramid = Pyramid::new(img.width(), img.height(), deep_zoom);
+    let sw = Stopwatch::start_new();
+
+    let mut tiles = vec![];
+
+    // The level above the one being cut, or the photo itself
+    let mut above: Option<RgbImageF32> = None;
+
+    for level in (0..=pyramid.max_level()).rev() {
+        let (width, height) = pyramid.level_size(level);
+        let scaled = if level == pyramid.max_level() {
+            None
+        } else {
+            Some(downscale(above.as_ref().unwrap_or(img), width, height))
+        };
+        let level_img = scaled.as_ref().unwrap_or(img);
+        let srgb = image_linear_to_srgb(level_img);
+
+        let (cols, rows) = pyramid.tile_count(level);
+        let cells: Vec<(u32, u32)> = (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| (col, row)))
+            .collect();
+
+        let encoded = cells
+            .into_par_iter()
+            .map(|(col, row)| -> Result<Tile, std::io::Error> {
+                let (x, y, w, h) = pyramid.tile_rect(level, col, row);
+                let tile = srgb.view(x, y, w, h).to_image();
+                let data = match deep_zoom.format {
+                    Format::Jpeg => encode_jpeg(&tile, deep_zoom.quality, false)?,
+                    Format::Webp => encode_webp(&tile, deep_zoom.quality),
+                };
+
+                Ok(Tile {
+                    level,
+                    col,
+                    row,
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        tiles.extend(encoded);
+
+        if scaled.is_some() {
+            above = scaled;
+        }
+    }
+
+    eprintln!(
+        " DZI: Rendered {} tiles in {} levels in {}ms",
+        tiles.len(),
+        pyramid.max_level() + 1,
+        sw.elapsed_ms()
+    );
+
+    Ok(Tiles {
+        pyramid,
+        media_type: deep_zoom.format.media_type(),
+        tiles,
+    })
+}
+
+/// Encode the placeholder for a photo, from a downscaled version of it
+fn encode_blurhash(small: &RgbImageF32, aspect_ratio: f32) -> String {
+    // The details are lost anyway, and the encoding is O(n) per component
+    let tiny = downscale(small, 32, (32 * small.height() / small.width()).max(1));
+
+    if aspect_ratio >= 1. {
+        blurhash::encode(&tiny, 4, 3)
+    } else {
+        blurhash::encode(&tiny, 3, 4)
+    }
+}
+
+/// Decode and render a photo like `ingest` does, without storing anything,
+/// to measure the time spent in each stage. The stages are run one after
+/// the other, each on the whole rayon pool, and their times are logged as a
+/// summary at the end
+pub fn benchmark(
+    data: &[u8],
+    limits: &Limits,
+    policy: &RenditionPolicy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = image::guess_format(data)?;
+    check_limits(data, format, limits)?;
+
+    let total = Stopwatch::start_new();
+
+    let sw = Stopwatch::start_new();
+    let Decoded { img, width, height } = decode(data, format, Some(policy), &|_| ())?;
+    let decode_ms = sw.elapsed_ms();
+
+    let thumb = policy.thumb(width, height);
+    let mut targets = policy.targets(width, height);
+    targets.push(thumb);
+    let sizes: Vec<(&str, u32, u32)> = targets
+        .iter()
+        .map(|x| (x.name, x.width, x.height))
+        .collect();
+
+    let sw = Stopwatch::start_new();
+    let scaled = downscale_all(&img, &sizes);
+    let resize_ms = sw.elapsed_ms();
+
+    let sw = Stopwatch::start_new();
+    let sharpened: Vec<RgbImageF32> = targets
+        .par_iter()
+        .zip(scaled.into_par_iter())
+        .map(
+            |(target, scaled)| match sharpening(target.rendition, target.width, width) {
+                Some(sharpening) => unsharp_mask(scaled, sharpening),
+                None => scaled,
+            },
+        )
+        .collect();
+    let sharpen_ms = sw.elapsed_ms();
+
+    let sw = Stopwatch::start_new();
+    let encoded = targets
+        .par_iter()
+        .zip(sharpened.into_par_iter())
+        .map(|(target, sharpened)| encode(sharpened, target.rendition, None, target.name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let encode_ms = sw.elapsed_ms();
+
+    let sw = Stopwatch::start_new();
+    let tiles = match policy.deep_zoom(width, height) {
+        Some(deep_zoom) if img.dimensions() == (width, height) => {
+            render_tiles(&img, deep_zoom)?.tiles.len()
+        }
+        _ => 0,
+    };
+    let tiles_ms = sw.elapsed_ms();
+
+    let encodings: usize = encoded.iter().map(|x| 1 + x.alternates.len()).sum();
+
+    eprintln!(
+        "Rendered {}x{} to {} renditions in {} encodings and {} tiles, using {} threads",
+        width,
+        height,
+        targets.len(),
+        encodings,
+        tiles,
+        rayon::current_num_threads()
+    );
+    eprintln!("  Decode   {:>7}ms", decode_ms);
+    eprintln!("  Resize   {:>7}ms", resize_ms);
+    eprintln!("  Sharpen  {:>7}ms", sharpen_ms);
+    eprintln!("  Encode   {:>7}ms", encode_ms);
+    eprintln!("  Tiles    {:>7}ms", tiles_ms);
+    eprintln!("  Total    {:>7}ms", total.elapsed_ms());
+
+    Ok(())
+}
+
+/// The number of thumbnails that are loaded at a time by the backfills
+const BACKFILL_BATCH: usize = 100;
+
+/// Compute the placeholders of the pixurs that were uploaded before they were
+/// introduced, from their thumbnails. Thumbnails that cannot be decoded are
+/// logged and skipped
+///
+/// Returns the number of pixurs that were updated.
+pub fn backfill_blurhashes(
+    db_connection: &SqliteConnection,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let missing: Vec<Id30> = pixurs::table
+        .filter(pixurs::blurhash.is_null())
+        .select(pixurs::id)
+        .load(db_connection)?;
+
+    let mut updated = 0;
+    for batch in missing.chunks(BACKFILL_BATCH) {
+        let thumbs: Vec<(Id30, f32, Vec<u8>)> = pixurs::table
+            .inner_join(thumbs::table)
+            .filter(pixurs::id.eq_any(batch))
+            .select((pixurs::id, pixurs::image_aspect_ratio, thumbs::data))
+            .load(db_connection)?;
+
+        for (pixurs_id, aspect_ratio, thumb) in thumbs {
+            let thumb = match image::load_from_memory_with_format(&thumb, image::ImageFormat::JPEG)
+            {
+                Ok(thumb) => thumb,
+                Err(err) => {
+                    eprintln!("BLR: Skipped {}: {}", pixurs_id, err);
+                    continue;
+                }
+            };
+            let placeholder = encode_blurhash(&image_srgb_to_linear(thumb.to_rgb()), aspect_ratio);
+
+            diesel::update(pixurs::table.filter(pixurs::id.eq(pixurs_id)))
+                .set(pixurs::blurhash.eq(placeholder))
+                .execute(db_connection)?;
+            updated += 1;
+        }
+    }
+
+    Ok(updated)
+}
+
+/// Find the dominant colors of the pixurs that were uploaded before palettes
+/// were introduced, from their thumbnails. Thumbnails that cannot be decoded
+/// are logged and skipped
+///
+/// Returns the number of pixurs that were updated.
+pub fn backfill_palettes(
+    db_connection: &SqliteConnection,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let missing: Vec<Id30> = pixurs::table
+        .filter(pixurs::palette.is_null())
+        .select(pixurs::id)
+        .load(db_connection)?;
+
+    let mut updated = 0;
+    for batch in missing.chunks(BACKFILL_BATCH) {
+        let thumbs: Vec<(Id30, Vec<u8>)> = pixurs::table
+            .inner_join(thumbs::table)
+            .filter(pixurs::id.eq_any(batch))
+            .select((pixurs::id, thumbs::data))
+            .load(db_connection)?;
+
+        for (pixurs_id, thumb) in thumbs {
+            let thumb = match image::load_from_memory(&thumb) {
+                Ok(thumb) => image_srgb_to_linear(thumb.to_rgb()),
+                Err(err) => {
+                    eprintln!("PAL: Skipped {}: {}", pixurs_id, err);
+                    continue;
+                }
+            };
+            let small = downscale(
+                &thumb,
+                ANALYSIS_WIDTH,
+                (ANALYSIS_WIDTH * thumb.height() / thumb.width()).max(1),
+            );
+            let palette = StoredPalette::new(&palette::palette(&small));
+
+            diesel::update(pixurs::table.filter(pixurs::id.eq(pixurs_id)))
+                .set((
+                    pixurs::palette.eq(palette.colors),
+                    pixurs::top_color.eq(palette.top_color),
+                    pixurs::bottom_color.eq(palette.bottom_color),
+                ))
+                .execute(db_connection)?;
+            updated += 1;
+        }
+    }
+
+    Ok(updated)
+}
+
+/// Compute the perceptual hashes of the pixurs that were uploaded before they
+/// were introduced, so they are found as duplicates. The hash is computed
+/// from the largest stored image, which stands in for the original. Images
+/// that cannot be decoded are logged and skipped
+///
+/// Returns the number of pixurs that were updated.
+pub fn backfill_perceptual_hashes(
+    db_connection: &SqliteConnection,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let missing: Vec<Id30> = pixurs::table
+        .filter(pixurs::perceptual_hash.is_null())
+        .select(pixurs::id)
+        .load(db_connection)?;
+
+    let mut updated = 0;
+    for pixurs_id in missing {
+        // Only one image is loaded at a time, as they may be large
+        let (media_type, data) = match largest_image(pixurs_id, db_connection).optional()? {
+            Some(largest) => largest,
+            None => {
+                eprintln!("HSH: Skipped {}: No images", pixurs_id);
+                continue;
+            }
+        };
+        let decoded = image_format(&media_type)
+            .ok_or_else(|| format!("Unsupported media type: {}", media_type))
+            .and_then(|format| {
+                image::load_from_memory_with_format(&data, format).map_err(|e| e.to_string())
+            });
+        let img = match decoded {
+            Ok(img) => image_srgb_to_linear(img.to_rgb()),
+            Err(err) => {
+                eprintln!("HSH: Skipped {}: {}", pixurs_id, err);
+                continue;
+            }
+        };
+        let small = downscale(
+            &img,
+            ANALYSIS_WIDTH,
+            (ANALYSIS_WIDTH * img.height() / img.width()).max(1),
+        );
+        let hash = perceptual_hash::dhash(&small);
+
+        diesel::update(pixurs::table.filter(pixurs::id.eq(pixurs_id)))
+            .set(pixurs::perceptual_hash.eq(hash as i64))
+            .execute(db_connection)?;
+        updated += 1;
+    }
+
+    Ok(updated)
+}
+
+fn insert_image(
+    db_connection: &SqliteConnection,
+    rng: &mut impl rand::Rng,
+    pixurs_id: Id30,
+    image: &EncodedImage,
+) -> Result<Id30, diesel::result::Error> {
+    #[derive(Insertable)]
+    #[table_name = "images"]
+    struct Image<'a> {
+        id: Id30,
+        media_type: &'a str,
+        data: &'a [u8],
+    }
+
+    let images_id = Id30::new_random(rng);
+
+    diesel::insert_into(images::table)
+        .values(&Image {
+            id: images_id,
+            media_type: image.encodings.primary.media_type,
+            data: &image.encodings.primary.data,
+        })
+        .execute(db_connection)?;
+
+    insert_image_encodings(db_connection, images_id, &image.encodings.alternates)?;
+
+    #[derive(Insertable)]
+    #[table_name = "images_meta"]
+    struct ImageMeta<'a> {
+        id: Id30,
+        width: i32,
+        height: i32,
+        pixurs_id: Id30,
+        rendition: &'a str,
+        role: Role,
+    }
+
+    diesel::insert_into(images_meta::table)
+        .values(&ImageMeta {
+            id: images_id,
+            width: image.width as i32,
+            height: image.height as i32,
+            pixurs_id,
+            rendition: &image.name,
+            role: image.role,
+        })
+        .execute(db_connection)?;
+
+    Ok(images_id)
+}
+
+/// Update a stored image in place with a new rendering, keeping its ID
+fn update_image(
+    db_connection: &SqliteConnection,
+    images_id: Id30,
+    image: &EncodedImage,
+) -> Result<(), diesel::result::Error> {
+    diesel::update(images::table.filter(images::id.eq(images_id)))
+        .set((
+            images::media_type.eq(image.encodings.primary.media_type),
+            images::data.eq(&image.encodings.primary.data),
+            images::version.eq(images::version + 1),
+        ))
+        .execute(db_connection)?;
+
+    diesel::delete(image_encodings::table.filter(image_encodings::images_id.eq(images_id)))
+        .execute(db_connection)?;
+    insert_image_encodings(db_connection, images_id, &image.encodings.alternates)?;
+
+    diesel::update(images_meta::table.filter(images_meta::id.eq(images_id)))
+        .set((
+            images_meta::width.eq(image.width as i32),
+            images_meta::height.eq(image.height as i32),
+            images_meta::rendition.eq(&image.name),
+            images_meta::role.eq(image.role),
+        ))
+        .execute(db_connection)?;
+
+    Ok(())
+}
+
+fn delete_image(
+    db_connection: &SqliteConnection,
+    images_id: Id30,
+) -> Result<(), diesel::result::Error> {
+    diesel::delete(image_encodings::table.filter(image_encodings::images_id.eq(images_id)))
+        .execute(db_connection)?;
+    diesel::delete(images_meta::table.filter(images_meta::id.eq(images_id)))
+        .execute(db_connection)?;
+    diesel::delete(images::table.filter(images::id.eq(images_id))).execute(db_connection)?;
+
+    Ok(())
+}
+
+fn insert_image_encodings(
+    db_connection: &SqliteConnection,
+    images_id: Id30,
+    encodings: &[Encoding],
+) -> Result<(), diesel::result::Error> {
+    #[derive(Insertable)]
+    #[table_name = "image_encodings"]
+    struct ImageEncoding<'a> {
+        images_id: Id30,
+        media_type: &'a str,
+        data: &'a [u8],
+    }
+
+    for encoding in encodings {
+        diesel::insert_into(image_encodings::table)
+            .values(&ImageEncoding {
+                images_id,
+                media_type: encoding.media_type,
+                data: &encoding.data,
+            })
+            .execute(db_connection)?;
+    }
+
+    Ok(())
+}
+
+fn insert_thumb_encodings(
+    db_connection: &SqliteConnection,
+    thumbs_id: Id30,
+    encodings: &[Encoding],
+) -> Result<(), diesel::result::Error> {
+    #[derive(Insertable)]
+    #[table_name = "thumb_encodings"]
+    struct ThumbEncoding<'a> {
+        thumbs_id: Id30,
+        media_type: &'a str,
+        data: &'a [u8],
+    }
+
+    for encoding in encodings {
+        diesel::insert_into(thumb_encodings::table)
+            .values(&ThumbEncoding {
+                thumbs_id,
+                media_type: encoding.media_type,
+                data: &encoding.data,
+            })
+            .execute(db_connection)?;
+    }
+
+    Ok(())
+}
+
+pub struct Ingested {
+    pub pixurs_id: Id30,
+    pub pixur_series_id: Id30,
+
+    /// Existing pixurs that are likely the same photo, closest first
+    pub duplicates: Vec<Id30>,
+}
+
+/// Ingest an uploaded image of the given media type, see `image_format`
+/// for the supported types. Progress is reported as the stages are reached
+///
+/// All formats end up as the renditions in the policy. Images beyond the
+/// limits fail with `Rejected` before they are decoded.
+///
+/// `stored` runs in the transaction that stores the pixur, so what it
+/// records is committed together with the pixur or not at all.
+pub fn ingest(
+    data: &[u8],
+    media_type: &str,
+    limits: &Limits,
+    policy: &RenditionPolicy,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    progress: &Progress,
+    stored: impl FnOnce(&SqliteConnection, &Ingested) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<Ingested, Box<dyn std::error::Error>> {
+    let format = image_format(media_type)
+        .ok_or_else(|| format!("Unsupported media type: {}", media_type))?;
+    check_limits(data, format, limits)?;
+    let Decoded { img, width, height } = decode(data, format, Some(policy), progress)?;
+
+    // The original is stored alongside the renditions, so they can be
+    // regenerated later with `rerender`.
+    // TODO Consider: Order photo prints based on collections in pixu.rs?
+
+    let renditions = render(img, (width, height), policy, progress)?;
+
+    let metadata = photo_metadata::from_exif(data);
+
+    // The clip of a motion photo is stored separately, so it can be played
+    // without downloading the original
+    let motion_video = if format == image::ImageFormat::JPEG {
+        motion_photo::from_jpeg(data)
+    } else {
+        None
+    };
+
+    progress(Stage::Storing);
 
     let db_connection = db_pool.get()?;
     db_connection
@@ -295,6 +1428,9 @@ pub fn ingest_jpeg(
 
             let mut rng = SmallRng::from_entropy();
 
+            let duplicates =
+                perceptual_hash::find_duplicates(&*db_connection, renditions.perceptual_hash)?;
+
             #[derive(Insertable)]
             #[table_name = "thumbs"]
             struct Thumb<'a> {
@@ -308,14 +1444,16 @@ pub fn ingest_jpeg(
             diesel::insert_into(thumbs::table)
                 .values(&Thumb {
                     id: thumbs_id,
-                    media_type: "image/jpeg",
-                    data: &small_jpeg,
+                    media_type: renditions.thumb.primary.media_type,
+                    data: &renditions.thumb.primary.data,
                 })
                 .execute(&*db_connection)?;
 
+            insert_thumb_encodings(&*db_connection, thumbs_id, &renditions.thumb.alternates)?;
+
             #[derive(Insertable)]
             #[table_name = "pixurs"]
-            struct Pixur {
+            struct Pixur<'a> {
                 id: Id30,
                 average_color: i32,
                 thumbs_id: Id30,
@@ -326,6 +1464,13 @@ pub fn ingest_jpeg(
                 crop_right: f32,
                 crop_top: f32,
                 crop_bottom: f32,
+
+                taken_at: Option<chrono::NaiveDateTime>,
+                perceptual_hash: i64,
+                blurhash: &'a str,
+                palette: &'a str,
+                top_color: i32,
+                bottom_color: i32,
             }
 
             let pixurs_id = Id30::new_random(&mut rng);
@@ -333,53 +1478,57 @@ pub fn ingest_jpeg(
             diesel::insert_into(pixurs::table)
                 .values(&Pixur {
                     id: pixurs_id,
-                    average_color: ((col.channels()[0] as i32) << 16)
-                        + ((col.channels()[1] as i32) << 8)
-                        + ((col.channels()[2] as i32) << 0),
+                    average_color: renditions.average_color(),
                     thumbs_id,
-                    image_aspect_ratio: large.width() as f32 / large.height() as f32,
+                    image_aspect_ratio: renditions.aspect_ratio,
                     crop_left: 0.5,
                     crop_right: 0.5,
                     crop_top: 0.5,
                     crop_bottom: 0.5,
+                    taken_at: metadata.sort_time(),
+                    perceptual_hash: renditions.perceptual_hash as i64,
+                    blurhash: &renditions.blurhash,
+                    palette: &renditions.palette.colors,
+                    top_color: renditions.palette.top_color,
+                    bottom_color: renditions.palette.bottom_color,
                 })
                 .execute(&*db_connection)?;
 
             #[derive(Insertable)]
-            #[table_name = "images"]
-            struct Image<'a> {
+            #[table_name = "originals"]
+            struct Original<'a> {
                 id: Id30,
+                pixurs_id: Id30,
                 media_type: &'a str,
                 data: &'a [u8],
             }
 
-            let images_id = Id30::new_random(&mut rng);
-
-            diesel::insert_into(images::table)
-                .values(&Image {
-                    id: images_id,
-                    media_type: "image/jpeg",
-                    data: &large_jpeg,
+            diesel::insert_into(originals::table)
+                .values(&Original {
+                    id: Id30::new_random(&mut rng),
+                    pixurs_id,
+                    media_type,
+                    data,
                 })
                 .execute(&*db_connection)?;
 
-            #[derive(Insertable)]
-            #[table_name = "images_meta"]
-            struct ImageMeta {
-                id: Id30,
-                width: i32,
-                height: i32,
-                pixurs_id: Id30,
+            photo_metadata::insert(&*db_connection, pixurs_id, &metadata)?;
+
+            if let Some(video) = motion_video {
+                diesel::insert_into(motion_videos::table)
+                    .values((
+                        motion_videos::pixurs_id.eq(pixurs_id),
+                        motion_videos::media_type.eq(motion_photo::MEDIA_TYPE),
+                        motion_videos::data.eq(video),
+                    ))
+                    .execute(&*db_connection)?;
             }
 
-            diesel::insert_into(images_meta::table)
-                .values(&ImageMeta {
-                    id: images_id,
-                    width: large.width() as i32,
-                    height: large.height() as i32,
-                    pixurs_id,
-                })
-                .execute(&*db_connection)?;
+            for image in &renditions.images {
+                insert_image(&*db_connection, &mut rng, pixurs_id, image)?;
+            }
+
+            deep_zoom::store(&*db_connection, pixurs_id, renditions.tiles.as_ref())?;
 
             #[derive(Insertable)]
             #[table_name = "pixur_series"]
@@ -399,7 +1548,726 @@ pub fn ingest_jpeg(
                 })
                 .execute(&*db_connection)?;
 
-            Ok((pixurs_id, pixur_series_id))
+            let ingested = Ingested {
+                pixurs_id,
+                pixur_series_id,
+                duplicates,
+            };
+
+            stored(&*db_connection, &ingested)?;
+
+            Ok(ingested)
         })
         .map_err(|x| dbg!(x))
 }
+
+/// Pixurs uploaded before originals were kept only have their renditions.
+/// The largest of these is kept as the original from then on, so every
+/// rerender starts from the same pixels, and edits are not applied twice.
+/// See `store_rerendered`
+fn largest_image(
+    pixurs_id: Id30,
+    db_connection: &SqliteConnection,
+) -> Result<(String, Vec<u8>), diesel::result::Error> {
+    images_meta::table
+        .inner_join(images::table)
+        .filter(images_meta::pixurs_id.eq(pixurs_id))
+        .order(images_meta::width.desc())
+        .select((images::media_type, images::data))
+        .first(db_connection)
+}
+
+/// The policy to scale JPEGs down by while decoding a photo with the given
+/// edits, see `decode_jpeg_scaled`
+///
+/// The scale is chosen from the size of the original, so it cannot be used
+/// with edits that change the size. A quarter turn swaps the width and the
+/// height, and straightening crops the photo, so the renditions of what is
+/// left need more of the resolution of the original.
+fn decode_scaling<'a>(edits: &[Edit], policy: &'a RenditionPolicy) -> Option<&'a RenditionPolicy> {
+    let changes_size = |edit: &Edit| match *edit {
+        Edit::Rotate { quarter_turns } => quarter_turns % 2 == 1,
+        Edit::Straighten { .. } => true,
+        _ => false,
+    };
+
+    if edits.iter().any(changes_size) {
+        None
+    } else {
+        Some(policy)
+    }
+}
+
+/// A pixur rendered anew by `render_pixur`, to be stored by
+/// `store_rerendered`
+pub struct Rerendered {
+    pixurs_id: Id30,
+
+    /// The largest image, when it is to be adopted as the original
+    adopted: Option<(String, Vec<u8>)>,
+
+    /// The edits that were applied, to tell if they changed before storing
+    edits: Vec<Edit>,
+
+    renditions: Renditions,
+}
+
+/// Render the renditions of an existing pixur again from its stored
+/// original, with its edits applied, see `image_edit`. Nothing is written to
+/// the database
+pub fn render_pixur(
+    pixurs_id: Id30,
+    policy: &RenditionPolicy,
+    db_connection: &SqliteConnection,
+) -> Result<Rerendered, Box<dyn std::error::Error>> {
+    let edits = image_edit::load(db_connection, pixurs_id)?;
+
+    render_edited(pixurs_id, edits, policy, db_connection)
+}
+
+/// Like `render_pixur`, but with the given edits rather than the stored ones
+fn render_edited(
+    pixurs_id: Id30,
+    edits: Vec<Edit>,
+    policy: &RenditionPolicy,
+    db_connection: &SqliteConnection,
+) -> Result<Rerendered, Box<dyn std::error::Error>> {
+    let original: Option<(String, Vec<u8>)> = originals::table
+        .filter(originals::pixurs_id.eq(pixurs_id))
+        .select((originals::media_type, originals::data))
+        .first(db_connection)
+        .optional()?;
+
+    let is_adopted = original.is_none();
+    let (media_type, original) = match original {
+        Some(original) => original,
+        None => largest_image(pixurs_id, db_connection)?,
+    };
+
+    let format = image_format(&media_type)
+        .ok_or_else(|| format!("Unsupported media type for original: {}", media_type))?;
+
+    let scaling = decode_scaling(&edits, policy);
+    let Decoded { img, width, height } = decode(&original, format, scaling, &|_| ())?;
+
+    let sw = Stopwatch::start_new();
+    let img = image_edit::apply(img, &edits);
+    eprintln!(
+        "ORG: Applied {} edits in {}ms",
+        edits.len(),
+        sw.elapsed_ms()
+    );
+
+    // Edits that change the size rule out a scaled decode, so the edited
+    // image is then at full size
+    let size = match scaling {
+        Some(_) => (width, height),
+        None => img.dimensions(),
+    };
+    let renditions = render(img, size, policy, &|_| ())?;
+
+    Ok(Rerendered {
+        pixurs_id,
+        adopted: if is_adopted {
+            Some((media_type, original))
+        } else {
+            None
+        },
+        edits,
+        renditions,
+    })
+}
+
+/// Replace the renditions of a pixur with those from `render_pixur`. This
+/// must run in an immediate transaction, as the renditions to replace are
+/// read here, and must not change before they are replaced
+///
+/// Fails if the pixur was edited, or got an original, after `render_pixur`
+/// read it. The renditions would then be stale, and would replace those of
+/// a later rerender.
+///
+/// The renditions that are still in the policy are updated in place, so all
+/// URLs pointing to them stay valid. Their versions are bumped, so cached
+/// copies are revalidated rather than kept. Images from before the policy was
+/// introduced are matched to display renditions by width. Renditions that
+/// are new to the policy are added, and images of renditions that are no
+/// longer in the policy are deleted. Likewise, the tile pyramid is replaced,
+/// or deleted if the photo no longer fits the deep zoom policy.
+pub fn store_rerendered(
+    db_connection: &SqliteConnection,
+    rendered: &Rerendered,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    let mut rng = SmallRng::from_entropy();
+    let Rerendered {
+        pixurs_id,
+        adopted,
+        edits,
+        renditions,
+    } = rendered;
+    let pixurs_id = *pixurs_id;
+
+    let has_original: bool = diesel::select(diesel::dsl::exists(
+        originals::table.filter(originals::pixurs_id.eq(pixurs_id)),
+    ))
+    .get_result(db_connection)?;
+    if adopted.is_some() && has_original {
+        return Err("The pixur was rerendered while rendering".into());
+    }
+    if image_edit::load(db_connection, pixurs_id)? != *edits {
+        return Err("The pixur was edited while rendering".into());
+    }
+
+    let existing: Vec<(Id30, i32, Option<String>)> = images_meta::table
+        .filter(images_meta::pixurs_id.eq(pixurs_id))
+        .select((images_meta::id, images_meta::width, images_meta::rendition))
+        .load(db_connection)?;
+
+    if let Some((media_type, data)) = adopted {
+        diesel::insert_into(originals::table)
+            .values((
+                originals::id.eq(Id30::new_random(&mut rng)),
+                originals::pixurs_id.eq(pixurs_id),
+                originals::media_type.eq(media_type),
+                originals::data.eq(data),
+                originals::adopted.eq(true),
+            ))
+            .execute(db_connection)?;
+    }
+
+    let thumbs_id: Id30 = pixurs::table
+        .filter(pixurs::id.eq(pixurs_id))
+        .select(pixurs::thumbs_id)
+        .first(db_connection)?;
+
+    diesel::update(thumbs::table.filter(thumbs::id.eq(thumbs_id)))
+        .set((
+            thumbs::media_type.eq(renditions.thumb.primary.media_type),
+            thumbs::data.eq(&renditions.thumb.primary.data),
+            thumbs::version.eq(thumbs::version + 1),
+        ))
+        .execute(db_connection)?;
+
+    diesel::delete(thumb_encodings::table.filter(thumb_encodings::thumbs_id.eq(thumbs_id)))
+        .execute(db_connection)?;
+    insert_thumb_encodings(db_connection, thumbs_id, &renditions.thumb.alternates)?;
+
+    diesel::update(pixurs::table.filter(pixurs::id.eq(pixurs_id)))
+        .set((
+            pixurs::average_color.eq(renditions.average_color()),
+            pixurs::image_aspect_ratio.eq(renditions.aspect_ratio),
+            pixurs::perceptual_hash.eq(renditions.perceptual_hash as i64),
+            pixurs::blurhash.eq(&renditions.blurhash),
+            pixurs::palette.eq(&renditions.palette.colors),
+            pixurs::top_color.eq(renditions.palette.top_color),
+            pixurs::bottom_color.eq(renditions.palette.bottom_color),
+        ))
+        .execute(db_connection)?;
+
+    let mut kept: Vec<Id30> = vec![];
+
+    for image in &renditions.images {
+        let by_name = existing
+            .iter()
+            .find(|(_, _, name)| name.as_deref() == Some(image.name.as_str()));
+        let by_width = || {
+            existing.iter().find(|&&(id, width, ref name)| {
+                name.is_none()
+                    && image.role == Role::Display
+                    && width as u32 == image.width
+                    && !kept.contains(&id)
+            })
+        };
+
+        match by_name.or_else(by_width) {
+            Some(&(images_id, _, _)) => {
+                update_image(db_connection, images_id, image)?;
+                kept.push(images_id);
+            }
+            None => {
+                kept.push(insert_image(db_connection, &mut rng, pixurs_id, image)?);
+            }
+        }
+    }
+
+    for &(images_id, _, _) in &existing {
+        if !kept.contains(&images_id) {
+            delete_image(db_connection, images_id)?;
+        }
+    }
+
+    deep_zoom::store(db_connection, pixurs_id, renditions.tiles.as_ref())?;
+
+    Ok(())
+}
+
+/// Regenerate the renditions of an existing pixur, see `render_pixur` and
+/// `store_rerendered`
+pub fn rerender(
+    pixurs_id: Id30,
+    policy: &RenditionPolicy,
+    db_connection: &SqliteConnection,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rendered = render_pixur(pixurs_id, policy, db_connection)?;
+
+    // Immediate, so the transaction waits for other writers up front rather
+    // than failing when it goes from reading to writing
+    db_connection.immediate_transaction(|| store_rerendered(db_connection, &rendered))?;
+
+    Ok(())
+}
+
+/// Replace the edits of a pixur, and rerender it with them. The renditions
+/// are rendered first, so the new edits and their renditions are stored
+/// together or not at all
+pub fn edit(
+    pixurs_id: Id30,
+    edits: Vec<Edit>,
+    policy: &RenditionPolicy,
+    db_connection: &SqliteConnection,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rendered = render_edited(pixurs_id, edits, policy, db_connection)?;
+
+    db_connection.immediate_transaction(|| -> Result<(), Box<dyn std::error::Error>> {
+        image_edit::store(db_connection, pixurs_id, &rendered.edits)?;
+        store_rerendered(db_connection, &rendered)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transparency_is_composited_onto_average_color() {
+        let img =
+            RgbaImage::from_raw(3, 1, vec![255, 0, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0]).unwrap();
+        let img = image_srgba_to_linear_composited(img);
+
+        assert_eq!(img.get_pixel(0, 0).channels(), &[1., 0., 0.]);
+        assert_eq!(img.get_pixel(1, 0).channels(), &[0., 0., 1.]);
+        assert_eq!(img.get_pixel(2, 0).channels(), &[0.5, 0., 0.5]);
+    }
+
+    #[test]
+    fn srgb_icc_profile_matches_builtin_conversion() {
+        let icc = lcms2::Profile::new_srgb().icc().unwrap();
+        let img = RgbImage::from_raw(3, 1, vec![255, 0, 0, 0, 128, 0, 20, 200, 255]).unwrap();
+
+        let expected = image_srgb_to_linear(img.clone());
+        let actual = image_icc_to_linear(&img, &icc).unwrap();
+
+        for (a, b) in actual.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 0.005, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn limits_are_checked_from_header() {
+        let mut png = vec![];
+        image::png::PNGEncoder::new(&mut png)
+            .encode(&[0; 20 * 10 * 3], 20, 10, image::ColorType::RGB(8))
+            .unwrap();
+
+        let limits = |max_dimension, max_pixels| Limits {
+            max_dimension,
+            max_pixels,
+            ..Default::default()
+        };
+        let check = |limits| check_limits(&png, image::ImageFormat::PNG, &limits);
+
+        assert!(check(limits(20, 200)).is_ok());
+        assert!(matches!(
+            check(limits(16, 200)),
+            Err(Rejected::TooLarge {
+                width: 20,
+                height: 10
+            })
+        ));
+        assert!(check(limits(20, 199)).is_err());
+
+        assert!(matches!(
+            check_limits(&png[..8], image::ImageFormat::PNG, &Limits::default()),
+            Err(Rejected::UnreadableHeader)
+        ));
+    }
+
+    fn small_policy() -> RenditionPolicy {
+        toml::from_str(
+            r#"
+            [thumb]
+            role = "thumb"
+            max_width = 160
+            quality = 20
+
+            [small]
+            role = "display"
+            max_width = 320
+            quality = 80
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn scale_denominator_keeps_renditions_sharp() {
+        let policy = RenditionPolicy::default();
+        assert_eq!(scale_denominator(8000, 6000, &policy), 2);
+        assert_eq!(scale_denominator(4032, 3024, &policy), 1);
+        assert_eq!(scale_denominator(1000, 750, &policy), 1);
+
+        let policy = small_policy();
+        assert_eq!(scale_denominator(4000, 3000, &policy), 8);
+        assert_eq!(scale_denominator(1280, 960, &policy), 4);
+        assert_eq!(scale_denominator(1276, 960, &policy), 2);
+
+        // Portrait photos are limited by the same maximum width
+        assert_eq!(scale_denominator(960, 1280, &policy), 2);
+
+        // Tile pyramids are made from the full size photo
+        let deep_zoom = toml::from_str("quality = 80").unwrap();
+        let policy = small_policy().with_deep_zoom(Some(deep_zoom));
+        assert_eq!(scale_denominator(4000, 3000, &policy), 8);
+        assert_eq!(scale_denominator(6000, 1500, &policy), 1);
+    }
+
+    #[test]
+    fn only_downscaled_renditions_are_sharpened() {
+        let policy = small_policy();
+        let thumb = policy.thumb(1280, 960);
+
+        assert_eq!(
+            sharpening(thumb.rendition, 160, 1280),
+            Some(thumb.rendition.sharpening())
+        );
+        assert_eq!(sharpening(thumb.rendition, 160, 160), None);
+
+        let mut rendition = thumb.rendition.clone();
+        rendition.sharpening = Some(UnsharpMask {
+            radius: 1.,
+            amount: 0.,
+        });
+        assert_eq!(sharpening(&rendition, 160, 1280), None);
+    }
+
+    fn insert_pixur_with_thumb(conn: &SqliteConnection, id: u32, thumb: &[u8]) -> Id30 {
+        let id = Id30::from(id);
+
+        diesel::insert_into(thumbs::table)
+            .values((
+                thumbs::id.eq(id),
+                thumbs::media_type.eq("image/jpeg"),
+                thumbs::data.eq(thumb),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        diesel::insert_into(pixurs::table)
+            .values((
+                pixurs::id.eq(id),
+                pixurs::average_color.eq(0),
+                pixurs::thumbs_id.eq(id),
+                pixurs::image_aspect_ratio.eq(1.),
+                pixurs::crop_left.eq(0.5),
+                pixurs::crop_right.eq(0.5),
+                pixurs::crop_top.eq(0.5),
+                pixurs::crop_bottom.eq(0.5),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        id
+    }
+
+    #[test]
+    fn backfills_skip_broken_thumbnails() {
+        let conn = crate::db::test::test_connection();
+
+        let mut jpeg = vec![];
+        image::jpeg::JPEGEncoder::new(&mut jpeg)
+            .encode(&[200; 16 * 16 * 3], 16, 16, image::ColorType::RGB(8))
+            .unwrap();
+        let good = insert_pixur_with_thumb(&conn, 1, &jpeg);
+        let broken = insert_pixur_with_thumb(&conn, 2, b"not a jpeg");
+
+        assert_eq!(backfill_blurhashes(&conn).unwrap(), 1);
+        assert_eq!(backfill_palettes(&conn).unwrap(), 1);
+
+        let filled = |id: Id30| -> (bool, bool) {
+            let (blurhash, palette): (Option<String>, Option<String>) = pixurs::table
+                .filter(pixurs::id.eq(id))
+                .select((pixurs::blurhash, pixurs::palette))
+                .first(&conn)
+                .unwrap();
+            (blurhash.is_some(), palette.is_some())
+        };
+        assert_eq!(filled(good), (true, true));
+        assert_eq!(filled(broken), (false, false));
+    }
+
+    #[test]
+    fn metadata_is_not_backfilled_from_adopted_originals() {
+        let conn = crate::db::test::test_connection();
+
+        for &(id, adopted) in &[(1, false), (2, true)] {
+            let pixurs_id = insert_pixur_with_thumb(&conn, id, b"");
+            diesel::insert_into(originals::table)
+                .values((
+                    originals::id.eq(pixurs_id),
+                    originals::pixurs_id.eq(pixurs_id),
+                    originals::media_type.eq("image/jpeg"),
+                    originals::data.eq(&b"not a jpeg"[..]),
+                    originals::adopted.eq(adopted),
+                ))
+                .execute(&conn)
+                .unwrap();
+        }
+
+        assert_eq!(photo_metadata::backfill(&conn).unwrap(), 1);
+        assert_eq!(photo_metadata::backfill(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn perceptual_hashes_are_backfilled_from_the_largest_image() {
+        let conn = crate::db::test::test_connection();
+        let mut rng = rand::thread_rng();
+
+        let mut jpeg = vec![];
+        image::jpeg::JPEGEncoder::new(&mut jpeg)
+            .encode(&[200; 32 * 16 * 3], 32, 16, image::ColorType::RGB(8))
+            .unwrap();
+        let encoded = |width, data: &[u8]| EncodedImage {
+            name: "small".to_string(),
+            role: Role::Display,
+            encodings: Encodings {
+                primary: Encoding {
+                    media_type: "image/jpeg",
+                    data: data.to_vec(),
+                },
+                alternates: vec![],
+            },
+            width,
+            height: width / 2,
+        };
+
+        // The broken image is smaller, so it is not the one that is decoded
+        let good = insert_pixur_with_thumb(&conn, 1, b"");
+        insert_image(&conn, &mut rng, good, &encoded(16, b"not a jpeg")).unwrap();
+        insert_image(&conn, &mut rng, good, &encoded(32, &jpeg)).unwrap();
+        let broken = insert_pixur_with_thumb(&conn, 2, b"");
+        insert_image(&conn, &mut rng, broken, &encoded(32, b"not a jpeg")).unwrap();
+        let empty = insert_pixur_with_thumb(&conn, 3, b"");
+
+        assert_eq!(backfill_perceptual_hashes(&conn).unwrap(), 1);
+
+        let filled = |id: Id30| -> bool {
+            let hash: Option<i64> = pixurs::table
+                .filter(pixurs::id.eq(id))
+                .select(pixurs::perceptual_hash)
+                .first(&conn)
+                .unwrap();
+            hash.is_some()
+        };
+        assert!(filled(good));
+        assert!(!filled(broken));
+        assert!(!filled(empty));
+    }
+
+    #[test]
+    fn tiles_cover_every_level() {
+        let deep_zoom: DeepZoom =
+            toml::from_str("tile_size = 16\nformat = \"webp\"\nquality = 80").unwrap();
+        let img = RgbImageF32::from_pixel(40, 10, Rgb([0.5, 0.5, 0.5]));
+
+        let tiles = render_tiles(&img, &deep_zoom).unwrap();
+        assert_eq!(tiles.pyramid.max_level(), 6);
+        assert_eq!(tiles.media_type, "image/webp");
+
+        // 3 tiles at full size, 2 at half size and 1 for each of the rest
+        assert_eq!(tiles.tiles.len(), 3 + 2 + 5);
+
+        let last = tiles
+            .tiles
+            .iter()
+            .find(|x| (x.level, x.col, x.row) == (6, 2, 0))
+            .unwrap();
+        let decoded = image::load_from_memory(&last.data).unwrap();
+        assert_eq!(decoded.dimensions(), (9, 10));
+    }
+
+    #[test]
+    fn jpeg_is_scaled_while_decoding() {
+        let mut jpeg = vec![];
+        image::jpeg::JPEGEncoder::new(&mut jpeg)
+            .encode(&[200; 1280 * 960 * 3], 1280, 960, image::ColorType::RGB(8))
+            .unwrap();
+
+        let (img, scale, size) = decode_jpeg_scaled(&jpeg, 0, &small_policy())
+            .unwrap()
+            .unwrap();
+        assert_eq!(scale, 4);
+        assert_eq!(size, (1280, 960));
+        assert_eq!(img.dimensions(), (320, 240));
+        assert!(img.iter().all(|&c| (c as i32 - 200).abs() <= 2));
+
+        // Rotated by a quarter turn, so the maximum width applies to the height
+        let (img, scale, _) = decode_jpeg_scaled(&jpeg, 5, &small_policy())
+            .unwrap()
+            .unwrap();
+        assert_eq!(scale, 2);
+        assert_eq!(img.dimensions(), (640, 480));
+    }
+
+    #[test]
+    fn rotated_photo_is_decoded_at_full_size() {
+        let mut policy = small_policy();
+        let quarter_turn = [Edit::Rotate { quarter_turns: 1 }];
+        assert!(decode_scaling(&quarter_turn, &policy).is_none());
+        assert!(decode_scaling(&[Edit::Rotate { quarter_turns: 2 }], &policy).is_some());
+        assert!(decode_scaling(&[Edit::FlipHorizontal], &policy).is_some());
+        assert!(decode_scaling(&[Edit::Straighten { degrees: 2. }], &policy).is_none());
+
+        policy = toml::from_str(
+            r#"
+            [thumb]
+            role = "thumb"
+            max_width = 160
+            format = "webp"
+            quality = 20
+
+            [small]
+            role = "display"
+            max_width = 320
+            format = "webp"
+            quality = 80
+            "#,
+        )
+        .unwrap();
+
+        let mut jpeg = vec![];
+        image::jpeg::JPEGEncoder::new(&mut jpeg)
+            .encode(&[200; 1280 * 960 * 3], 1280, 960, image::ColorType::RGB(8))
+            .unwrap();
+
+        let scaling = decode_scaling(&quarter_turn, &policy);
+        let decoded = decode(&jpeg, image::ImageFormat::JPEG, scaling, &|_| ()).unwrap();
+        let img = image_edit::apply(decoded.img, &quarter_turn);
+        let size = img.dimensions();
+        assert_eq!(size, (960, 1280));
+
+        let renditions = render(img, size, &policy, &|_| ()).unwrap();
+        let small = &renditions.images[0];
+        let encoded = image::load_from_memory(&small.encodings.primary.data).unwrap();
+        assert_eq!((small.width, small.height), (320, 426));
+        assert_eq!(encoded.dimensions(), (small.width, small.height));
+    }
+
+    #[test]
+    fn rerender_replaces_what_is_stored_when_storing() {
+        let conn = crate::db::test::test_connection();
+        let id = insert_pixur_with_thumb(&conn, 1, b"");
+
+        let mut png = vec![];
+        image::png::PNGEncoder::new(&mut png)
+            .encode(&[200; 64 * 48 * 3], 64, 48, image::ColorType::RGB(8))
+            .unwrap();
+        diesel::insert_into(originals::table)
+            .values((
+                originals::id.eq(id),
+                originals::pixurs_id.eq(id),
+                originals::media_type.eq("image/png"),
+                originals::data.eq(&png),
+            ))
+            .execute(&conn)
+            .unwrap();
+
+        let policy: RenditionPolicy = toml::from_str(
+            r#"
+            [thumb]
+            role = "thumb"
+            max_width = 16
+            format = "webp"
+            quality = 20
+
+            [small]
+            role = "display"
+            max_width = 32
+            format = "webp"
+            quality = 80
+            "#,
+        )
+        .unwrap();
+        let images = || -> i64 {
+            images_meta::table
+                .filter(images_meta::pixurs_id.eq(id))
+                .count()
+                .get_result(&conn)
+                .unwrap()
+        };
+
+        // Both replace the same rendition, even though the first to be
+        // stored adds it
+        let first = render_pixur(id, &policy, &conn).unwrap();
+        let second = render_pixur(id, &policy, &conn).unwrap();
+        store_rerendered(&conn, &second).unwrap();
+        store_rerendered(&conn, &first).unwrap();
+        assert_eq!(images(), 1);
+
+        // Cached copies are revalidated against the version
+        let thumb_version: i32 = thumbs::table
+            .inner_join(pixurs::table)
+            .filter(pixurs::id.eq(id))
+            .select(thumbs::version)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(thumb_version, 2);
+
+        let stale = render_pixur(id, &policy, &conn).unwrap();
+        image_edit::store(&conn, id, &[Edit::FlipHorizontal]).unwrap();
+        assert!(store_rerendered(&conn, &stale).is_err());
+    }
+
+    #[test]
+    fn edits_are_not_stored_when_rendering_fails() {
+        let conn = crate::db::test::test_connection();
+
+        // Neither an original nor any images to render from
+        let id = insert_pixur_with_thumb(&conn, 1, b"");
+
+        let policy: RenditionPolicy = toml::from_str(
+            r#"
+            [thumb]
+            role = "thumb"
+            max_width = 16
+            format = "webp"
+            quality = 20
+            "#,
+        )
+        .unwrap();
+
+        assert!(edit(id, vec![Edit::FlipHorizontal], &policy, &conn).is_err());
+        assert_eq!(image_edit::load(&conn, id).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn size_is_kept_through_scaled_decoding() {
+        // Not divisible by the scale, so the decoded image is rounded up
+        let mut jpeg = vec![];
+        image::jpeg::JPEGEncoder::new(&mut jpeg)
+            .encode(&[200; 1283 * 963 * 3], 1283, 963, image::ColorType::RGB(8))
+            .unwrap();
+
+        let decoded = decode(
+            &jpeg,
+            image::ImageFormat::JPEG,
+            Some(&small_policy()),
+            &|_| (),
+        )
+        .unwrap();
+        assert_eq!(decoded.img.dimensions(), (321, 241));
+        assert_eq!((decoded.width, decoded.height), (1283, 963));
+    }
+}
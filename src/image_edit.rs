@@ -0,0 +1,268 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use image::{imageops, ImageBuffer, Rgb};
+use rayon::prelude::*;
+
+use crate::db::schema::*;
+use crate::id30::Id30;
+
+type RgbImageF32 = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+/// Straightening is meant for fixing a slightly tilted horizon. Larger
+/// angles would crop away most of the photo
+pub const MAX_STRAIGHTEN_DEGREES: f32 = 15.;
+
+pub const MAX_EXPOSURE_STOPS: f32 = 5.;
+
+pub const MIN_WHITE_BALANCE_GAIN: f32 = 0.25;
+pub const MAX_WHITE_BALANCE_GAIN: f32 = 4.;
+
+/// An adjustment of a photo, applied on top of the original when rendering
+///
+/// Edits are stored per pixur in `pixur_edits`, as a list in the order they
+/// are applied. The original itself is never modified.
+#[derive(Debug, Clone, Copy, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Edit {
+    /// Rotate clockwise by the given number of quarter turns
+    Rotate {
+        quarter_turns: u8,
+    },
+    FlipHorizontal,
+    FlipVertical,
+
+    /// Rotate clockwise by a small angle and crop to the largest rectangle
+    /// with the same aspect ratio that lies within the rotated photo
+    Straighten {
+        degrees: f32,
+    },
+
+    /// Scale the amount of light by 2^stops
+    Exposure {
+        stops: f32,
+    },
+
+    /// Scale the red and blue channels, leaving green as is
+    WhiteBalance {
+        red: f32,
+        blue: f32,
+    },
+}
+
+impl Edit {
+    pub fn is_valid(&self) -> bool {
+        let gain = MIN_WHITE_BALANCE_GAIN..=MAX_WHITE_BALANCE_GAIN;
+
+        match *self {
+            Edit::Rotate { quarter_turns } => quarter_turns < 4,
+            Edit::FlipHorizontal | Edit::FlipVertical => true,
+            Edit::Straighten { degrees } => degrees.abs() <= MAX_STRAIGHTEN_DEGREES,
+            Edit::Exposure { stops } => stops.abs() <= MAX_EXPOSURE_STOPS,
+            Edit::WhiteBalance { red, blue } => gain.contains(&red) && gain.contains(&blue),
+        }
+    }
+}
+
+fn scale_channels(img: RgbImageF32, gains: [f32; 3]) -> RgbImageF32 {
+    let (width, height) = img.dimensions();
+    let mut data = img.into_raw();
+
+    data.par_chunks_mut(3).for_each(|px| {
+        for c in 0..3 {
+            px[c] *= gains[c];
+        }
+    });
+
+    RgbImageF32::from_raw(width, height, data).unwrap()
+}
+
+/// Sample at the given coordinates, where pixel centers are at +0.5
+fn sample_bilinear(img: &RgbImageF32, x: f32, y: f32) -> [f32; 3] {
+    let (width, height) = img.dimensions();
+
+    let x = (x - 0.5).max(0.).min((width - 1) as f32);
+    let y = (y - 0.5).max(0.).min((height - 1) as f32);
+
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let mut px = [0f32; 3];
+    for c in 0..3 {
+        let top = img.get_pixel(x0, y0)[c] * (1. - fx) + img.get_pixel(x1, y0)[c] * fx;
+        let bottom = img.get_pixel(x0, y1)[c] * (1. - fx) + img.get_pixel(x1, y1)[c] * fx;
+        px[c] = top * (1. - fy) + bottom * fy;
+    }
+
+    px
+}
+
+fn straighten(img: &RgbImageF32, degrees: f32) -> RgbImageF32 {
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as f32, height as f32);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+
+    // The crop, rotated back, must fit within the original in both directions
+    let scale = (w / (w * cos.abs() + h * sin.abs())).min(h / (w * sin.abs() + h * cos.abs()));
+    let nwidth = ((w * scale) as u32).max(1);
+    let nheight = ((h * scale) as u32).max(1);
+
+    let mut data = vec![0f32; (nwidth * nheight * 3) as usize];
+
+    data.par_chunks_mut(nwidth as usize * 3)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let v = y as f32 + 0.5 - nheight as f32 / 2.;
+
+            for (x, px) in row.chunks_mut(3).enumerate() {
+                let u = x as f32 + 0.5 - nwidth as f32 / 2.;
+
+                // Rotating the result clockwise is sampling the original
+                // counterclockwise
+                let sx = u * cos + v * sin + w / 2.;
+                let sy = -u * sin + v * cos + h / 2.;
+
+                px.copy_from_slice(&sample_bilinear(img, sx, sy));
+            }
+        });
+
+    RgbImageF32::from_raw(nwidth, nheight, data).unwrap()
+}
+
+fn apply_one(img: RgbImageF32, edit: &Edit) -> RgbImageF32 {
+    match *edit {
+        Edit::Rotate { quarter_turns } => match quarter_turns % 4 {
+            1 => imageops::rotate90(&img),
+            2 => imageops::rotate180(&img),
+            3 => imageops::rotate270(&img),
+            _ => img,
+        },
+        Edit::FlipHorizontal => imageops::flip_horizontal(&img),
+        Edit::FlipVertical => imageops::flip_vertical(&img),
+        Edit::Straighten { degrees } if degrees == 0. => img,
+        Edit::Straighten { degrees } => straighten(&img, degrees),
+        Edit::Exposure { stops } => scale_channels(img, [2f32.powf(stops); 3]),
+        Edit::WhiteBalance { red, blue } => scale_channels(img, [red, 1., blue]),
+    }
+}
+
+/// Apply the edits in order to an image in linear light
+pub fn apply(img: RgbImageF32, edits: &[Edit]) -> RgbImageF32 {
+    edits.iter().fold(img, apply_one)
+}
+
+/// The edits of the given pixur, in the order they are applied
+pub fn load(
+    db_connection: &SqliteConnection,
+    pixurs_id: Id30,
+) -> Result<Vec<Edit>, Box<dyn std::error::Error>> {
+    let edits: Option<String> = pixur_edits::table
+        .filter(pixur_edits::pixurs_id.eq(pixurs_id))
+        .select(pixur_edits::edits)
+        .first(db_connection)
+        .optional()?;
+
+    match edits {
+        Some(edits) => Ok(serde_json::from_str(&edits)?),
+        None => Ok(vec![]),
+    }
+}
+
+/// Replace the edits of the given pixur. This does not touch the stored
+/// renditions, see `image::rerender`
+pub fn store(
+    db_connection: &SqliteConnection,
+    pixurs_id: Id30,
+    edits: &[Edit],
+) -> Result<(), diesel::result::Error> {
+    if edits.is_empty() {
+        diesel::delete(pixur_edits::table.filter(pixur_edits::pixurs_id.eq(pixurs_id)))
+            .execute(db_connection)?;
+        return Ok(());
+    }
+
+    let json = serde_json::to_string(edits).expect("Edits are always serializable");
+
+    diesel::replace_into(pixur_edits::table)
+        .values((
+            pixur_edits::pixurs_id.eq(pixurs_id),
+            pixur_edits::edits.eq(json),
+        ))
+        .execute(db_connection)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn numbered(width: u32, height: u32) -> RgbImageF32 {
+        ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([x as f32, y as f32, (y * width + x) as f32])
+        })
+    }
+
+    #[test]
+    fn edits_as_json() {
+        let edits = vec![
+            Edit::Rotate { quarter_turns: 1 },
+            Edit::FlipHorizontal,
+            Edit::Exposure { stops: -0.5 },
+        ];
+
+        let json = serde_json::to_string(&edits).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"op":"rotate","quarter_turns":1},{"op":"flip_horizontal"},{"op":"exposure","stops":-0.5}]"#
+        );
+        assert_eq!(serde_json::from_str::<Vec<Edit>>(&json).unwrap(), edits);
+    }
+
+    #[test]
+    fn validation() {
+        assert!(Edit::Rotate { quarter_turns: 3 }.is_valid());
+        assert!(!Edit::Rotate { quarter_turns: 4 }.is_valid());
+        assert!(!Edit::Straighten { degrees: 45. }.is_valid());
+        assert!(!Edit::Exposure {
+            stops: std::f32::NAN
+        }
+        .is_valid());
+        assert!(!Edit::WhiteBalance { red: 0., blue: 1. }.is_valid());
+    }
+
+    #[test]
+    fn rotation_and_flips() {
+        let img = numbered(3, 2);
+
+        let rotated = apply(img.clone(), &[Edit::Rotate { quarter_turns: 1 }]);
+        assert_eq!(rotated.dimensions(), (2, 3));
+        // The bottom left corner ends up in the top left
+        assert_eq!(rotated.get_pixel(0, 0), img.get_pixel(0, 1));
+
+        let turned = apply(img.clone(), &[Edit::FlipHorizontal, Edit::FlipVertical]);
+        let rotated = apply(img, &[Edit::Rotate { quarter_turns: 2 }]);
+        assert_eq!(turned.into_raw(), rotated.into_raw());
+    }
+
+    #[test]
+    fn straighten_crops_to_fit() {
+        let img = numbered(400, 300);
+
+        let unchanged = apply(img.clone(), &[Edit::Straighten { degrees: 0. }]);
+        assert_eq!(unchanged.into_raw(), img.clone().into_raw());
+
+        let straightened = apply(img, &[Edit::Straighten { degrees: 10. }]);
+        let (width, height) = straightened.dimensions();
+        assert!(width < 400 && height < 300);
+        assert!(((width as f32 / height as f32) - 4. / 3.).abs() < 0.01);
+    }
+
+    #[test]
+    fn exposure_is_linear() {
+        let img = ImageBuffer::from_pixel(2, 2, Rgb([0.1, 0.2, 0.4]));
+
+        let brighter = apply(img, &[Edit::Exposure { stops: 1. }]);
+        assert_eq!(brighter.get_pixel(1, 1), &Rgb([0.2, 0.4, 0.8]));
+    }
+}
@@ -0,0 +1,597 @@
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::prelude::*;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+
+use crate::archive;
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::image;
+use crate::rendition_policy::RenditionPolicy;
+
+/// The progress of an ingest job. The stages from `Decoding` to `Storing`
+/// are reported by `image::ingest` as it goes
+#[derive(
+    PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, AsExpression, FromSqlRow,
+)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum Stage {
+    Queued,
+    Decoding,
+    Linearizing,
+    Downscaling,
+    Encoding,
+    Storing,
+    Done,
+    Failed,
+}
+
+impl ToSql<Text, Sqlite> for Stage {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        let s = match self {
+            Stage::Queued => "queued",
+            Stage::Decoding => "decoding",
+            Stage::Linearizing => "linearizing",
+            Stage::Downscaling => "downscaling",
+            Stage::Encoding => "encoding",
+            Stage::Storing => "storing",
+            Stage::Done => "done",
+            Stage::Failed => "failed",
+        };
+        ToSql::<Text, Sqlite>::to_sql(s, out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Stage {
+    fn from_sql(value: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        // See comment_position.rs
+        let text_ptr = <*const str as FromSql<Text, Sqlite>>::from_sql(value)?;
+        let text = unsafe { &*text_ptr };
+        match text {
+            "queued" => Ok(Stage::Queued),
+            "decoding" => Ok(Stage::Decoding),
+            "linearizing" => Ok(Stage::Linearizing),
+            "downscaling" => Ok(Stage::Downscaling),
+            "encoding" => Ok(Stage::Encoding),
+            "storing" => Ok(Stage::Storing),
+            "done" => Ok(Stage::Done),
+            "failed" => Ok(Stage::Failed),
+            _ => Err("Invalid value in database".into()),
+        }
+    }
+}
+
+/// The order of the photos in the series that is created from a multi-file
+/// upload
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize, AsExpression, FromSqlRow)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum SeriesOrder {
+    /// The order of the files in the upload
+    Upload,
+
+    /// By the time of capture in the EXIF metadata. Photos without it are
+    /// placed last, in upload order
+    Exif,
+}
+
+impl ToSql<Text, Sqlite> for SeriesOrder {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        let s = match self {
+            SeriesOrder::Upload => "upload",
+            SeriesOrder::Exif => "exif",
+        };
+        ToSql::<Text, Sqlite>::to_sql(s, out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for SeriesOrder {
+    fn from_sql(value: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        // See comment_position.rs
+        let text_ptr = <*const str as FromSql<Text, Sqlite>>::from_sql(value)?;
+        let text = unsafe { &*text_ptr };
+        match text {
+            "upload" => Ok(SeriesOrder::Upload),
+            "exif" => Ok(SeriesOrder::Exif),
+            _ => Err("Invalid value in database".into()),
+        }
+    }
+}
+
+fn insert_job(
+    db_connection: &SqliteConnection,
+    rng: &mut impl rand::Rng,
+    media_type: &str,
+    data: &[u8],
+    batch: Option<(Id30, i32)>,
+) -> Result<Id30, diesel::result::Error> {
+    let id = Id30::new_random(rng);
+
+    diesel::insert_into(ingest_jobs::table)
+        .values((
+            ingest_jobs::id.eq(id),
+            ingest_jobs::media_type.eq(media_type),
+            ingest_jobs::data.eq(data),
+            ingest_jobs::stage.eq(Stage::Queued),
+            ingest_jobs::batch_id.eq(batch.map(|x| x.0)),
+            ingest_jobs::batch_position.eq(batch.map(|x| x.1)),
+        ))
+        .execute(db_connection)?;
+
+    Ok(id)
+}
+
+/// Store an upload for processing, see `start_worker`. The caller is
+/// responsible for notifying the worker of the returned job
+pub fn enqueue(
+    db_connection: &SqliteConnection,
+    media_type: &str,
+    data: &[u8],
+) -> Result<Id30, diesel::result::Error> {
+    use rand::{rngs::SmallRng, SeedableRng};
+    let mut rng = SmallRng::from_entropy();
+
+    insert_job(db_connection, &mut rng, media_type, data, None)
+}
+
+/// Store many uploads for processing, like `enqueue` does with one, all or
+/// nothing. See `enqueue_batch` for making a series of them
+pub fn enqueue_all(
+    db_connection: &SqliteConnection,
+    uploads: &[(&str, &[u8])],
+) -> Result<Vec<Id30>, diesel::result::Error> {
+    use rand::{rngs::SmallRng, SeedableRng};
+    let mut rng = SmallRng::from_entropy();
+
+    db_connection.transaction(|| {
+        uploads
+            .iter()
+            .map(|&(media_type, data)| insert_job(db_connection, &mut rng, media_type, data, None))
+            .collect()
+    })
+}
+
+/// A multi-file upload that has been stored for processing
+pub struct Batch {
+    /// The series is created when all the jobs are finished
+    pub pixur_series_id: Id30,
+    pub jobs: Vec<Id30>,
+}
+
+/// Store a multi-file upload, given as media types and data, for
+/// processing. Each file is processed as a separate job, like by `enqueue`.
+/// When the last of them is finished, a series is created from the photos
+/// that were ingested successfully
+pub fn enqueue_batch(
+    db_connection: &SqliteConnection,
+    series_order: SeriesOrder,
+    uploads: &[(&str, &[u8])],
+) -> Result<Batch, diesel::result::Error> {
+    use rand::{rngs::SmallRng, SeedableRng};
+    let mut rng = SmallRng::from_entropy();
+
+    db_connection.transaction(|| {
+        let id = Id30::new_random(&mut rng);
+        let pixur_series_id = Id30::new_random(&mut rng);
+
+        diesel::insert_into(ingest_batches::table)
+            .values((
+                ingest_batches::id.eq(id),
+                ingest_batches::pixur_series_id.eq(pixur_series_id),
+                ingest_batches::series_order.eq(series_order),
+            ))
+            .execute(db_connection)?;
+
+        let jobs = uploads
+            .iter()
+            .enumerate()
+            .map(|(position, &(media_type, data))| {
+                let batch = Some((id, position as i32));
+                insert_job(db_connection, &mut rng, media_type, data, batch)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Batch {
+            pixur_series_id,
+            jobs,
+        })
+    })
+}
+
+#[derive(Queryable)]
+struct BatchJob {
+    stage: Stage,
+    pixurs_id: Option<Id30>,
+    position: Option<i32>,
+    taken_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Create the series of a multi-file upload if all its jobs are finished
+/// and the batch is not finished already. Photos that failed are left out,
+/// and the batch is marked as finished even when none of them succeeded
+fn finish_batch(
+    db_connection: &SqliteConnection,
+    batch_id: Id30,
+) -> Result<(), diesel::result::Error> {
+    let (pixur_series_id, series_order, finished): (
+        Id30,
+        SeriesOrder,
+        Option<chrono::NaiveDateTime>,
+    ) = ingest_batches::table
+        .filter(ingest_batches::id.eq(batch_id))
+        .select((
+            ingest_batches::pixur_series_id,
+            ingest_batches::series_order,
+            ingest_batches::finished,
+        ))
+        .first(db_connection)?;
+
+    if finished.is_some() {
+        return Ok(());
+    }
+
+    let jobs: Vec<BatchJob> = ingest_jobs::table
+        .left_join(pixurs::table)
+        .filter(ingest_jobs::batch_id.eq(batch_id))
+        .select((
+            ingest_jobs::stage,
+            ingest_jobs::pixurs_id,
+            ingest_jobs::batch_position,
+            pixurs::taken_at.nullable(),
+        ))
+        .load(db_connection)?;
+
+    if jobs
+        .iter()
+        .any(|x| x.stage != Stage::Done && x.stage != Stage::Failed)
+    {
+        return Ok(());
+    }
+
+    let mut photos: Vec<BatchJob> = jobs
+        .into_iter()
+        .filter(|x| x.stage == Stage::Done)
+        .collect();
+
+    // Sorting is stable, so photos taken at the same time stay in upload order
+    photos.sort_by_key(|x| x.position);
+    if series_order == SeriesOrder::Exif {
+        photos.sort_by_key(|x| (x.taken_at.is_none(), x.taken_at));
+    }
+
+    db_connection.transaction(|| {
+        // Only mark the batch as finished once, so the series is created once
+        let marked = diesel::update(
+            ingest_batches::table
+                .filter(ingest_batches::id.eq(batch_id))
+                .filter(ingest_batches::finished.is_null()),
+        )
+        .set(ingest_batches::finished.eq(diesel::dsl::now.nullable()))
+        .execute(db_connection)?;
+        if marked == 0 {
+            return Ok(());
+        }
+
+        let pixurs_ids = photos.iter().filter_map(|x| x.pixurs_id);
+        for (order, pixurs_id) in pixurs_ids.enumerate() {
+            diesel::insert_into(pixur_series::table)
+                .values((
+                    pixur_series::id.eq(pixur_series_id),
+                    pixur_series::order.eq(order as i32),
+                    pixur_series::pixurs_id.eq(pixurs_id),
+                ))
+                .execute(db_connection)?;
+        }
+
+        Ok(())
+    })
+}
+
+fn set_stage(
+    db_pool: &Pool<ConnectionManager<SqliteConnection>>,
+    id: Id30,
+    stage: Stage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    diesel::update(ingest_jobs::table.filter(ingest_jobs::id.eq(id)))
+        .set(ingest_jobs::stage.eq(stage))
+        .execute(&*db_pool.get()?)?;
+
+    Ok(())
+}
+
+fn run(
+    id: Id30,
+    db_pool: &Pool<ConnectionManager<SqliteConnection>>,
+    limits: &image::Limits,
+    policy: &RenditionPolicy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (media_type, data): (String, Option<Vec<u8>>) = ingest_jobs::table
+        .filter(ingest_jobs::id.eq(id))
+        .select((ingest_jobs::media_type, ingest_jobs::data))
+        .first(&*db_pool.get()?)?;
+    let data = data.ok_or("The upload is gone")?;
+
+    // Stages are reported from parallel renderings, so they may arrive out
+    // of order. Only advancing the stage keeps the reported progress sane
+    let reached = Mutex::new(Stage::Queued);
+    let progress = |stage: Stage| {
+        let mut reached = reached.lock().unwrap();
+        if stage > *reached {
+            *reached = stage;
+            if let Err(err) = set_stage(db_pool, id, stage) {
+                eprintln!("JOB: Failed to update stage of {}: {}", id, err);
+            }
+        }
+    };
+
+    // The job is marked as done along with storing the pixur, so a restart
+    // in between does not ingest it again
+    image::ingest(
+        &data,
+        &media_type,
+        limits,
+        policy,
+        db_pool.clone(),
+        &progress,
+        |db_connection, ingested| {
+            let duplicates: Vec<String> = ingested.duplicates.iter().map(Id30::to_string).collect();
+
+            diesel::update(ingest_jobs::table.filter(ingest_jobs::id.eq(id)))
+                .set((
+                    ingest_jobs::stage.eq(Stage::Done),
+                    ingest_jobs::pixurs_id.eq(ingested.pixurs_id),
+                    ingest_jobs::pixur_series_id.eq(ingested.pixur_series_id),
+                    ingest_jobs::duplicates.eq(serde_json::to_string(&duplicates)?),
+                    ingest_jobs::data.eq(None::<Vec<u8>>),
+                ))
+                .execute(db_connection)?;
+
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+fn process(
+    id: Id30,
+    db_pool: &Pool<ConnectionManager<SqliteConnection>>,
+    limits: &image::Limits,
+    policy: &RenditionPolicy,
+) {
+    // A panic must not take down the worker thread
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run(id, db_pool, limits, policy).map_err(|err| err.to_string())
+    }))
+    .unwrap_or_else(|_| Err("Processing panicked".to_string()));
+
+    let db_connection = match db_pool.get() {
+        Ok(db_connection) => db_connection,
+        Err(_) => return,
+    };
+
+    if let Err(err) = result {
+        eprintln!("JOB: Failed to ingest {}: {}", id, err);
+
+        // A failed job is not retried, so the upload is of no further use
+        let _ = diesel::update(ingest_jobs::table.filter(ingest_jobs::id.eq(id)))
+            .set((
+                ingest_jobs::stage.eq(Stage::Failed),
+                ingest_jobs::error.eq(err),
+                ingest_jobs::data.eq(None::<Vec<u8>>),
+            ))
+            .execute(&*db_connection);
+    }
+
+    let batch_id = ingest_jobs::table
+        .filter(ingest_jobs::id.eq(id))
+        .select(ingest_jobs::batch_id)
+        .first::<Option<Id30>>(&*db_connection);
+    if let Ok(Some(batch_id)) = batch_id {
+        if let Err(err) = finish_batch(&*db_connection, batch_id) {
+            eprintln!(
+                "JOB: Failed to create the series of batch {}: {}",
+                batch_id, err
+            );
+        }
+    }
+}
+
+/// Ingest local files right away, on this thread, rather than by way of the
+/// background worker. ZIP archives are unpacked, see `archive::read_zip`, and
+/// files that cannot be ingested are skipped. The outcome for each file is
+/// logged
+///
+/// When a series order is given, a new series is made of all the photos, as
+/// for a multi-file upload. Returns the number of photos that were ingested.
+pub fn ingest_files(
+    db_pool: &Pool<ConnectionManager<SqliteConnection>>,
+    paths: &[PathBuf],
+    series_order: Option<SeriesOrder>,
+    limits: &image::Limits,
+    policy: &RenditionPolicy,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut names = vec![];
+    let mut uploads = vec![];
+    for path in paths {
+        let data = std::fs::read(path)?;
+
+        let files = if archive::is_zip(&data) {
+            archive::read_zip(&data, limits)?
+                .into_iter()
+                .map(|file| (format!("{}/{}", path.display(), file.name), file.content))
+                .collect()
+        } else {
+            vec![(
+                path.display().to_string(),
+                archive::supported_image(data, limits),
+            )]
+        };
+
+        for (name, content) in files {
+            match content {
+                Ok((media_type, data)) => {
+                    names.push(name);
+                    uploads.push((media_type, data));
+                }
+                Err(reason) => eprintln!("ING: Skipped {}: {}", name, reason),
+            }
+        }
+    }
+
+    if uploads.is_empty() {
+        return Ok(0);
+    }
+
+    let uploads: Vec<(&str, &[u8])> = uploads
+        .iter()
+        .map(|(media_type, data)| (*media_type, &data[..]))
+        .collect();
+
+    let db_connection = db_pool.get()?;
+    let (pixur_series_id, jobs) = match series_order {
+        Some(series_order) => {
+            let batch = enqueue_batch(&*db_connection, series_order, &uploads)?;
+            (Some(batch.pixur_series_id), batch.jobs)
+        }
+        None => (None, enqueue_all(&*db_connection, &uploads)?),
+    };
+    drop(db_connection);
+
+    let mut ingested = 0;
+    for (i, (&id, name)) in jobs.iter().zip(&names).enumerate() {
+        eprintln!("ING: Ingesting {} ({}/{})", name, i + 1, jobs.len());
+        process(id, db_pool, limits, policy);
+
+        let (pixurs_id, duplicates): (Option<Id30>, Option<String>) = ingest_jobs::table
+            .filter(ingest_jobs::id.eq(id))
+            .select((ingest_jobs::pixurs_id, ingest_jobs::duplicates))
+            .first(&*db_pool.get()?)?;
+
+        if let Some(pixurs_id) = pixurs_id {
+            ingested += 1;
+            eprintln!("ING: Ingested {} as {}", name, pixurs_id);
+
+            let duplicates: Vec<String> = duplicates
+                .map(|x| serde_json::from_str(&x))
+                .transpose()?
+                .unwrap_or_default();
+            if !duplicates.is_empty() {
+                eprintln!("ING: {} looks like {}", name, duplicates.join(", "));
+            }
+        }
+    }
+
+    if let Some(pixur_series_id) = pixur_series_id {
+        if ingested > 0 {
+            eprintln!("ING: Created series {}", pixur_series_id);
+        }
+    }
+
+    Ok(ingested)
+}
+
+/// Start the background thread that processes ingest jobs, one at a time.
+/// Send the ID of each new job to the returned channel
+///
+/// Jobs that were left unfinished, for example by a restart, are processed
+/// first, starting over from the upload. Multi-file uploads that were
+/// interrupted after the last job finished are finished right away. A
+/// batch that cannot be finished is logged, and does not stop the worker.
+pub fn start_worker(
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    limits: image::Limits,
+    policy: RenditionPolicy,
+) -> Result<Sender<Id30>, Box<dyn std::error::Error>> {
+    let unfinished_batches: Vec<Id30> = ingest_batches::table
+        .filter(ingest_batches::finished.is_null())
+        .select(ingest_batches::id)
+        .load(&*db_pool.get()?)?;
+
+    for batch_id in unfinished_batches {
+        if let Err(err) = finish_batch(&*db_pool.get()?, batch_id) {
+            eprintln!(
+                "JOB: Failed to create the series of batch {}: {}",
+                batch_id, err
+            );
+        }
+    }
+
+    let unfinished: Vec<Id30> = ingest_jobs::table
+        .filter(ingest_jobs::stage.ne(Stage::Done))
+        .filter(ingest_jobs::stage.ne(Stage::Failed))
+        .order(ingest_jobs::created.asc())
+        .select(ingest_jobs::id)
+        .load(&*db_pool.get()?)?;
+
+    let (sender, receiver) = mpsc::channel();
+    for id in unfinished {
+        sender.send(id)?;
+    }
+
+    std::thread::Builder::new()
+        .name("ingest".to_string())
+        .spawn(move || {
+            for id in receiver {
+                process(id, &db_pool, &limits, &policy);
+            }
+        })?;
+
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::test_connection;
+
+    #[test]
+    fn batch_is_finished_when_all_jobs_failed() {
+        let conn = test_connection();
+        let batch = enqueue_batch(
+            &conn,
+            SeriesOrder::Upload,
+            &[("image/jpeg", &b"a"[..]), ("image/jpeg", &b"b"[..])],
+        )
+        .unwrap();
+
+        let (batch_id, finished): (Id30, Option<chrono::NaiveDateTime>) = ingest_batches::table
+            .select((ingest_batches::id, ingest_batches::finished))
+            .first(&conn)
+            .unwrap();
+        assert_eq!(finished, None);
+
+        let fail = |id: Id30| {
+            diesel::update(ingest_jobs::table.filter(ingest_jobs::id.eq(id)))
+                .set(ingest_jobs::stage.eq(Stage::Failed))
+                .execute(&conn)
+                .unwrap();
+            finish_batch(&conn, batch_id).unwrap();
+
+            ingest_batches::table
+                .select(ingest_batches::finished)
+                .first::<Option<chrono::NaiveDateTime>>(&conn)
+                .unwrap()
+        };
+
+        assert_eq!(fail(batch.jobs[0]), None);
+        assert!(fail(batch.jobs[1]).is_some());
+
+        let series: i64 = pixur_series::table
+            .filter(pixur_series::id.eq(batch.pixur_series_id))
+            .count()
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(series, 0);
+    }
+}
@@ -7,11 +7,26 @@ extern crate diesel;
 #[macro_use]
 extern crate lazy_static;
 
+mod archive;
+mod blurhash;
 mod comment_position;
 mod db;
+mod deep_zoom;
+mod icc_profile;
 mod id30;
 mod image;
+mod image_edit;
+mod ingest_job;
+mod motion_photo;
+mod palette;
+mod perceptual_hash;
+mod photo_metadata;
+mod regenerate;
+mod rendition_policy;
+mod resize;
 mod site;
+mod unsharp_mask;
+mod upload_session;
 
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -34,6 +49,55 @@ struct Options {
     /// SQLite database file
     #[structopt(name = "DB")]
     db: String,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Apply the rendition policy in the config file to all existing pixurs,
+    /// and exit. An interrupted run resumes where it left off
+    #[structopt(name = "regenerate")]
+    Regenerate {
+        /// The number of pixurs to regenerate at a time. Each of these holds
+        /// its photo, decoded, in memory
+        #[structopt(long = "jobs", short = "j", default_value = "2")]
+        jobs: usize,
+
+        /// Log what each pixur would be regenerated from, without changing
+        /// anything
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+
+        /// Start over, rather than resume an interrupted run
+        #[structopt(long = "restart")]
+        restart: bool,
+    },
+
+    /// Render a photo with the rendition policy in the config file without
+    /// storing anything, log the time spent in each stage, and exit
+    #[structopt(name = "benchmark")]
+    Benchmark {
+        #[structopt(parse(from_os_str))]
+        photo: PathBuf,
+    },
+
+    /// Ingest photos, and the photos in ZIP archives, and exit
+    #[structopt(name = "ingest")]
+    Ingest {
+        /// Make a new series of all the photos, in the order of the files,
+        /// `upload`, or by the time of capture, `exif`
+        #[structopt(long = "series", parse(try_from_str = "parse_series_order"))]
+        series: Option<ingest_job::SeriesOrder>,
+
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+}
+
+fn parse_series_order(src: &str) -> Result<ingest_job::SeriesOrder, serde_plain::Error> {
+    serde_plain::from_str(src)
 }
 
 #[derive(Debug, serde_derive::Deserialize)]
@@ -53,13 +117,28 @@ struct Config {
     url: String,
     secret: String,
     email: EmailConfig,
+
+    #[serde(default)]
+    limits: image::Limits,
+
+    #[serde(default)]
+    renditions: rendition_policy::RenditionPolicy,
+
+    deep_zoom: Option<deep_zoom::DeepZoom>,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Options::from_args();
 
     let config = std::fs::read_to_string(opt.config)?;
-    let config: Config = toml::from_str(&config)?;
+    let mut config: Config = toml::from_str(&config)?;
+    config.renditions = config.renditions.with_deep_zoom(config.deep_zoom.take());
+    config.renditions.validate()?;
+
+    if let Some(Command::Benchmark { photo }) = &opt.command {
+        let data = std::fs::read(photo)?;
+        return image::benchmark(&data, &config.limits, &config.renditions);
+    }
 
     // The following starts a thread pool. This, in turn, blocks propagation
     // of panics..! However, it looks like propagation of panics is planned,
@@ -68,6 +147,84 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let db_pool = db::create_pool(opt.db)?;
 
+    let backfilled = photo_metadata::backfill(&*db_pool.get()?)?;
+    if backfilled > 0 {
+        eprintln!(
+            "Extracted photo metadata for {} existing pixurs",
+            backfilled
+        );
+    }
+
+    let backfilled = image::backfill_blurhashes(&*db_pool.get()?)?;
+    if backfilled > 0 {
+        eprintln!("Computed placeholders for {} existing pixurs", backfilled);
+    }
+
+    let backfilled = image::backfill_palettes(&*db_pool.get()?)?;
+    if backfilled > 0 {
+        eprintln!("Found palettes for {} existing pixurs", backfilled);
+    }
+
+    let backfilled = image::backfill_perceptual_hashes(&*db_pool.get()?)?;
+    if backfilled > 0 {
+        eprintln!(
+            "Computed perceptual hashes for {} existing pixurs",
+            backfilled
+        );
+    }
+
+    let deleted = upload_session::delete_stale(&*db_pool.get()?)?;
+    if deleted > 0 {
+        eprintln!("Deleted {} stale upload sessions", deleted);
+    }
+
+    if let Some(Command::Regenerate {
+        jobs,
+        dry_run: true,
+        restart,
+    }) = opt.command
+    {
+        let summary = regenerate::dry_run(&*db_pool.get()?, restart)?;
+        eprintln!(
+            "Would regenerate {} pixurs, {} are already done and {} would fail",
+            summary.regenerated, summary.already_done, summary.failed
+        );
+        if summary.regenerated > 0 {
+            eprintln!("Would run {} at a time", jobs);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Regenerate { jobs, restart, .. }) = opt.command {
+        let summary = regenerate::regenerate(&db_pool, &config.renditions, jobs, restart)?;
+        eprintln!(
+            "Regenerated {} pixurs, {} were already done and {} failed",
+            summary.regenerated, summary.already_done, summary.failed
+        );
+        if summary.failed > 0 {
+            eprintln!("Run regenerate again to retry the pixurs that failed");
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Ingest { series, files }) = opt.command {
+        let ingested = ingest_job::ingest_files(
+            &db_pool,
+            &files,
+            series,
+            &config.limits,
+            &config.renditions,
+        )?;
+        eprintln!("Ingested {} photos", ingested);
+        return Ok(());
+    }
+
+    let ingest_queue = ingest_job::start_worker(
+        db_pool.clone(),
+        config.limits,
+        config.renditions.clone(),
+    )?;
+
     let bind_host = "127.0.0.1".parse().expect("Acceptable IP address");
     let bind_port = 1212;
 
@@ -88,6 +245,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         key,
         config.url,
         db_pool,
+        config.limits,
+        config.renditions,
+        ingest_queue,
         mailer,
         sender,
         runtime.executor().compat(),
@@ -0,0 +1,253 @@
+use byteorder::{BigEndian, ByteOrder};
+
+const MARKER_SOI: u8 = 0xd8;
+const MARKER_SOS: u8 = 0xda;
+const MARKER_EOI: u8 = 0xd9;
+const MARKER_APP1: u8 = 0xe1;
+
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+pub const MEDIA_TYPE: &str = "video/mp4";
+
+/// Find the XMP packet of a JPEG file, along with the offset of the end of
+/// the segment that holds it
+fn xmp_from_jpeg(data: &[u8]) -> Option<(&str, usize)> {
+    if data.get(0..2)? != [0xff, MARKER_SOI] {
+        return None;
+    }
+
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+
+        match marker {
+            // Fill bytes
+            0xff => {
+                pos += 1;
+                continue;
+            }
+            // The metadata must precede the image data
+            MARKER_SOS | MARKER_EOI => return None,
+            _ => (),
+        }
+
+        let len = BigEndian::read_u16(data.get(pos + 2..pos + 4)?) as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+
+        if marker == MARKER_APP1 && segment.starts_with(XMP_SIGNATURE) {
+            let xmp = std::str::from_utf8(&segment[XMP_SIGNATURE.len()..]).ok()?;
+            return Some((xmp, pos + 2 + len));
+        }
+
+        pos += 2 + len;
+    }
+}
+
+/// Find the end of the image, just past the EOI marker, by walking the
+/// segments from the given offset. The entropy-coded data after each SOS
+/// segment ends at the first marker that is not a restart marker or a
+/// stuffed 0xff byte
+fn end_of_image(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+
+        match marker {
+            // Fill bytes
+            0xff => {
+                pos += 1;
+                continue;
+            }
+            MARKER_EOI => return Some(pos + 2),
+            _ => (),
+        }
+
+        let len = BigEndian::read_u16(data.get(pos + 2..pos + 4)?) as usize;
+        pos += 2 + len;
+
+        if marker == MARKER_SOS {
+            loop {
+                match data.get(pos..pos + 2)? {
+                    [0xff, 0x00] | [0xff, 0xd0..=0xd7] => pos += 2,
+                    [0xff, _] => break,
+                    _ => pos += 1,
+                }
+            }
+        }
+    }
+}
+
+/// Look up a property by its local name, ignoring the namespace prefix,
+/// which varies between vendors. XMP allows simple properties both as
+/// attributes and as elements
+fn property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let attribute = format!(":{}=\"", name);
+    if let Some(start) = xmp.find(&attribute) {
+        let value = &xmp[start + attribute.len()..];
+        return Some(&value[..value.find('"')?]);
+    }
+
+    let element = format!(":{}>", name);
+    let start = xmp.find(&element)?;
+    let value = &xmp[start + element.len()..];
+    Some(value[..value.find('<')?].trim())
+}
+
+/// The length of the video at the end of the file, as declared in the
+/// container directory of the Motion Photo format
+fn motion_photo_length(xmp: &str) -> Option<usize> {
+    let semantic = xmp.find(":Semantic=\"MotionPhoto\"")?;
+
+    // The length is another attribute of the same directory item
+    let item_start = xmp[..semantic].rfind('<')?;
+    let item_end = semantic + xmp[semantic..].find('>')?;
+
+    property(&xmp[item_start..item_end], "Length")?.parse().ok()
+}
+
+/// Check that the data starts with the `ftyp` box of an MP4 file
+fn is_mp4(data: &[u8]) -> bool {
+    data.len() >= 8
+        && &data[4..8] == b"ftyp"
+        && (8..=data.len()).contains(&(BigEndian::read_u32(&data[0..4]) as usize))
+}
+
+/// Search for the start of an MP4 file after the given offset
+fn find_mp4(data: &[u8], from: usize) -> Option<usize> {
+    (from..=data.len().saturating_sub(8))
+        .find(|&pos| is_mp4(&data[pos..]) && BigEndian::read_u32(&data[pos..pos + 4]) <= 256)
+}
+
+/// Extract the video clip of a motion photo
+///
+/// Google and Samsung cameras append an MP4 to the JPEG and describe it in
+/// the XMP metadata. The older MicroVideo format gives the offset of the
+/// video from the end of the file in `MicroVideoOffset`, while the Motion
+/// Photo format lists it in a container directory. Some files declare a
+/// length that does not match, typically because of vendor data after the
+/// video, so fall back to searching for the MP4 header. Returns `None` if
+/// the JPEG is not a motion photo.
+pub fn from_jpeg(data: &[u8]) -> Option<&[u8]> {
+    let (xmp, xmp_end) = xmp_from_jpeg(data)?;
+
+    let length = if property(xmp, "MotionPhoto") == Some("1") {
+        motion_photo_length(xmp)
+    } else if property(xmp, "MicroVideo") == Some("1") {
+        property(xmp, "MicroVideoOffset").and_then(|x| x.parse().ok())
+    } else {
+        return None;
+    };
+
+    // The video follows the image, and the image data could by chance look
+    // like an MP4 header
+    let image_end = end_of_image(data, xmp_end)?;
+
+    let declared = length
+        .and_then(|length: usize| data.len().checked_sub(length))
+        .filter(|&start| start >= image_end && is_mp4(&data[start..]));
+
+    let start = declared.or_else(|| find_mp4(data, image_end))?;
+    Some(&data[start..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let len = (payload.len() + 2) as u16;
+        let mut seg = vec![0xff, marker, (len >> 8) as u8, len as u8];
+        seg.extend_from_slice(payload);
+        seg
+    }
+
+    fn jpeg(xmp: &str, trailer: &[u8]) -> Vec<u8> {
+        let mut payload = XMP_SIGNATURE.to_vec();
+        payload.extend_from_slice(xmp.as_bytes());
+
+        let mut data = vec![0xff, MARKER_SOI];
+        data.extend(segment(MARKER_APP1, &payload));
+        data.extend(segment(MARKER_SOS, &[0; 10]));
+        data.extend_from_slice(&[1, 2, 3, 4, 0xff, MARKER_EOI]);
+        data.extend_from_slice(trailer);
+        data
+    }
+
+    fn mp4() -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 16];
+        data.extend_from_slice(b"ftypmp42\0\0\0\0");
+        data.extend_from_slice(&[0, 0, 0, 8]);
+        data.extend_from_slice(b"mdat");
+        data
+    }
+
+    #[test]
+    fn micro_video() {
+        let video = mp4();
+        let xmp = format!(
+            r#"<rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="{}"/>"#,
+            video.len()
+        );
+
+        assert_eq!(from_jpeg(&jpeg(&xmp, &video)), Some(&video[..]));
+    }
+
+    #[test]
+    fn motion_photo() {
+        let video = mp4();
+        let xmp = format!(
+            r#"<rdf:Description>
+                <Camera:MotionPhoto>1</Camera:MotionPhoto>
+                <Container:Directory><rdf:Seq>
+                    <rdf:li><Container:Item Item:Mime="image/jpeg" Item:Semantic="Primary"/></rdf:li>
+                    <rdf:li><Container:Item Item:Mime="video/mp4" Item:Semantic="MotionPhoto" Item:Length="{}"/></rdf:li>
+                </rdf:Seq></Container:Directory>
+            </rdf:Description>"#,
+            video.len()
+        );
+
+        assert_eq!(from_jpeg(&jpeg(&xmp, &video)), Some(&video[..]));
+    }
+
+    #[test]
+    fn wrong_length_falls_back_to_search() {
+        let mut trailer = mp4();
+        trailer.extend_from_slice(b"vendor data");
+        let xmp = r#"<rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="3"/>"#;
+
+        assert_eq!(from_jpeg(&jpeg(xmp, &trailer)), Some(&trailer[..]));
+    }
+
+    #[test]
+    fn search_starts_after_the_image() {
+        let mut trailer = mp4();
+        trailer.extend_from_slice(b"vendor data");
+        let xmp = r#"<rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="3"/>"#;
+
+        // Image data that looks like an MP4 header, and a stuffed 0xff byte
+        let mut data = jpeg(xmp, &trailer);
+        let scan = data.windows(4).position(|x| x == [1, 2, 3, 4]).unwrap();
+        let mut fake = mp4();
+        fake.extend_from_slice(&[0xff, 0x00]);
+        data.splice(scan..scan, fake);
+
+        assert_eq!(from_jpeg(&data), Some(&trailer[..]));
+    }
+
+    #[test]
+    fn still_photos() {
+        let video = mp4();
+        assert_eq!(from_jpeg(&jpeg("<rdf:Description/>", &video)), None);
+
+        let xmp = r#"<rdf:Description GCamera:MotionPhoto="0"/>"#;
+        assert_eq!(from_jpeg(&jpeg(xmp, &video)), None);
+
+        let xmp = r#"<rdf:Description GCamera:MicroVideo="1" GCamera:MicroVideoOffset="8"/>"#;
+        assert_eq!(from_jpeg(&jpeg(xmp, b"not a video")), None);
+    }
+}
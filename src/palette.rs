@@ -0,0 +1,206 @@
+use image::{ImageBuffer, Rgb};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+type RgbImageF32 = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+/// The maximum number of colors in a palette. Images with fewer distinct
+/// colors get fewer
+pub const PALETTE_SIZE: usize = 5;
+
+const MAX_ITERATIONS: usize = 20;
+
+/// The top and bottom edges are this fraction of the height of the image
+const EDGE_FRACTION: u32 = 8;
+
+/// The dominant colors of an image, in linear light
+#[derive(Debug)]
+pub struct Palette {
+    /// The most common color first
+    pub colors: Vec<[f32; 3]>,
+
+    /// The index of the most common color along the top edge
+    pub top: usize,
+
+    /// The index of the most common color along the bottom edge
+    pub bottom: usize,
+}
+
+fn distance2(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+fn nearest(centers: &[[f32; 3]], px: &[f32; 3]) -> usize {
+    let mut best = (0, std::f32::INFINITY);
+    for (i, center) in centers.iter().enumerate() {
+        let d = distance2(center, px);
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+    best.0
+}
+
+/// Choose the initial centers with k-means++, which spreads them out over
+/// the colors of the image. The seed is fixed, so the same image always
+/// gives the same palette
+fn initial_centers(pixels: &[[f32; 3]], k: usize) -> Vec<[f32; 3]> {
+    let mut rng = SmallRng::seed_from_u64(0);
+
+    let mut centers = vec![pixels[rng.gen_range(0, pixels.len())]];
+    let mut distances: Vec<f32> = pixels.iter().map(|px| distance2(&centers[0], px)).collect();
+
+    while centers.len() < k {
+        let total: f32 = distances.iter().sum();
+        if total <= 0. {
+            // Every pixel is already a center
+            break;
+        }
+
+        let mut target = rng.gen::<f32>() * total;
+        let chosen = distances
+            .iter()
+            .position(|&d| {
+                target -= d;
+                target <= 0.
+            })
+            .unwrap_or(pixels.len() - 1);
+
+        let center = pixels[chosen];
+        for (d, px) in distances.iter_mut().zip(pixels) {
+            *d = d.min(distance2(&center, px));
+        }
+        centers.push(center);
+    }
+
+    centers
+}
+
+fn most_common(assignment: &[usize], k: usize) -> usize {
+    let mut counts = vec![0usize; k];
+    for &i in assignment {
+        counts[i] += 1;
+    }
+
+    (0..k)
+        .max_by_key(|&i| (counts[i], std::cmp::Reverse(i)))
+        .unwrap()
+}
+
+/// Find the dominant colors of an image in linear light by k-means
+/// clustering, as well as the dominant colors along its top and bottom
+/// edges
+///
+/// This is meant for small images, such as the 160px analysis image made
+/// during ingest.
+pub fn palette(img: &RgbImageF32) -> Palette {
+    let (width, height) = img.dimensions();
+    let pixels: Vec<[f32; 3]> = img.pixels().map(|px| [px[0], px[1], px[2]]).collect();
+
+    let mut centers = initial_centers(&pixels, PALETTE_SIZE);
+    let k = centers.len();
+
+    let mut assignment = vec![usize::max_value(); pixels.len()];
+    let mut counts = vec![0usize; k];
+
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (a, px) in assignment.iter_mut().zip(&pixels) {
+            let n = nearest(&centers, px);
+            if n != *a {
+                *a = n;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![[0f32; 3]; k];
+        counts = vec![0usize; k];
+        for (&a, px) in assignment.iter().zip(&pixels) {
+            for c in 0..3 {
+                sums[a][c] += px[c];
+            }
+            counts[a] += 1;
+        }
+
+        for i in 0..k {
+            if counts[i] > 0 {
+                let n = counts[i] as f32;
+                centers[i] = [sums[i][0] / n, sums[i][1] / n, sums[i][2] / n];
+            }
+        }
+    }
+
+    // Order by how common the colors are, leaving out empty clusters
+    let mut order: Vec<usize> = (0..k).filter(|&i| counts[i] > 0).collect();
+    order.sort_by_key(|&i| (std::cmp::Reverse(counts[i]), i));
+
+    let mut index = vec![0; k];
+    for (new, &old) in order.iter().enumerate() {
+        index[old] = new;
+    }
+    let assignment: Vec<usize> = assignment.into_iter().map(|a| index[a]).collect();
+
+    let edge = ((height / EDGE_FRACTION).max(1) * width) as usize;
+    let top = most_common(&assignment[..edge], order.len());
+    let bottom = most_common(&assignment[assignment.len() - edge..], order.len());
+
+    Palette {
+        colors: order.iter().map(|&i| centers[i]).collect(),
+        top,
+        bottom,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RED: [f32; 3] = [0.8, 0.1, 0.1];
+    const BLUE: [f32; 3] = [0.1, 0.1, 0.8];
+
+    fn close(a: &[f32; 3], b: &[f32; 3]) -> bool {
+        distance2(a, b) < 1e-6
+    }
+
+    #[test]
+    fn single_color() {
+        let img = ImageBuffer::from_pixel(16, 16, Rgb([0.2, 0.4, 0.6]));
+
+        let palette = palette(&img);
+        assert_eq!(palette.colors.len(), 1);
+        assert!(close(&palette.colors[0], &[0.2, 0.4, 0.6]));
+        assert_eq!((palette.top, palette.bottom), (0, 0));
+    }
+
+    #[test]
+    fn most_common_color_first() {
+        // Red sky over a larger blue sea
+        let img = ImageBuffer::from_fn(16, 16, |_, y| Rgb(if y < 4 { RED } else { BLUE }));
+
+        let palette = palette(&img);
+        assert_eq!(palette.colors.len(), 2);
+        assert!(close(&palette.colors[0], &BLUE));
+        assert!(close(&palette.colors[1], &RED));
+        assert_eq!((palette.top, palette.bottom), (1, 0));
+    }
+
+    #[test]
+    fn colors_are_not_averaged() {
+        // The mean of these would be a muddy purple that is in neither half
+        let img = ImageBuffer::from_fn(64, 64, |x, y| {
+            let noise = ((x * 7 + y * 13) % 10) as f32 / 200.;
+            let [r, g, b] = if x < 32 { RED } else { BLUE };
+            Rgb([r + noise, g + noise, b + noise])
+        });
+
+        let palette = palette(&img);
+        assert!(palette.colors.len() >= 2);
+        for color in &palette.colors[..2] {
+            let nearest_half = distance2(color, &RED).min(distance2(color, &BLUE));
+            assert!(nearest_half < 0.01, "{:?}", color);
+        }
+    }
+}
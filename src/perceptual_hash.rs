@@ -0,0 +1,96 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use image::{ImageBuffer, Rgb};
+
+use crate::db::schema::*;
+use crate::id30::Id30;
+
+/// Hashes at most this many bits apart are considered likely duplicates.
+/// Recompression and moderate resizing typically stay well below this,
+/// while different photos rarely come this close.
+pub const DUPLICATE_THRESHOLD: u32 = 10;
+
+/// Compute the difference hash, dHash, of an image in linear light
+///
+/// The image is reduced to 9x8 luminance samples, and each bit tells whether
+/// a sample is brighter than its right neighbour. This is robust against
+/// rescaling, recompression and small color adjustments.
+pub fn dhash(img: &ImageBuffer<Rgb<f32>, Vec<f32>>) -> u64 {
+    let small = image::imageops::resize(img, 9, 8, image::imageops::Triangle);
+
+    let luminance = |x, y| {
+        let px: &Rgb<f32> = small.get_pixel(x, y);
+        0.2126 * px[0] + 0.7152 * px[1] + 0.0722 * px[2]
+    };
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if luminance(x, y) > luminance(x + 1, y) {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Find the pixurs that are likely duplicates of an image with the given
+/// hash, closest first
+pub fn find_duplicates(
+    db_connection: &SqliteConnection,
+    hash: u64,
+) -> Result<Vec<Id30>, diesel::result::Error> {
+    // SQLite has no popcount, so the comparison is done here. Every hash is
+    // loaded and compared, so the cost is linear in the size of the library.
+    // That is fine for a personal collection, but an index such as a BK-tree
+    // would be needed for a much larger one
+    let hashes: Vec<(Id30, Option<i64>)> = pixurs::table
+        .filter(pixurs::perceptual_hash.is_not_null())
+        .select((pixurs::id, pixurs::perceptual_hash))
+        .load(db_connection)?;
+
+    let mut duplicates: Vec<(u32, Id30)> = hashes
+        .into_iter()
+        .filter_map(|(id, other)| Some((distance(hash, other? as u64), id)))
+        .filter(|&(distance, _)| distance <= DUPLICATE_THRESHOLD)
+        .collect();
+    duplicates.sort_by_key(|&(distance, _)| distance);
+
+    Ok(duplicates.into_iter().map(|(_, id)| id).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gradient(width: u32, height: u32, reverse: bool) -> ImageBuffer<Rgb<f32>, Vec<f32>> {
+        ImageBuffer::from_fn(width, height, |x, _| {
+            let v = x as f32 / width as f32;
+            let v = if reverse { 1. - v } else { v };
+            Rgb([v, v, v])
+        })
+    }
+
+    #[test]
+    fn hash_survives_rescaling() {
+        let a = dhash(&gradient(160, 120, true));
+        let b = dhash(&gradient(90, 40, true));
+
+        assert_eq!(a, !0);
+        assert!(distance(a, b) <= DUPLICATE_THRESHOLD);
+    }
+
+    #[test]
+    fn different_images_are_far_apart() {
+        let a = dhash(&gradient(160, 120, true));
+        let b = dhash(&gradient(160, 120, false));
+
+        assert!(distance(a, b) > DUPLICATE_THRESHOLD);
+    }
+}
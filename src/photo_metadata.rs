@@ -0,0 +1,291 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use exif::{Context, Rational, Reader, Tag, Value};
+
+use crate::db::schema::*;
+use crate::id30::Id30;
+
+/// Capture details of a photo, as recorded by the camera in EXIF
+///
+/// All fields are optional, since cameras and editing software differ
+/// wildly in what they record.
+#[derive(Debug, Default, PartialEq, Queryable, Insertable, serde_derive::Serialize)]
+#[table_name = "photo_metadata"]
+pub struct PhotoMetadata {
+    /// Local time at the camera
+    pub taken_at: Option<chrono::NaiveDateTime>,
+    /// Minutes east of UTC of `taken_at`, when known
+    pub taken_at_offset: Option<i32>,
+
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+
+    pub exposure_time: Option<f32>,
+    pub f_number: Option<f32>,
+    pub iso: Option<i32>,
+    pub focal_length: Option<f32>,
+
+    pub gps_latitude: Option<f64>,
+    pub gps_longitude: Option<f64>,
+}
+
+/// The columns of `photo_metadata` that make up `PhotoMetadata`
+pub const COLUMNS: (
+    photo_metadata::taken_at,
+    photo_metadata::taken_at_offset,
+    photo_metadata::camera_make,
+    photo_metadata::camera_model,
+    photo_metadata::lens,
+    photo_metadata::exposure_time,
+    photo_metadata::f_number,
+    photo_metadata::iso,
+    photo_metadata::focal_length,
+    photo_metadata::gps_latitude,
+    photo_metadata::gps_longitude,
+) = (
+    photo_metadata::taken_at,
+    photo_metadata::taken_at_offset,
+    photo_metadata::camera_make,
+    photo_metadata::camera_model,
+    photo_metadata::lens,
+    photo_metadata::exposure_time,
+    photo_metadata::f_number,
+    photo_metadata::iso,
+    photo_metadata::focal_length,
+    photo_metadata::gps_latitude,
+    photo_metadata::gps_longitude,
+);
+
+fn ascii(reader: &Reader, tag: Tag) -> Option<String> {
+    match reader.get_field(tag, false)?.value {
+        Value::Ascii(ref x) => {
+            let s = String::from_utf8_lossy(x.first()?);
+            let s = s.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            if s.is_empty() {
+                None
+            } else {
+                Some(s.to_string())
+            }
+        }
+        _ => None,
+    }
+}
+
+fn rational(reader: &Reader, tag: Tag) -> Option<f64> {
+    match reader.get_field(tag, false)?.value {
+        Value::Rational(ref x) => x.first().filter(|x| x.denom != 0).map(|x| x.to_f64()),
+        _ => None,
+    }
+}
+
+fn degrees(dms: &[Rational]) -> Option<f64> {
+    if dms.len() != 3 || dms.iter().any(|x| x.denom == 0) {
+        return None;
+    }
+
+    Some(dms[0].to_f64() + dms[1].to_f64() / 60. + dms[2].to_f64() / 3600.)
+}
+
+/// GPS coordinates are stored as unsigned degrees, minutes and seconds with
+/// a separate reference tag telling the hemisphere
+fn gps_coordinate(reader: &Reader, tag: Tag, ref_tag: Tag, negative_ref: &[u8]) -> Option<f64> {
+    let degrees = match reader.get_field(tag, false)?.value {
+        Value::Rational(ref x) => degrees(x)?,
+        _ => return None,
+    };
+
+    let negative = match reader.get_field(ref_tag, false)?.value {
+        Value::Ascii(ref x) => x.first()?.starts_with(negative_ref),
+        _ => return None,
+    };
+
+    Some(if negative { -degrees } else { degrees })
+}
+
+impl PhotoMetadata {
+    /// The time of capture as used for sorting, see `pixurs.taken_at`
+    ///
+    /// This is in UTC when the time zone offset is known, and otherwise in
+    /// the local time at the camera, which is the best guess available.
+    pub fn sort_time(&self) -> Option<chrono::NaiveDateTime> {
+        let taken_at = self.taken_at?;
+
+        Some(match self.taken_at_offset {
+            Some(offset) => taken_at - chrono::Duration::minutes(offset as i64),
+            None => taken_at,
+        })
+    }
+}
+
+// Introduced in EXIF 2.31, so not among the tags known to the exif crate
+const OFFSET_TIME_ORIGINAL: Tag = Tag(Context::Exif, 0x9011);
+
+/// Parse a time zone offset on the form `+01:00`, giving minutes east of UTC
+fn parse_offset(src: &[u8]) -> Option<i32> {
+    let src = std::str::from_utf8(src).ok()?.trim_end_matches('\0');
+    if src.len() != 6 || src.as_bytes()[3] != b':' {
+        return None;
+    }
+
+    let sign = match src.as_bytes()[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let hours: i32 = src.get(1..3)?.parse().ok()?;
+    let minutes: i32 = src.get(4..6)?.parse().ok()?;
+
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn taken_at_offset(reader: &Reader) -> Option<i32> {
+    match reader.get_field(OFFSET_TIME_ORIGINAL, false)?.value {
+        Value::Ascii(ref x) => parse_offset(x.first()?),
+        _ => None,
+    }
+}
+
+fn taken_at(reader: &Reader) -> Option<chrono::NaiveDateTime> {
+    let dt = match reader.get_field(Tag::DateTimeOriginal, false)?.value {
+        Value::Ascii(ref x) => exif::DateTime::from_ascii(x.first()?).ok()?,
+        _ => return None,
+    };
+
+    chrono::NaiveDate::from_ymd_opt(dt.year as i32, dt.month as u32, dt.day as u32)?.and_hms_opt(
+        dt.hour as u32,
+        dt.minute as u32,
+        dt.second as u32,
+    )
+}
+
+/// Extract the metadata from the EXIF data of an image file
+///
+/// Files without EXIF data, or in formats that cannot carry it, give all
+/// empty metadata.
+pub fn from_exif(data: &[u8]) -> PhotoMetadata {
+    let reader = match Reader::new(&mut std::io::Cursor::new(data)) {
+        Ok(reader) => reader,
+        Err(_) => return PhotoMetadata::default(),
+    };
+
+    PhotoMetadata {
+        taken_at: taken_at(&reader),
+        taken_at_offset: taken_at_offset(&reader),
+
+        camera_make: ascii(&reader, Tag::Make),
+        camera_model: ascii(&reader, Tag::Model),
+        lens: ascii(&reader, Tag::LensModel),
+
+        exposure_time: rational(&reader, Tag::ExposureTime).map(|x| x as f32),
+        f_number: rational(&reader, Tag::FNumber).map(|x| x as f32),
+        iso: reader
+            .get_field(Tag::PhotographicSensitivity, false)
+            .and_then(|x| x.value.get_uint(0))
+            .map(|x| x as i32),
+        focal_length: rational(&reader, Tag::FocalLength).map(|x| x as f32),
+
+        gps_latitude: gps_coordinate(&reader, Tag::GPSLatitude, Tag::GPSLatitudeRef, b"S"),
+        gps_longitude: gps_coordinate(&reader, Tag::GPSLongitude, Tag::GPSLongitudeRef, b"W"),
+    }
+}
+
+pub fn insert(
+    db_connection: &SqliteConnection,
+    pixurs_id: Id30,
+    metadata: &PhotoMetadata,
+) -> Result<(), diesel::result::Error> {
+    diesel::insert_into(photo_metadata::table)
+        .values((photo_metadata::pixurs_id.eq(pixurs_id), metadata))
+        .execute(db_connection)?;
+
+    Ok(())
+}
+
+/// Extract metadata for all pixurs that have an uploaded original, but no
+/// metadata yet. This covers the pixurs that were uploaded before metadata
+/// was extracted at ingest. Every examined pixur gets metadata, even if it is
+/// empty, so each is examined only once, and later runs find nothing to do.
+///
+/// Pixurs that were uploaded before originals were kept cannot be covered.
+/// They only have renditions, which carry no EXIF, and this holds for the
+/// rendition they adopt as their original when rerendered as well. They are
+/// left without metadata, so `site::image_metadata` can tell that it was
+/// never recorded.
+///
+/// Returns the number of pixurs that were examined.
+pub fn backfill(db_connection: &SqliteConnection) -> Result<usize, diesel::result::Error> {
+    let missing: Vec<Id30> = originals::table
+        .left_join(photo_metadata::table.on(photo_metadata::pixurs_id.eq(originals::pixurs_id)))
+        .filter(originals::adopted.eq(false))
+        .filter(photo_metadata::pixurs_id.is_null())
+        .select(originals::pixurs_id)
+        .load(db_connection)?;
+
+    for &pixurs_id in &missing {
+        let original: Vec<u8> = originals::table
+            .filter(originals::pixurs_id.eq(pixurs_id))
+            .select(originals::data)
+            .first(db_connection)?;
+
+        let metadata = from_exif(&original);
+
+        insert(db_connection, pixurs_id, &metadata)?;
+
+        diesel::update(pixurs::table.filter(pixurs::id.eq(pixurs_id)))
+            .set(pixurs::taken_at.eq(metadata.sort_time()))
+            .execute(db_connection)?;
+    }
+
+    Ok(missing.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn no_exif_gives_empty_metadata() {
+        assert_eq!(from_exif(b"\x89PNG\r\n\x1a\n"), PhotoMetadata::default());
+    }
+
+    #[test]
+    fn degrees_from_dms() {
+        let r = |num, denom| Rational { num, denom };
+
+        let d = degrees(&[r(59, 1), r(54, 1), r(4530, 100)]).unwrap();
+        assert!((d - 59.912583).abs() < 1e-6);
+
+        assert_eq!(degrees(&[r(59, 1), r(54, 0), r(0, 1)]), None);
+        assert_eq!(degrees(&[r(59, 1)]), None);
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_offset(b"+02:00"), Some(120));
+        assert_eq!(parse_offset(b"-03:30\0"), Some(-210));
+        assert_eq!(parse_offset(b"   :  "), None);
+        assert_eq!(parse_offset(b"02:00"), None);
+    }
+
+    #[test]
+    fn sort_time_is_utc_when_offset_is_known() {
+        let local = chrono::NaiveDate::from_ymd(2020, 9, 5).and_hms(10, 15, 0);
+
+        let mut metadata = PhotoMetadata {
+            taken_at: Some(local),
+            ..Default::default()
+        };
+        assert_eq!(metadata.sort_time(), Some(local));
+
+        metadata.taken_at_offset = Some(120);
+        assert_eq!(
+            metadata.sort_time(),
+            Some(chrono::NaiveDate::from_ymd(2020, 9, 5).and_hms(8, 15, 0))
+        );
+    }
+}
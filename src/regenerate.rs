@@ -0,0 +1,315 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::image;
+use crate::rendition_policy::RenditionPolicy;
+
+/// The outcome of the regenerate command
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub regenerated: usize,
+
+    /// Done by an earlier run that was interrupted
+    pub already_done: usize,
+
+    /// Left for the next run to retry
+    pub failed: usize,
+}
+
+/// The pixurs that are left in the current run, oldest first, and the
+/// number of pixurs that are done
+fn remaining(db_connection: &SqliteConnection) -> QueryResult<(Vec<Id30>, usize)> {
+    let remaining = pixurs::table
+        .filter(diesel::dsl::not(diesel::dsl::exists(
+            regenerated_pixurs::table.filter(regenerated_pixurs::pixurs_id.eq(pixurs::id)),
+        )))
+        .order(pixurs::created.asc())
+        .select(pixurs::id)
+        .load(db_connection)?;
+
+    let done: i64 = regenerated_pixurs::table
+        .count()
+        .get_result(db_connection)?;
+
+    Ok((remaining, done as usize))
+}
+
+fn mark_done(db_connection: &SqliteConnection, pixurs_id: Id30) -> QueryResult<()> {
+    diesel::insert_into(regenerated_pixurs::table)
+        .values(regenerated_pixurs::pixurs_id.eq(pixurs_id))
+        .execute(db_connection)?;
+
+    Ok(())
+}
+
+/// Forget the progress, so the next run starts over
+fn reset(db_connection: &SqliteConnection) -> QueryResult<()> {
+    diesel::delete(regenerated_pixurs::table).execute(db_connection)?;
+
+    Ok(())
+}
+
+/// Regenerate a pixur and mark it as done in the same transaction, so an
+/// interrupted run neither skips nor repeats it
+fn regenerate_one(
+    pixurs_id: Id30,
+    db_pool: &Pool<ConnectionManager<SqliteConnection>>,
+    policy: &RenditionPolicy,
+) -> Result<(), String> {
+    // A panic must only fail this pixur
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let db_connection = db_pool.get().map_err(|err| err.to_string())?;
+
+        let rendered = image::render_pixur(pixurs_id, policy, &*db_connection)
+            .map_err(|err| err.to_string())?;
+
+        // Immediate, so the transaction waits for other writers up front
+        // rather than failing when it goes from reading to writing
+        db_connection
+            .immediate_transaction(|| -> Result<(), Box<dyn std::error::Error>> {
+                image::store_rerendered(&*db_connection, &rendered)?;
+                Ok(mark_done(&*db_connection, pixurs_id)?)
+            })
+            .map_err(|err| err.to_string())
+    }))
+    .unwrap_or_else(|_| Err("Regenerating panicked".to_string()))
+}
+
+/// Apply the rendition policy to all existing pixurs, see `image::rerender`,
+/// with the given number of pixurs in progress at a time. Each of these
+/// holds its photo, decoded, in memory
+///
+/// The progress is kept in the database, so a run that is interrupted
+/// resumes where it left off, unless `restart` is given. Pixurs that fail
+/// are logged and left for the next run to retry. The progress is forgotten
+/// when a run completes without failures.
+pub fn regenerate(
+    db_pool: &Pool<ConnectionManager<SqliteConnection>>,
+    policy: &RenditionPolicy,
+    jobs: usize,
+    restart: bool,
+) -> Result<Summary, Box<dyn std::error::Error>> {
+    // Every job needs a connection of its own
+    let max_jobs = db_pool.max_size() as usize;
+    if jobs == 0 || jobs > max_jobs {
+        return Err(format!("The number of jobs must be 1-{}", max_jobs).into());
+    }
+
+    let db_connection = db_pool.get()?;
+    if restart {
+        reset(&*db_connection)?;
+    }
+    let (remaining, already_done) = remaining(&*db_connection)?;
+    drop(db_connection);
+
+    let total = already_done + remaining.len();
+    if already_done > 0 {
+        eprintln!(
+            "REG: Resuming, {} of {} pixurs are already done",
+            already_done, total
+        );
+    }
+
+    let queue = Arc::new(Mutex::new(remaining.into_iter()));
+    let (sender, receiver) = mpsc::channel();
+
+    let mut workers = vec![];
+    for i in 0..jobs {
+        let queue = queue.clone();
+        let sender = sender.clone();
+        let db_pool = db_pool.clone();
+        let policy = policy.clone();
+
+        workers.push(
+            std::thread::Builder::new()
+                .name(format!("regenerate-{}", i))
+                .spawn(move || loop {
+                    let next = queue.lock().unwrap().next();
+                    let pixurs_id = match next {
+                        Some(pixurs_id) => pixurs_id,
+                        None => break,
+                    };
+
+                    let result = regenerate_one(pixurs_id, &db_pool, &policy);
+                    if sender.send((pixurs_id, result)).is_err() {
+                        break;
+                    }
+                })?,
+        );
+    }
+    drop(sender);
+
+    let mut summary = Summary {
+        already_done,
+        ..Default::default()
+    };
+
+    for (pixurs_id, result) in receiver {
+        let position = already_done + summary.regenerated + summary.failed + 1;
+        match result {
+            Ok(()) => {
+                summary.regenerated += 1;
+                eprintln!("REG: Regenerated {} ({}/{})", pixurs_id, position, total);
+            }
+            Err(err) => {
+                summary.failed += 1;
+                eprintln!(
+                    "REG: Failed to regenerate {} ({}/{}): {}",
+                    pixurs_id, position, total, err
+                );
+            }
+        }
+    }
+
+    for worker in workers {
+        worker.join().map_err(|_| "A regenerate worker panicked")?;
+    }
+
+    if summary.failed == 0 {
+        reset(&*db_pool.get()?)?;
+    }
+
+    Ok(summary)
+}
+
+/// Log what `regenerate` would render each pixur from, without rendering or
+/// storing anything. Pixurs that would fail for want of a supported source
+/// are counted as failed
+pub fn dry_run(
+    db_connection: &SqliteConnection,
+    restart: bool,
+) -> Result<Summary, Box<dyn std::error::Error>> {
+    let (remaining, already_done) = if restart {
+        let all = pixurs::table
+            .order(pixurs::created.asc())
+            .select(pixurs::id)
+            .load(db_connection)?;
+        (all, 0)
+    } else {
+        remaining(db_connection)?
+    };
+
+    let mut summary = Summary {
+        already_done,
+        ..Default::default()
+    };
+
+    for pixurs_id in remaining {
+        let original: Option<String> = originals::table
+            .filter(originals::pixurs_id.eq(pixurs_id))
+            .select(originals::media_type)
+            .first(db_connection)
+            .optional()?;
+
+        // See `image::render_pixur`
+        let source = match original {
+            Some(media_type) => Some(("the original", media_type)),
+            None => images_meta::table
+                .inner_join(images::table)
+                .filter(images_meta::pixurs_id.eq(pixurs_id))
+                .order(images_meta::width.desc())
+                .select(images::media_type)
+                .first(db_connection)
+                .optional()?
+                .map(|media_type| {
+                    (
+                        "the largest image, which is kept as the original",
+                        media_type,
+                    )
+                }),
+        };
+
+        match source {
+            Some((source, media_type)) if image::image_format(&media_type).is_some() => {
+                summary.regenerated += 1;
+                eprintln!(
+                    "REG: Would regenerate {} from {} ({})",
+                    pixurs_id, source, media_type
+                );
+            }
+            Some((_, media_type)) => {
+                summary.failed += 1;
+                eprintln!(
+                    "REG: Would fail to regenerate {}: Unsupported media type {}",
+                    pixurs_id, media_type
+                );
+            }
+            None => {
+                summary.failed += 1;
+                eprintln!("REG: Would fail to regenerate {}: No images", pixurs_id);
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::test_connection;
+
+    fn insert_pixur(conn: &SqliteConnection, id: u32, created: chrono::NaiveDateTime) -> Id30 {
+        let id = Id30::from(id);
+
+        diesel::insert_into(thumbs::table)
+            .values((
+                thumbs::id.eq(id),
+                thumbs::media_type.eq("image/jpeg"),
+                thumbs::data.eq(&b""[..]),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        diesel::insert_into(pixurs::table)
+            .values((
+                pixurs::id.eq(id),
+                pixurs::average_color.eq(0),
+                pixurs::thumbs_id.eq(id),
+                pixurs::created.eq(created),
+                pixurs::image_aspect_ratio.eq(1.),
+                pixurs::crop_left.eq(0.5),
+                pixurs::crop_right.eq(0.5),
+                pixurs::crop_top.eq(0.5),
+                pixurs::crop_bottom.eq(0.5),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        id
+    }
+
+    #[test]
+    fn resume_where_an_interrupted_run_left_off() {
+        let conn = test_connection();
+        let day = |d| chrono::NaiveDate::from_ymd(2020, 12, d).and_hms(12, 0, 0);
+        let a = insert_pixur(&conn, 1, day(1));
+        let c = insert_pixur(&conn, 3, day(3));
+        let b = insert_pixur(&conn, 2, day(2));
+
+        assert_eq!(remaining(&conn).unwrap(), (vec![a, b, c], 0));
+
+        mark_done(&conn, b).unwrap();
+        assert_eq!(remaining(&conn).unwrap(), (vec![a, c], 1));
+
+        // None of them have any images to regenerate from
+        assert_eq!(
+            dry_run(&conn, false).unwrap(),
+            Summary {
+                regenerated: 0,
+                already_done: 1,
+                failed: 2,
+            }
+        );
+        assert_eq!(dry_run(&conn, true).unwrap().failed, 3);
+        assert_eq!(remaining(&conn).unwrap(), (vec![a, c], 1));
+
+        reset(&conn).unwrap();
+        assert_eq!(remaining(&conn).unwrap(), (vec![a, b, c], 0));
+    }
+}
@@ -0,0 +1,466 @@
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::sqlite::Sqlite;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::deep_zoom::DeepZoom;
+use crate::unsharp_mask::UnsharpMask;
+
+/// What a rendition is used for
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, AsExpression, FromSqlRow,
+)]
+#[serde(rename_all = "snake_case")]
+#[sql_type = "Text"]
+pub enum Role {
+    /// Shown in the index and in notification emails, and while the photo
+    /// loads. Stored in `thumbs`, the other roles are stored in `images`
+    Thumb,
+
+    /// Offered to the viewer in `srcset`
+    Display,
+
+    /// Offered for downloading from the viewer
+    Download,
+}
+
+impl ToSql<Text, Sqlite> for Role {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        let s = match self {
+            Role::Thumb => "thumb",
+            Role::Display => "display",
+            Role::Download => "download",
+        };
+        ToSql::<Text, Sqlite>::to_sql(s, out)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Role {
+    fn from_sql(value: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        // See comment_position.rs
+        let text_ptr = <*const str as FromSql<Text, Sqlite>>::from_sql(value)?;
+        let text = unsafe { &*text_ptr };
+        match text {
+            "thumb" => Ok(Role::Thumb),
+            "display" => Ok(Role::Display),
+            "download" => Ok(Role::Download),
+            _ => Err("Invalid value in database".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Jpeg,
+    Webp,
+}
+
+impl Format {
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::Jpeg => "image/jpeg",
+            Format::Webp => "image/webp",
+        }
+    }
+}
+
+fn default_format() -> Format {
+    Format::Jpeg
+}
+
+fn default_alternates() -> Vec<Format> {
+    vec![Format::Webp]
+}
+
+/// A kind of image that is rendered from each photo
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rendition {
+    pub role: Role,
+
+    /// The rendition is scaled down to fit within these, keeping the aspect
+    /// ratio. It is never scaled up
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+
+    /// Served to clients that accept none of the alternates
+    #[serde(default = "default_format")]
+    pub format: Format,
+
+    /// Typically more efficient formats that are not universally supported.
+    /// Served to clients that accept them
+    #[serde(default = "default_alternates")]
+    pub alternates: Vec<Format>,
+
+    /// 1-100, used for all formats
+    pub quality: u8,
+
+    /// Halve the chroma resolution in both directions, 4:2:0, for JPEG.
+    /// This gives smaller files, at the cost of color bleeding along sharp
+    /// edges
+    #[serde(default)]
+    pub chroma_subsampling: bool,
+
+    /// Defaults by role, see `Rendition::sharpening`
+    #[serde(default)]
+    pub sharpening: Option<UnsharpMask>,
+}
+
+// Lanczos3 downscaling leaves the renditions looking soft, so they are
+// sharpened in linear light before being converted to sRGB
+const DISPLAY_SHARPENING: UnsharpMask = UnsharpMask {
+    radius: 0.8,
+    amount: 0.4,
+};
+
+const SMALL_SHARPENING: UnsharpMask = UnsharpMask {
+    radius: 0.5,
+    amount: 0.6,
+};
+
+/// The blur kernel grows with the radius, and so does the time it takes
+const MAX_SHARPENING_RADIUS: f32 = 10.;
+
+impl Rendition {
+    /// How the rendition is sharpened after downscaling, as configured or
+    /// by default for its role
+    pub fn sharpening(&self) -> UnsharpMask {
+        self.sharpening.unwrap_or(match self.role {
+            Role::Thumb => SMALL_SHARPENING,
+            Role::Display | Role::Download => DISPLAY_SHARPENING,
+        })
+    }
+
+    /// The size of this rendition of a photo with the given size
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (w, h) = (width as u64, height as u64);
+        let max_width = self.max_width.unwrap_or(width) as u64;
+        let max_height = self.max_height.unwrap_or(height) as u64;
+
+        if max_width >= w && max_height >= h {
+            (width, height)
+        } else if max_width * h <= max_height * w {
+            (max_width as u32, (max_width * h / w).max(1) as u32)
+        } else {
+            ((max_height * w / h).max(1) as u32, max_height as u32)
+        }
+    }
+
+    /// The formats to encode in, starting with the one that is served by
+    /// default
+    pub fn formats(&self) -> Vec<Format> {
+        let mut formats = vec![self.format];
+        for &format in &self.alternates {
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        formats
+    }
+}
+
+/// A rendition, sized for a particular photo
+#[derive(Debug)]
+pub struct Target<'a> {
+    pub name: &'a str,
+    pub rendition: &'a Rendition,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The renditions that are rendered from each photo, by name, as configured
+/// in the `[renditions]` section of config.toml
+///
+/// A changed policy applies to new uploads. Existing pixurs are updated by
+/// the `regenerate` command, or when they are edited.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct RenditionPolicy {
+    renditions: BTreeMap<String, Rendition>,
+
+    /// Configured separately, see `with_deep_zoom`
+    #[serde(skip)]
+    deep_zoom: Option<DeepZoom>,
+}
+
+impl Default for RenditionPolicy {
+    fn default() -> Self {
+        let mut renditions = BTreeMap::new();
+
+        // Only meant to be looked at briefly while the display rendition loads
+        renditions.insert(
+            "thumb".to_string(),
+            Rendition {
+                role: Role::Thumb,
+                max_width: Some(160),
+                max_height: None,
+                format: Format::Jpeg,
+                alternates: vec![Format::Webp],
+                quality: 20,
+                chroma_subsampling: true,
+                sharpening: None,
+            },
+        );
+
+        for &width in &[640, 1280, 1920, 2560, 3840] {
+            renditions.insert(
+                format!("display-{}", width),
+                Rendition {
+                    role: Role::Display,
+                    max_width: Some(width),
+                    max_height: None,
+                    format: Format::Jpeg,
+                    alternates: vec![Format::Webp],
+                    quality: 80,
+                    chroma_subsampling: false,
+                    sharpening: None,
+                },
+            );
+        }
+
+        RenditionPolicy {
+            renditions,
+            deep_zoom: None,
+        }
+    }
+}
+
+impl RenditionPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, rendition) in &self.renditions {
+            if rendition.quality < 1 || rendition.quality > 100 {
+                return Err(format!("Rendition {}: quality must be 1-100", name));
+            }
+            if rendition.max_width == Some(0) || rendition.max_height == Some(0) {
+                return Err(format!("Rendition {}: maximum size must be positive", name));
+            }
+            if let Some(UnsharpMask { radius, amount }) = rendition.sharpening {
+                if !(radius > 0. && radius <= MAX_SHARPENING_RADIUS) {
+                    return Err(format!(
+                        "Rendition {}: sharpening radius must be above 0 and at most {}",
+                        name, MAX_SHARPENING_RADIUS
+                    ));
+                }
+                if !(amount >= 0. && amount.is_finite()) {
+                    return Err(format!(
+                        "Rendition {}: sharpening amount must be 0 or more",
+                        name
+                    ));
+                }
+            }
+        }
+
+        let count = |role| self.renditions.values().filter(|x| x.role == role).count();
+
+        if count(Role::Thumb) != 1 {
+            return Err("There must be exactly one rendition with role thumb".to_string());
+        }
+        if count(Role::Display) == 0 {
+            return Err("There must be at least one rendition with role display".to_string());
+        }
+
+        if let Some(deep_zoom) = &self.deep_zoom {
+            deep_zoom.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Also make tile pyramids of the photos that fit the given deep zoom
+    /// policy, from the `[deep_zoom]` section of config.toml
+    pub fn with_deep_zoom(self, deep_zoom: Option<DeepZoom>) -> RenditionPolicy {
+        RenditionPolicy { deep_zoom, ..self }
+    }
+
+    /// The deep zoom policy, if a photo with the given size gets a tile
+    /// pyramid
+    pub fn deep_zoom(&self, width: u32, height: u32) -> Option<&DeepZoom> {
+        self.deep_zoom.as_ref().filter(|x| x.applies(width, height))
+    }
+
+    /// The thumbnail of a photo with the given size
+    pub fn thumb(&self, width: u32, height: u32) -> Target<'_> {
+        let (name, rendition) = self
+            .renditions
+            .iter()
+            .find(|(_, x)| x.role == Role::Thumb)
+            .expect("The policy has been validated");
+
+        let (width, height) = rendition.size(width, height);
+        Target {
+            name,
+            rendition,
+            width,
+            height,
+        }
+    }
+
+    /// The display and download renditions of a photo with the given size,
+    /// ordered by role and size
+    ///
+    /// A photo that is smaller than the maximum size of several renditions
+    /// of the same role would get identical renditions. Only the first of
+    /// these, by name, is kept.
+    pub fn targets(&self, width: u32, height: u32) -> Vec<Target<'_>> {
+        let mut targets: Vec<Target> = self
+            .renditions
+            .iter()
+            .filter(|(_, x)| x.role != Role::Thumb)
+            .map(|(name, rendition)| {
+                let (width, height) = rendition.size(width, height);
+                Target {
+                    name,
+                    rendition,
+                    width,
+                    height,
+                }
+            })
+            .collect();
+
+        targets.sort_by_key(|x| (x.rendition.role, x.width, x.height));
+        targets.dedup_by_key(|x| (x.rendition.role, x.width, x.height));
+
+        targets
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn display_widths(policy: &RenditionPolicy, width: u32) -> Vec<u32> {
+        policy
+            .targets(width, width / 2)
+            .iter()
+            .map(|x| x.width)
+            .collect()
+    }
+
+    #[test]
+    fn default_policy() {
+        let policy = RenditionPolicy::default();
+        assert!(policy.validate().is_ok());
+
+        assert_eq!(
+            display_widths(&policy, 4032),
+            vec![640, 1280, 1920, 2560, 3840]
+        );
+        assert_eq!(display_widths(&policy, 1600), vec![640, 1280, 1600]);
+        assert_eq!(display_widths(&policy, 1280), vec![640, 1280]);
+        assert_eq!(display_widths(&policy, 320), vec![320]);
+
+        assert_eq!(policy.thumb(4032, 3024).width, 160);
+    }
+
+    #[test]
+    fn sizes_fit_within_the_maximum() {
+        let rendition = |max_width, max_height| Rendition {
+            role: Role::Display,
+            max_width,
+            max_height,
+            format: Format::Jpeg,
+            alternates: vec![],
+            quality: 80,
+            chroma_subsampling: false,
+            sharpening: None,
+        };
+
+        assert_eq!(rendition(Some(1000), None).size(4000, 3000), (1000, 750));
+        assert_eq!(rendition(None, Some(1000)).size(3000, 4000), (750, 1000));
+        assert_eq!(
+            rendition(Some(1000), Some(1000)).size(3000, 4000),
+            (750, 1000)
+        );
+        assert_eq!(rendition(Some(1000), Some(1000)).size(500, 400), (500, 400));
+        assert_eq!(rendition(None, None).size(4000, 3000), (4000, 3000));
+    }
+
+    #[test]
+    fn parse_from_config() {
+        let policy: RenditionPolicy = toml::from_str(
+            r#"
+            [thumb]
+            role = "thumb"
+            max_width = 200
+            quality = 30
+
+            [large]
+            role = "display"
+            max_width = 2048
+            max_height = 2048
+            format = "webp"
+            alternates = []
+            quality = 85
+            sharpening = { radius = 1.2, amount = 0.3 }
+
+            [full]
+            role = "download"
+            quality = 95
+            "#,
+        )
+        .unwrap();
+
+        assert!(policy.validate().is_ok());
+
+        let large = &policy.renditions["large"];
+        assert_eq!(large.formats(), vec![Format::Webp]);
+        assert_eq!(
+            large.sharpening(),
+            UnsharpMask {
+                radius: 1.2,
+                amount: 0.3
+            }
+        );
+        assert_eq!(policy.renditions["thumb"].sharpening(), SMALL_SHARPENING);
+        assert_eq!(
+            policy.renditions["full"].formats(),
+            vec![Format::Jpeg, Format::Webp]
+        );
+
+        let targets = policy.targets(4000, 3000);
+        let sizes: Vec<_> = targets
+            .iter()
+            .map(|x| (x.name, x.width, x.height))
+            .collect();
+        assert_eq!(sizes, vec![("large", 2048, 1536), ("full", 4000, 3000)]);
+    }
+
+    #[test]
+    fn invalid_policies() {
+        let parse = |toml| toml::from_str::<RenditionPolicy>(toml).unwrap();
+
+        let no_thumb = parse("[large]\nrole = \"display\"\nquality = 80");
+        assert!(no_thumb.validate().is_err());
+
+        let no_display = parse("[thumb]\nrole = \"thumb\"\nquality = 80");
+        assert!(no_display.validate().is_err());
+
+        let bad_quality = parse(
+            "[thumb]\nrole = \"thumb\"\nquality = 0\n[large]\nrole = \"display\"\nquality = 80",
+        );
+        assert!(bad_quality.validate().is_err());
+
+        let sharpening = |settings| {
+            toml::from_str::<RenditionPolicy>(&format!(
+                "[thumb]\nrole = \"thumb\"\nquality = 20\nsharpening = {}\n\
+                 [large]\nrole = \"display\"\nquality = 80",
+                settings
+            ))
+            .unwrap()
+        };
+        assert!(sharpening("{ radius = 1.0, amount = 0.0 }")
+            .validate()
+            .is_ok());
+        assert!(sharpening("{ radius = 0.0, amount = 0.5 }")
+            .validate()
+            .is_err());
+        assert!(sharpening("{ radius = 1.0, amount = -0.5 }")
+            .validate()
+            .is_err());
+    }
+}
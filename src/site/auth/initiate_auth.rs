@@ -221,6 +221,7 @@ impl<S: Spawn + Send + 'static> InitiateAuth<S> {
                 }),
             )],
             cookies: vec![cookie],
+            content_disposition: None,
         })
     }
 }
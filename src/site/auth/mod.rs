@@ -107,6 +107,7 @@ mod test {
                 etag: None,
                 get: Some(Box::new(Qr)),
                 post: None,
+                put: None,
             };
             let c = AuthorizationHandler { ok };
             let a = Box::new(JwtCookieHandler::new(KEY.into(), c));
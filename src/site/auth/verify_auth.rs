@@ -94,6 +94,7 @@ impl VerifyAuth {
                 }),
             )],
             cookies,
+            content_disposition: None,
         })
     }
 }
@@ -136,6 +137,7 @@ impl CookieHandler for VerifyAuthCookieHandler {
                 head_sign: cookie,
             })),
             post: None,
+            put: None,
         })
     }
 }
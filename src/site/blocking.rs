@@ -0,0 +1,27 @@
+use futures::channel::oneshot;
+
+use super::handling_error::HandlingError;
+
+/// Run CPU heavy work, such as rendering, on a thread of its own, so it does
+/// not hold up the other requests on the executor
+pub async fn run<T: Send + 'static>(
+    name: &str,
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, HandlingError> {
+    let (sender, receiver) = oneshot::channel();
+
+    std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            let _ = sender.send(work());
+        })
+        .map_err(|e| {
+            eprintln!("Unable to start {}: {}", name, e);
+            HandlingError::InternalServerError
+        })?;
+
+    // The sender is dropped without sending if the work panics
+    receiver
+        .await
+        .map_err(|_| HandlingError::InternalServerError)
+}
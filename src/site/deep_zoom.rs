@@ -0,0 +1,198 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use web::{Get, MediaType, RepresentationBox, Resource, Response};
+
+use super::auth;
+use super::download;
+use super::handling_error::HandlingError;
+use crate::db::schema::*;
+use crate::deep_zoom;
+use crate::id30::Id30;
+
+/// The DZI descriptor of the tile pyramid of a pixur. The tiles are found
+/// next to it, see `Tile`
+pub struct Descriptor {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    id: Id30,
+}
+
+impl Descriptor {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let (pyramid, media_type) = deep_zoom::load(&*db_connection, self.id)
+            .map_err(|_| HandlingError::InternalServerError)?
+            .ok_or(HandlingError::BadRequest("The photo has no tiles"))?;
+
+        let descriptor = pyramid.descriptor(download::extension(&media_type));
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::new("application", "xml", vec!["charset=utf-8".to_string()]),
+                Box::new(move || Box::new(descriptor) as RepresentationBox),
+            )],
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for Descriptor {
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// A tile in the pyramid of a pixur, by level, column and row
+pub struct Tile {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    id: Id30,
+    level: i32,
+    col: i32,
+    row: i32,
+}
+
+impl Tile {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let (media_type, data): (String, Vec<u8>) = deep_zoom_tiles::table
+            .inner_join(deep_zoom_images::table)
+            .filter(deep_zoom_tiles::pixurs_id.eq(self.id))
+            .filter(deep_zoom_tiles::level.eq(self.level))
+            .filter(deep_zoom_tiles::col.eq(self.col))
+            .filter(deep_zoom_tiles::row.eq(self.row))
+            .select((deep_zoom_images::media_type, deep_zoom_tiles::data))
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| HandlingError::InternalServerError)?
+            .ok_or(HandlingError::BadRequest("No such tile"))?;
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::parse(&media_type),
+                Box::new(move || Box::new(data) as RepresentationBox),
+            )],
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for Tile {
+    fn cache_control(&self) -> web::CacheControl {
+        // Not immutable, since the tiles are replaced when the pixur is
+        // rerendered
+        web::CacheControl {
+            cacheability: web::Cacheability {
+                private: true,
+                policy: web::CacheabilityPolicy::AllowCaching,
+            },
+            max_age: None,
+            revalidation: Default::default(),
+        }
+    }
+
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// What is requested of the pyramid of a pixur
+pub enum Part {
+    Descriptor,
+    Tile { level: i32, col: i32, row: i32 },
+}
+
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub id: Id30,
+    pub part: Part,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = ();
+
+    fn authorization(self, _: ()) -> Result<Resource, web::Error> {
+        let get: Box<dyn Get + Send> = match self.part {
+            Part::Descriptor => Box::new(Descriptor {
+                title: self.title,
+                db_pool: self.db_pool,
+                id: self.id,
+            }),
+            Part::Tile { level, col, row } => Box::new(Tile {
+                title: self.title,
+                db_pool: self.db_pool,
+                id: self.id,
+                level,
+                col,
+                row,
+            }),
+        };
+
+        Ok(Resource {
+            etag: None,
+            get: Some(get),
+            post: None,
+            put: None,
+        })
+    }
+}
+
+/// Authorizes like `image::AuthorizationProvider`, by membership in a series
+/// the user can see
+pub struct AuthorizationProvider {
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub id: Id30,
+}
+
+impl auth::authorizer::Provider for AuthorizationProvider {
+    type Authorization = ();
+
+    fn get_authorization(&self, sub: &str) -> Result<Option<Self::Authorization>, web::Error> {
+        use diesel::dsl::*;
+
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        let is_uploader = select(exists(uploaders::table.filter(uploaders::sub.eq(sub))))
+            .first(&*db_connection)
+            .expect("Query must return 1 result");
+
+        let authorized = is_uploader
+            || select(exists(
+                pixur_series_authorizations::table
+                    .inner_join(
+                        pixur_series::table
+                            .on(pixur_series::id.eq(pixur_series_authorizations::pixur_series_id)),
+                    )
+                    .filter(pixur_series::pixurs_id.eq(self.id))
+                    .filter(pixur_series_authorizations::sub.eq(sub)),
+            ))
+            .first(&*db_connection)
+            .expect("Query must return 1 result");
+
+        if authorized {
+            Ok(Some(()))
+        } else {
+            Ok(None)
+        }
+    }
+}
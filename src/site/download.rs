@@ -0,0 +1,231 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use web::{ContentDisposition, Get, MediaType, RepresentationBox, Resource, Response};
+
+use super::auth;
+use super::handling_error::HandlingError;
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::rendition_policy::Role;
+
+/// A photo offered as a file to save. This is the original upload when the
+/// series allows it, see `pixur_series_settings`, and otherwise the largest
+/// rendition
+///
+/// The original is served as it was uploaded. Edits, see `pixur_edits`, are
+/// not applied to it, and its metadata is kept, including the location in
+/// EXIF and XMP. The series setting says as much. The renditions have the
+/// edits applied and carry no metadata.
+pub struct Download {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    id: Id30,
+    original: bool,
+}
+
+pub fn extension(media_type: &str) -> &'static str {
+    match media_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/tiff" => "tif",
+        "image/webp" => "webp",
+        _ => "bin",
+    }
+}
+
+/// Named by the time of capture when it is known, so the file sorts along
+/// with other photos from the same time
+pub fn filename(id: Id30, taken_at: Option<chrono::NaiveDateTime>, media_type: &str) -> String {
+    match taken_at {
+        Some(taken_at) => format!(
+            "{}_{}.{}",
+            taken_at.format("%Y%m%d_%H%M%S"),
+            id,
+            extension(media_type)
+        ),
+        None => format!("{}.{}", id, extension(media_type)),
+    }
+}
+
+/// The media type and data of the largest rendition that is good for keeping
+pub fn largest_rendition(
+    db_connection: &SqliteConnection,
+    id: Id30,
+) -> QueryResult<(String, Vec<u8>)> {
+    images_meta::table
+        .inner_join(images::table)
+        .filter(images_meta::pixurs_id.eq(id))
+        .filter(images_meta::role.eq_any(vec![Role::Display, Role::Download]))
+        .order(images_meta::width.desc())
+        .select((images::media_type, images::data))
+        .first(db_connection)
+}
+
+impl Download {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let taken_at: Option<chrono::NaiveDateTime> = pixurs::table
+            .filter(pixurs::id.eq(self.id))
+            .select(pixurs::taken_at)
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| HandlingError::InternalServerError)?
+            .ok_or(HandlingError::BadRequest("No such photo"))?;
+
+        let original: Option<(String, Vec<u8>)> = if self.original {
+            originals::table
+                .filter(originals::pixurs_id.eq(self.id))
+                .select((originals::media_type, originals::data))
+                .first(&*db_connection)
+                .optional()
+                .map_err(|_| HandlingError::InternalServerError)?
+        } else {
+            None
+        };
+
+        let (media_type, data) = match original {
+            Some(original) => original,
+            None => largest_rendition(&*db_connection, self.id)
+                .map_err(|_| HandlingError::InternalServerError)?,
+        };
+
+        let filename = filename(self.id, taken_at, &media_type);
+
+        Ok(Response {
+            status: web::Status::Ok,
+            representations: vec![(
+                MediaType::parse(&media_type),
+                Box::new(move || Box::new(data) as RepresentationBox),
+            )],
+            cookies: vec![],
+            content_disposition: Some(ContentDisposition { filename }),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for Download {
+    fn cache_control(&self) -> web::CacheControl {
+        web::CacheControl {
+            cacheability: web::Cacheability {
+                private: true,
+                policy: web::CacheabilityPolicy::NoStore,
+            },
+            max_age: None,
+            revalidation: Default::default(),
+        }
+    }
+
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// What a user may download of a photo
+pub struct CanDownload {
+    original: bool,
+}
+
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub id: Id30,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = CanDownload;
+
+    fn authorization(self, can_download: CanDownload) -> Result<Resource, web::Error> {
+        Ok(Resource {
+            etag: None,
+            get: Some(Box::new(Download {
+                title: self.title,
+                db_pool: self.db_pool,
+                id: self.id,
+                original: can_download.original,
+            })),
+            post: None,
+            put: None,
+        })
+    }
+}
+
+/// Authorizes like `image::AuthorizationProvider`, by membership in a
+/// series the user can see. The original may be downloaded by uploaders, and
+/// by recipients when any of those series allow it
+pub struct AuthorizationProvider {
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub id: Id30,
+}
+
+impl auth::authorizer::Provider for AuthorizationProvider {
+    type Authorization = CanDownload;
+
+    fn get_authorization(&self, sub: &str) -> Result<Option<Self::Authorization>, web::Error> {
+        use diesel::dsl::*;
+
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        let is_uploader = select(exists(uploaders::table.filter(uploaders::sub.eq(sub))))
+            .first(&*db_connection)
+            .expect("Query must return 1 result");
+
+        if is_uploader {
+            return Ok(Some(CanDownload { original: true }));
+        }
+
+        let series: Vec<Id30> = pixur_series_authorizations::table
+            .inner_join(
+                pixur_series::table
+                    .on(pixur_series::id.eq(pixur_series_authorizations::pixur_series_id)),
+            )
+            .filter(pixur_series::pixurs_id.eq(self.id))
+            .filter(pixur_series_authorizations::sub.eq(sub))
+            .select(pixur_series::id)
+            .load(&*db_connection)
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        if series.is_empty() {
+            return Ok(None);
+        }
+
+        let original = select(exists(
+            pixur_series_settings::table
+                .filter(pixur_series_settings::pixur_series_id.eq_any(series))
+                .filter(pixur_series_settings::allow_original_download.eq(true)),
+        ))
+        .first(&*db_connection)
+        .expect("Query must return 1 result");
+
+        Ok(Some(CanDownload { original }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filename_from_time_of_capture() {
+        let id: Id30 = "qnpsq4".parse().unwrap();
+        let taken_at = chrono::NaiveDate::from_ymd(2020, 9, 5).and_hms(10, 15, 0);
+
+        assert_eq!(
+            filename(id, Some(taken_at), "image/jpeg"),
+            "20200905_101500_qnpsq4.jpg"
+        );
+        assert_eq!(filename(id, None, "image/png"), "qnpsq4.png");
+    }
+}
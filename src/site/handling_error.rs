@@ -3,6 +3,7 @@ use web::{MediaType, RepresentationBox, Response, Status};
 #[derive(Debug)]
 pub enum HandlingError {
     BadRequest(&'static str),
+    PayloadTooLarge(&'static str),
     InternalServerError,
 }
 
@@ -13,6 +14,13 @@ struct BadRequest<'a> {
     details: &'a str,
 }
 
+#[derive(BartDisplay)]
+#[template = "templates/err/payload-too-large.html"]
+struct PayloadTooLarge<'a> {
+    title: &'a str,
+    details: &'a str,
+}
+
 #[derive(BartDisplay)]
 #[template = "templates/err/internal-server-error.html"]
 struct InternalServerError<'a> {
@@ -33,6 +41,18 @@ impl HandlingError {
                     )],
                 )
             }
+            HandlingError::PayloadTooLarge(details) => {
+                let body =
+                    Box::new(PayloadTooLarge { title, details }.to_string()) as RepresentationBox;
+
+                Response::new(
+                    Status::PayloadTooLarge,
+                    vec![(
+                        MediaType::new("text", "html", vec!["charset=utf-8".to_string()]),
+                        Box::new(move || body),
+                    )],
+                )
+            }
             HandlingError::InternalServerError => {
                 let body = Box::new(InternalServerError { title }.to_string()) as RepresentationBox;
 
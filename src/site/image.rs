@@ -1,15 +1,70 @@
 use diesel;
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
+use futures::TryStreamExt;
 use r2d2::Pool;
 use r2d2_diesel::ConnectionManager;
-use web::{Get, MediaType, RepresentationBox, Resource, Response};
+use std::io;
+use web::{
+    Get, MediaType, RendererBox, Representation, RepresentationBox, RepresentationsVec, Resource,
+    Response,
+};
 
 use super::auth;
 use super::handling_error::HandlingError;
 use crate::db::schema::*;
 use crate::id30::Id30;
 
+/// The data of an encoding, which is loaded only when content negotiation
+/// selects it, so the other encodings are not read in vain
+struct LazyData<F>(F);
+
+impl<F: FnOnce() -> Result<Vec<u8>, String>> Representation for LazyData<F> {
+    fn body(self: Box<Self>) -> hyper::Body {
+        let data = (self.0)().map_err(|e| {
+            eprintln!("Unable to load image data: {}", e);
+            io::Error::new(io::ErrorKind::Other, e)
+        });
+
+        // Fail the body, so the client does not keep a broken image
+        hyper::Body::wrap_stream(futures::stream::iter(vec![data]).compat())
+    }
+}
+
+/// A representation of the given media type with data from `load`, see
+/// `LazyData`
+fn lazy_data(
+    media_type: String,
+    load: impl FnOnce() -> Result<Vec<u8>, String> + Send + 'static,
+) -> (MediaType, RendererBox) {
+    (
+        MediaType::parse(&media_type),
+        Box::new(move || Box::new(LazyData(load)) as RepresentationBox),
+    )
+}
+
+/// The representations of a rendition, of an image or a thumbnail, in each
+/// of its encodings. The data of the primary encoding is loaded by
+/// `load_primary`, and that of the alternates by `load_alternate` with their
+/// media type, but only for the one that is selected
+pub fn rendition_representations(
+    media_type: String,
+    alternates: Vec<String>,
+    load_primary: impl FnOnce() -> Result<Vec<u8>, String> + Send + 'static,
+    load_alternate: impl Fn(String) -> Result<Vec<u8>, String> + Clone + Send + 'static,
+) -> RepresentationsVec {
+    // The primary format of the rendition goes first, see rendition_policy.
+    // It is the one that gets served when the client does not prefer any
+    // of the alternates
+    std::iter::once(lazy_data(media_type, load_primary))
+        .chain(alternates.into_iter().map(|media_type| {
+            let load = load_alternate.clone();
+            let key = media_type.clone();
+            lazy_data(media_type, move || load(key))
+        }))
+        .collect()
+}
+
 pub struct Image {
     title: String,
     db_pool: Pool<ConnectionManager<SqliteConnection>>,
@@ -23,44 +78,59 @@ impl Image {
             .get()
             .map_err(|_| HandlingError::InternalServerError)?;
 
-        #[derive(Queryable)]
-        struct Image {
-            #[allow(unused)]
-            id: i32,
-            media_type: String,
-            data: Vec<u8>,
-        }
-
         // TODO Schedule IO operation on some kind of background thread
         // Maybe using spawn_blocking()?
-        let pix: Image = images::table
+        let media_type: String = images::table
             .filter(images::id.eq(self.id))
+            .select(images::media_type)
             .first(&*db_connection)
             .map_err(|_| HandlingError::InternalServerError)?;
 
-        Ok(Response::new(
-            web::Status::Ok,
-            vec![(
-                MediaType::parse(&pix.media_type),
-                Box::new(move || Box::new(pix.data) as RepresentationBox),
-            )],
-        ))
+        let alternates: Vec<String> = image_encodings::table
+            .filter(image_encodings::images_id.eq(self.id))
+            .select(image_encodings::media_type)
+            .load(&*db_connection)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let id = self.id;
+        let db_pool = self.db_pool.clone();
+        let load_primary = move || {
+            images::table
+                .filter(images::id.eq(id))
+                .select(images::data)
+                .first(&*db_pool.get().map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())
+        };
+
+        let db_pool = self.db_pool.clone();
+        let load_alternate = move |media_type: String| {
+            image_encodings::table
+                .filter(image_encodings::images_id.eq(id))
+                .filter(image_encodings::media_type.eq(media_type))
+                .select(image_encodings::data)
+                .first(&*db_pool.get().map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())
+        };
+
+        let representations =
+            rendition_representations(media_type, alternates, load_primary, load_alternate);
+
+        Ok(Response::new(web::Status::Ok, representations))
     }
 }
 
 #[async_trait::async_trait]
 impl Get for Image {
     fn cache_control(&self) -> web::CacheControl {
+        // Not immutable, since the rendition is replaced in place when the
+        // pixur is rerendered. Clients revalidate against the version ETag
         web::CacheControl {
             cacheability: web::Cacheability {
                 private: true,
                 policy: web::CacheabilityPolicy::AllowCaching,
             },
-            revalidation: web::Revalidation {
-                must_revalidate: false,
-                proxy_revalidate: false,
-                immutable: true,
-            },
+            max_age: Some(60),
+            revalidation: Default::default(),
         }
     }
 
@@ -80,14 +150,29 @@ impl auth::authorizer::Consumer for AuthorizationConsumer {
     type Authorization = Id30;
 
     fn authorization(self, id: Id30) -> Result<Resource, web::Error> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        let version: Option<i32> = images::table
+            .filter(images::id.eq(id))
+            .select(images::version)
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        drop(db_connection);
+
         Ok(Resource {
-            etag: None,
+            etag: version.map(|x| web::ETag::Weak(x.to_string())),
             get: Some(Box::new(Image {
                 title: self.title,
                 db_pool: self.db_pool,
                 id,
             })),
             post: None,
+            put: None,
         })
     }
 }
@@ -0,0 +1,100 @@
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use web::{Get, MediaType, RepresentationBox, Resource, Response};
+
+use super::auth;
+use super::handling_error::HandlingError;
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::photo_metadata::PhotoMetadata;
+
+/// The capture details of the photo that the given image is a rendition of
+pub struct ImageMetadata {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    id: Id30,
+}
+
+impl ImageMetadata {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let pixurs_id: Id30 = images_meta::table
+            .filter(images_meta::id.eq(self.id))
+            .select(images_meta::pixurs_id)
+            .first(&*db_connection)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        // Pixurs uploaded before originals were kept have never been
+        // examined, see photo_metadata::backfill
+        let metadata: Option<PhotoMetadata> = photo_metadata::table
+            .filter(photo_metadata::pixurs_id.eq(pixurs_id))
+            .select(crate::photo_metadata::COLUMNS)
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        /// `recorded` tells the viewer whether the metadata is empty because
+        /// it was never recorded, rather than because the photo has none
+        #[derive(serde_derive::Serialize)]
+        struct Info {
+            #[serde(flatten)]
+            metadata: PhotoMetadata,
+            recorded: bool,
+        }
+
+        let info = Info {
+            recorded: metadata.is_some(),
+            metadata: metadata.unwrap_or_default(),
+        };
+
+        let json = serde_json::to_string(&info).map_err(|_| HandlingError::InternalServerError)?;
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::new("application", "json", vec![]),
+                Box::new(move || Box::new(json) as RepresentationBox),
+            )],
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for ImageMetadata {
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// Access is authorized like for the image itself, by
+/// `image::AuthorizationProvider`
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = Id30;
+
+    fn authorization(self, id: Id30) -> Result<Resource, web::Error> {
+        Ok(Resource {
+            etag: None,
+            get: Some(Box::new(ImageMetadata {
+                title: self.title,
+                db_pool: self.db_pool,
+                id,
+            })),
+            post: None,
+            put: None,
+        })
+    }
+}
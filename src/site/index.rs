@@ -7,6 +7,7 @@ use web::{Error, MediaType, RepresentationBox, Resource, Response};
 
 use super::auth;
 use super::handling_error::HandlingError;
+use crate::blurhash;
 use crate::db::schema::*;
 use crate::id30::Id30;
 
@@ -29,7 +30,19 @@ struct Get<'a> {
     self_url: &'a str,
     claims: &'a Option<auth::Claims>,
     is_uploader: Option<UploaderExtra<'a>>,
-    authorized_pixurs: &'a [(Id30, Id30, Id30)],
+    authorized_pixurs: &'a [(Id30, Id30, Id30, String, String)],
+}
+
+type PixurRow = (Id30, Id30, Id30, Option<String>, f32);
+
+// Pixurs have images in several sizes. Select the largest display rendition,
+// to get one row per pixur
+fn largest_image() -> diesel::expression::SqlLiteral<diesel::sql_types::Bool> {
+    diesel::dsl::sql(
+        "images_meta.role = 'display' AND images_meta.width = \
+            (SELECT MAX(width) FROM images_meta AS m \
+                WHERE m.pixurs_id = pixurs.id AND m.role = 'display')",
+    )
 }
 
 impl Index {
@@ -79,9 +92,16 @@ impl Index {
                 if is_uploader.is_some() {
                     pixurs::table
                         .inner_join(images_meta::table)
-                        .order(pixurs::created.desc())
-                        .select((pixurs::id, pixurs::thumbs_id, images_meta::id))
-                        .load::<(Id30, Id30, Id30)>(&*db_connection)
+                        .filter(largest_image())
+                        .order(super::pixurs_sort_time().desc())
+                        .select((
+                            pixurs::id,
+                            pixurs::thumbs_id,
+                            images_meta::id,
+                            pixurs::blurhash,
+                            pixurs::image_aspect_ratio,
+                        ))
+                        .load::<PixurRow>(&*db_connection)
                 } else {
                     pixur_series_authorizations::table
                         .inner_join(
@@ -90,19 +110,33 @@ impl Index {
                                 .on(pixur_series::id
                                     .eq(pixur_series_authorizations::pixur_series_id)),
                         )
-                        .order(pixurs::created.desc())
+                        .filter(largest_image())
+                        .order(super::pixurs_sort_time().desc())
                         .filter(pixur_series_authorizations::sub.eq(&claims.sub))
                         .select((
                             pixur_series_authorizations::pixur_series_id,
                             pixurs::thumbs_id,
                             images_meta::id,
+                            pixurs::blurhash,
+                            pixurs::image_aspect_ratio,
                         ))
-                        .load::<(Id30, Id30, Id30)>(&*db_connection)
+                        .load::<PixurRow>(&*db_connection)
                 }
             })
             .transpose()
             .map_err(|_| HandlingError::InternalServerError)?
-            .unwrap_or_else(|| vec![]);
+            .unwrap_or_else(|| vec![])
+            .into_iter()
+            .map(|(id, thumbs_id, images_id, hash, aspect_ratio)| {
+                let placeholder = hash
+                    .and_then(|hash| blurhash::to_data_uri(&hash, aspect_ratio))
+                    .unwrap_or_default();
+                // Reserve the space of the thumbnail, so the placeholder shows
+                // before the thumbnail is loaded. Keep in sync with style.css
+                let height = format!("{:.0}", 80. / aspect_ratio);
+                (id, thumbs_id, images_id, placeholder, height)
+            })
+            .collect::<Vec<_>>();
 
         Ok(Response::new(
             web::Status::Ok,
@@ -156,6 +190,7 @@ impl auth::ClaimsConsumer for IndexLoader {
                 db_pool: self.db_pool,
             })),
             post: None,
+            put: None,
         })
     }
 }
@@ -1,19 +1,87 @@
-use diesel;
 use diesel::sqlite::SqliteConnection;
 use futures::{compat::Stream01CompatExt, TryStreamExt};
 use r2d2::Pool;
 use r2d2_diesel::ConnectionManager;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 use web::{Post, Resource, Response};
 
 use super::auth;
 use super::auth_provider;
 use super::handling_error::HandlingError;
+use super::multipart;
+use super::query_args::QueryArgsConsumer;
+use crate::archive;
+use crate::id30::Id30;
 use crate::image;
+use crate::ingest_job::{self, SeriesOrder};
 
+/// Read the whole request body, giving up as soon as it exceeds the limit
+pub async fn read_body(body: hyper::Body, limit: usize) -> Result<Vec<u8>, HandlingError> {
+    let mut body = body.compat();
+    let mut data = Vec::new();
+
+    while let Some(chunk) = body
+        .try_next()
+        .await
+        .map_err(|_| HandlingError::InternalServerError)?
+    {
+        if data.len() + chunk.len() > limit {
+            return Err(HandlingError::PayloadTooLarge(
+                "Upload exceeds the size limit",
+            ));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+/// Refuse oversized images right away, rather than in the background
+pub fn check_limits(
+    data: &[u8],
+    format: ::image::ImageFormat,
+    limits: &image::Limits,
+) -> Result<(), HandlingError> {
+    image::check_limits(data, format, limits).map_err(|e| match e {
+        image::Rejected::UnreadableHeader => {
+            HandlingError::BadRequest("Unable to read image header")
+        }
+        image::Rejected::TooLarge { .. } => {
+            HandlingError::PayloadTooLarge("Image dimensions exceed the limits")
+        }
+    })
+}
+
+pub fn json_response(status: web::Status, json: String) -> Response {
+    Response {
+        status,
+        representations: vec![(
+            web::MediaType::new("application", "json", vec![]),
+            Box::new(move || Box::new(json) as web::RepresentationBox),
+        )],
+        cookies: vec![],
+        content_disposition: None,
+    }
+}
+
+/// Accepts a single image as the request body, many as
+/// `multipart/form-data`, or a ZIP archive as `application/zip`
+///
+/// A multipart upload becomes a new series. Each part is an image, except
+/// for an optional `order` field, which is `upload` or `exif`, see
+/// `SeriesOrder`.
+///
+/// The images in an archive are ingested separately, unless the `series`
+/// query argument gives the order of a new series to make of them. Other
+/// files are skipped, as reported in the response.
 pub struct Ingest {
     pub title: String,
     pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
     pub base_url: String,
+    pub limits: image::Limits,
+    pub ingest_queue: Arc<Mutex<Sender<Id30>>>,
+    pub series: Option<SeriesOrder>,
 }
 
 impl Ingest {
@@ -23,44 +91,230 @@ impl Ingest {
         body: hyper::Body,
     ) -> Result<Response, HandlingError> {
         // TODO Real parsing of media type syntax
-        if !content_type.starts_with("image/jpeg") {
-            return Err(HandlingError::BadRequest(
-                "Unacceptable Content-Type, must be image/jpeg",
-            ));
+        let media_type = content_type.split(';').next().unwrap().trim();
+        if media_type == "multipart/form-data" {
+            return self.try_post_multipart(&content_type, body).await;
+        }
+        if media_type == "application/zip" {
+            return self.try_post_zip(body).await;
         }
 
-        let body = body
-            .compat()
-            .try_concat()
-            .await
+        let format = image::image_format(media_type).ok_or(HandlingError::BadRequest(
+            "Unacceptable Content-Type, must be one of image/jpeg, image/png, image/gif, image/tiff, multipart/form-data or application/zip",
+        ))?;
+
+        let body = read_body(body, self.limits.max_upload_bytes).await?;
+        check_limits(&body, format, &self.limits)?;
+
+        let db_connection = self
+            .db_pool
+            .get()
             .map_err(|_| HandlingError::InternalServerError)?;
 
-        let (id, series_id) = image::ingest_jpeg(&body, self.db_pool)
+        let job_id = ingest_job::enqueue(&*db_connection, media_type, &body)
             .map_err(|_| HandlingError::InternalServerError)?;
 
-        let url = format!("{}{}", self.base_url, id);
-        let series_url = format!("{}{}", self.base_url, series_id);
+        self.ingest_queue
+            .lock()
+            .unwrap()
+            .send(job_id)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let status_url = format!("{}ingest/{}", self.base_url, job_id);
 
         #[derive(serde_derive::Serialize)]
         struct IngestResponse<'a> {
-            url: &'a str,
-            series_url: &'a str,
+            status_url: &'a str,
         }
 
         let json = serde_json::to_string(&IngestResponse {
-            url: &url,
+            status_url: &status_url,
+        })
+        .map_err(|_| HandlingError::InternalServerError)?;
+
+        Ok(json_response(web::Status::Accepted(status_url), json))
+    }
+
+    async fn try_post_multipart(
+        self: Box<Self>,
+        content_type: &str,
+        body: hyper::Body,
+    ) -> Result<Response, HandlingError> {
+        let boundary = multipart::boundary(content_type)
+            .ok_or(HandlingError::BadRequest("Missing multipart boundary"))?;
+
+        let body = read_body(body, self.limits.max_series_upload_bytes).await?;
+        let parts = multipart::parse(&body, boundary)
+            .ok_or(HandlingError::BadRequest("Malformed multipart body"))?;
+
+        let mut series_order = SeriesOrder::Upload;
+        let mut uploads = vec![];
+
+        // All files are checked before any of them are stored, so a failed
+        // request leaves nothing behind
+        for part in &parts {
+            if part.name.as_deref() == Some("order") {
+                series_order = std::str::from_utf8(part.data)
+                    .ok()
+                    .and_then(|x| serde_plain::from_str(x.trim()).ok())
+                    .ok_or(HandlingError::BadRequest(
+                        "Unacceptable order, must be upload or exif",
+                    ))?;
+                continue;
+            }
+
+            let media_type = part
+                .content_type
+                .as_ref()
+                .and_then(|x| x.split(';').next())
+                .unwrap_or("")
+                .trim();
+            let format = image::image_format(media_type).ok_or(HandlingError::BadRequest(
+                "Unacceptable Content-Type of file, must be one of image/jpeg, image/png, image/gif or image/tiff",
+            ))?;
+
+            if part.data.len() > self.limits.max_upload_bytes {
+                return Err(HandlingError::PayloadTooLarge(
+                    "A file exceeds the size limit",
+                ));
+            }
+            check_limits(part.data, format, &self.limits)?;
+
+            uploads.push((media_type, part.data));
+        }
+
+        if uploads.is_empty() {
+            return Err(HandlingError::BadRequest("No files in upload"));
+        }
+
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let batch = ingest_job::enqueue_batch(&*db_connection, series_order, &uploads)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let ingest_queue = self.ingest_queue.lock().unwrap();
+        for &job_id in &batch.jobs {
+            ingest_queue
+                .send(job_id)
+                .map_err(|_| HandlingError::InternalServerError)?;
+        }
+
+        let series_url = format!("{}{}", self.base_url, batch.pixur_series_id);
+        let status_urls: Vec<String> = batch
+            .jobs
+            .iter()
+            .map(|job_id| format!("{}ingest/{}", self.base_url, job_id))
+            .collect();
+
+        /// The series is empty until all the files have been processed, which
+        /// is tracked by the status of each file. Its `batch` tells when they
+        /// are, and whether the series was created, which it is not when none
+        /// of the files could be ingested
+        #[derive(serde_derive::Serialize)]
+        struct BatchResponse<'a> {
+            series_url: &'a str,
+            status_urls: &'a [String],
+        }
+
+        let json = serde_json::to_string(&BatchResponse {
             series_url: &series_url,
+            status_urls: &status_urls,
         })
         .map_err(|_| HandlingError::InternalServerError)?;
 
-        Ok(Response {
-            status: web::Status::Created(url),
-            representations: vec![(
-                web::MediaType::new("application", "json", vec![]),
-                Box::new(move || Box::new(json) as web::RepresentationBox),
-            )],
-            cookies: vec![],
+        Ok(json_response(web::Status::Accepted(series_url), json))
+    }
+
+    async fn try_post_zip(self: Box<Self>, body: hyper::Body) -> Result<Response, HandlingError> {
+        let body = read_body(body, self.limits.max_series_upload_bytes).await?;
+        let files = archive::read_zip(&body, &self.limits)
+            .map_err(|_| HandlingError::BadRequest("Malformed ZIP archive"))?;
+
+        let uploads: Vec<(&str, &[u8])> = files
+            .iter()
+            .filter_map(|file| file.content.as_ref().ok())
+            .map(|(media_type, data)| (*media_type, &data[..]))
+            .collect();
+
+        if uploads.is_empty() {
+            return Err(HandlingError::BadRequest("No supported images in archive"));
+        }
+
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let (pixur_series_id, jobs) = match self.series {
+            Some(series_order) => {
+                let batch = ingest_job::enqueue_batch(&*db_connection, series_order, &uploads)
+                    .map_err(|_| HandlingError::InternalServerError)?;
+                (Some(batch.pixur_series_id), batch.jobs)
+            }
+            None => {
+                let jobs = ingest_job::enqueue_all(&*db_connection, &uploads)
+                    .map_err(|_| HandlingError::InternalServerError)?;
+                (None, jobs)
+            }
+        };
+
+        let ingest_queue = self.ingest_queue.lock().unwrap();
+        for &job_id in &jobs {
+            ingest_queue
+                .send(job_id)
+                .map_err(|_| HandlingError::InternalServerError)?;
+        }
+
+        let series_url = pixur_series_id.map(|id| format!("{}{}", self.base_url, id));
+        let mut status_urls = jobs
+            .iter()
+            .map(|job_id| format!("{}ingest/{}", self.base_url, job_id));
+
+        /// Each file in the archive has either a status URL, or the reason
+        /// it was skipped
+        #[derive(serde_derive::Serialize)]
+        struct FileReport<'a> {
+            name: &'a str,
+            status_url: Option<String>,
+            skipped: Option<&'a str>,
+        }
+
+        #[derive(serde_derive::Serialize)]
+        struct ArchiveResponse<'a> {
+            series_url: Option<&'a str>,
+            files: Vec<FileReport<'a>>,
+        }
+
+        let files: Vec<FileReport> = files
+            .iter()
+            .map(|file| match &file.content {
+                Ok(_) => FileReport {
+                    name: &file.name,
+                    status_url: status_urls.next(),
+                    skipped: None,
+                },
+                Err(reason) => FileReport {
+                    name: &file.name,
+                    status_url: None,
+                    skipped: Some(reason),
+                },
+            })
+            .collect();
+
+        let location = series_url
+            .clone()
+            .unwrap_or_else(|| format!("{}ingest/{}", self.base_url, jobs[0]));
+
+        let json = serde_json::to_string(&ArchiveResponse {
+            series_url: series_url.as_deref(),
+            files,
         })
+        .map_err(|_| HandlingError::InternalServerError)?;
+
+        Ok(json_response(web::Status::Accepted(location), json))
     }
 }
 
@@ -79,6 +333,9 @@ pub struct AuthorizationConsumer {
     pub title: String,
     pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
     pub base_url: String,
+    pub limits: image::Limits,
+    pub ingest_queue: Arc<Mutex<Sender<Id30>>>,
+    pub series: Option<SeriesOrder>,
 }
 
 impl auth::authorizer::Consumer for AuthorizationConsumer {
@@ -92,7 +349,48 @@ impl auth::authorizer::Consumer for AuthorizationConsumer {
                 title: self.title,
                 db_pool: self.db_pool,
                 base_url: self.base_url,
+                limits: self.limits,
+                ingest_queue: self.ingest_queue,
+                series: self.series,
             })),
+            put: None,
         })
     }
 }
+
+#[derive(serde_derive::Deserialize)]
+pub struct IngestArgs {
+    series: Option<SeriesOrder>,
+}
+
+pub struct IngestArgsConsumer {
+    pub title: String,
+    pub key: Vec<u8>,
+    pub path: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub base_url: String,
+    pub limits: image::Limits,
+    pub ingest_queue: Arc<Mutex<Sender<Id30>>>,
+}
+
+impl QueryArgsConsumer for IngestArgsConsumer {
+    type Args = IngestArgs;
+
+    fn args(self, args: Self::Args) -> Result<Box<dyn web::CookieHandler + Send>, web::Error> {
+        let provider = auth_provider::CanEditProvider {
+            db_pool: self.db_pool.clone(),
+        };
+        let consumer = AuthorizationConsumer {
+            title: self.title.clone(),
+            db_pool: self.db_pool,
+            base_url: self.base_url,
+            limits: self.limits,
+            ingest_queue: self.ingest_queue,
+            series: args.series,
+        };
+        let authorizer =
+            auth::authorizer::Authorizer::new(self.title, self.path, provider, consumer);
+
+        Ok(Box::new(auth::JwtCookieHandler::new(self.key, authorizer)))
+    }
+}
@@ -0,0 +1,200 @@
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use web::{Get, MediaType, RepresentationBox, Resource, Response};
+
+use super::auth;
+use super::auth_provider;
+use super::handling_error::HandlingError;
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::ingest_job::Stage;
+use crate::rendition_policy::Role;
+
+/// The progress of an upload that is being processed in the background,
+/// see `ingest_job`. Polled by the uploader until the stage is `done` or
+/// `failed`
+pub struct IngestStatus {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    base_url: String,
+    id: Id30,
+}
+
+#[derive(serde_derive::Serialize)]
+struct Duplicate {
+    url: String,
+    thumb_url: String,
+    image_url: String,
+}
+
+/// The outcome of the multi-file upload that the file is part of. The
+/// series is created when all the files are processed, and only if any of
+/// them were ingested
+#[derive(serde_derive::Serialize)]
+struct BatchStatus {
+    finished: bool,
+    series_url: Option<String>,
+}
+
+#[derive(serde_derive::Serialize)]
+struct StatusResponse {
+    stage: Stage,
+
+    // When done
+    url: Option<String>,
+    series_url: Option<String>,
+    duplicates: Vec<Duplicate>,
+
+    // When failed
+    error: Option<String>,
+
+    // When part of a multi-file upload
+    batch: Option<BatchStatus>,
+}
+
+impl IngestStatus {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let (stage, pixurs_id, pixur_series_id, duplicates, error, batch_id): (
+            Stage,
+            Option<Id30>,
+            Option<Id30>,
+            Option<String>,
+            Option<String>,
+            Option<Id30>,
+        ) = ingest_jobs::table
+            .filter(ingest_jobs::id.eq(self.id))
+            .select((
+                ingest_jobs::stage,
+                ingest_jobs::pixurs_id,
+                ingest_jobs::pixur_series_id,
+                ingest_jobs::duplicates,
+                ingest_jobs::error,
+                ingest_jobs::batch_id,
+            ))
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| HandlingError::InternalServerError)?
+            .ok_or(HandlingError::BadRequest("No such upload"))?;
+
+        let duplicates: Vec<String> = duplicates
+            .map(|x| serde_json::from_str(&x))
+            .transpose()
+            .map_err(|_| HandlingError::InternalServerError)?
+            .unwrap_or_default();
+
+        let duplicates = duplicates
+            .into_iter()
+            .filter_map(|x| x.parse::<Id30>().ok())
+            .map(|pixurs_id| {
+                let (thumbs_id, images_id): (Id30, Id30) = pixurs::table
+                    .inner_join(images_meta::table)
+                    .filter(pixurs::id.eq(pixurs_id))
+                    .filter(images_meta::role.eq(Role::Display))
+                    .order(images_meta::width.desc())
+                    .select((pixurs::thumbs_id, images_meta::id))
+                    .first(&*db_connection)?;
+
+                Ok(Duplicate {
+                    url: format!("{}{}", self.base_url, pixurs_id),
+                    thumb_url: format!("thumb/{}", thumbs_id),
+                    image_url: format!("img/{}", images_id),
+                })
+            })
+            .collect::<Result<_, diesel::result::Error>>()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let batch = batch_id
+            .map(|batch_id| {
+                let (batch_series_id, finished): (Id30, Option<chrono::NaiveDateTime>) =
+                    ingest_batches::table
+                        .filter(ingest_batches::id.eq(batch_id))
+                        .select((ingest_batches::pixur_series_id, ingest_batches::finished))
+                        .first(&*db_connection)?;
+
+                let created = finished.is_some()
+                    && diesel::select(diesel::dsl::exists(
+                        pixur_series::table.filter(pixur_series::id.eq(batch_series_id)),
+                    ))
+                    .get_result(&*db_connection)?;
+
+                Ok(BatchStatus {
+                    finished: finished.is_some(),
+                    series_url: Some(format!("{}{}", self.base_url, batch_series_id))
+                        .filter(|_| created),
+                })
+            })
+            .transpose()
+            .map_err(|_: diesel::result::Error| HandlingError::InternalServerError)?;
+
+        let json = serde_json::to_string(&StatusResponse {
+            stage,
+            url: pixurs_id.map(|id| format!("{}{}", self.base_url, id)),
+            series_url: pixur_series_id.map(|id| format!("{}{}", self.base_url, id)),
+            duplicates,
+            error,
+            batch,
+        })
+        .map_err(|_| HandlingError::InternalServerError)?;
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::new("application", "json", vec![]),
+                Box::new(move || Box::new(json) as RepresentationBox),
+            )],
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for IngestStatus {
+    fn cache_control(&self) -> web::CacheControl {
+        web::CacheControl {
+            cacheability: web::Cacheability {
+                private: true,
+                policy: web::CacheabilityPolicy::NoStore,
+            },
+            max_age: None,
+            revalidation: Default::default(),
+        }
+    }
+
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub base_url: String,
+    pub id: Id30,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = auth_provider::CanEdit;
+
+    fn authorization(self, _: Self::Authorization) -> Result<Resource, web::Error> {
+        Ok(Resource {
+            etag: None,
+            get: Some(Box::new(IngestStatus {
+                title: self.title,
+                db_pool: self.db_pool,
+                base_url: self.base_url,
+                id: self.id,
+            })),
+            post: None,
+            put: None,
+        })
+    }
+}
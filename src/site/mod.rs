@@ -1,14 +1,25 @@
 mod auth;
 mod auth_provider;
+mod blocking;
+mod deep_zoom;
+mod download;
 mod handling_error;
 mod image;
+mod image_metadata;
 mod index;
 mod ingest;
+mod ingest_status;
+mod motion_video;
+mod multipart;
+mod pixur_edits;
 mod pixur_meta;
+mod pixur_rerender;
 mod pixur_series;
+mod pixur_series_download;
 mod pixur_series_meta;
 mod query_args;
 mod thumbnail;
+mod upload;
 
 use diesel;
 use diesel::sqlite::SqliteConnection;
@@ -18,6 +29,7 @@ use lettre_email::Mailbox;
 use r2d2::Pool;
 use r2d2_diesel::ConnectionManager;
 use regex::{Regex, RegexSet};
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use web::{Lookup, MediaType, QueryHandler, RepresentationBox, Response};
 
@@ -71,6 +83,12 @@ fn moved_permanently(redirect: impl Into<String>) -> Response {
     )
 }
 
+/// The time pixurs are listed by, see `pixurs.taken_at`. Pixurs without a
+/// known time of capture fall back to the time of upload
+fn pixurs_sort_time() -> diesel::expression::SqlLiteral<diesel::sql_types::Timestamp> {
+    diesel::dsl::sql("COALESCE(pixurs.taken_at, pixurs.created)")
+}
+
 struct StaticAsset {
     media_type: MediaType,
     body: String, // Should be Vec<[u8]>, no?
@@ -95,6 +113,7 @@ fn static_asset(media_type: MediaType, body: String) -> impl QueryHandler {
         etag: None,
         get: Some(Box::new(StaticAsset { media_type, body })),
         post: None,
+        put: None,
     }
 }
 
@@ -103,6 +122,9 @@ pub struct Site<S: Spawn + Clone + Send + Sync + 'static> {
     key: Vec<u8>,
     base_url: String,
     db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    limits: crate::image::Limits,
+    renditions: Arc<RenditionPolicy>,
+    ingest_queue: Arc<Mutex<Sender<Id30>>>,
     mailer: Arc<Mutex<SmtpTransport>>,
     sender: Mailbox,
     spawn: S,
@@ -139,6 +161,7 @@ macro_rules! regex_routes {
 }
 
 use super::id30::Id30;
+use super::rendition_policy::RenditionPolicy;
 fn canonicalize_id30(
     given: &str,
     then: impl Fn(Id30) -> Box<dyn QueryHandler + 'static>,
@@ -162,6 +185,9 @@ impl<S: Spawn + Clone + Send + Sync + 'static> Site<S> {
         key: Vec<u8>,
         base_url: String,
         db_pool: Pool<ConnectionManager<SqliteConnection>>,
+        limits: crate::image::Limits,
+        renditions: RenditionPolicy,
+        ingest_queue: Sender<Id30>,
         mailer: SmtpTransport,
         sender: Mailbox,
         spawn: S,
@@ -171,6 +197,9 @@ impl<S: Spawn + Clone + Send + Sync + 'static> Site<S> {
             key,
             base_url,
             db_pool,
+            limits,
+            renditions: Arc::new(renditions),
+            ingest_queue: Arc::new(Mutex::new(ingest_queue)),
             mailer: Arc::new(Mutex::new(mailer)),
             sender,
             spawn,
@@ -250,6 +279,61 @@ impl<S: Spawn + Clone + Send + Sync + 'static> Site<S> {
                 );
                 Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
             },
+            m = r"^([a-zA-Z0-9]{6})/download\.zip$" => {
+                // Like the series itself, the Id30 is the ID in pixur_series
+
+                let id = m[1].parse().map_err(|_| not_found())?;
+                let provider = pixur_series::AuthorizationProvider { db_pool: self.db_pool.clone(), id };
+                let consumer = pixur_series_download::AuthorizationConsumer {
+                    title: title.clone(),
+                    db_pool: self.db_pool.clone(),
+                };
+                let authorizer = auth::authorizer::Authorizer::new(
+                    title,
+                    path.to_string(),
+                    provider,
+                    consumer,
+                );
+                Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
+            },
+            m = r"^([a-zA-Z0-9]{6})/rerender$" => {
+                // Like /meta, the Id30 in the URL is the ID in the pixurs table
+
+                let id = m[1].parse().map_err(|_| not_found())?;
+                let provider = auth_provider::CanEditProvider { db_pool: self.db_pool.clone() };
+                let consumer = pixur_rerender::AuthorizationConsumer {
+                    title: title.clone(),
+                    db_pool: self.db_pool.clone(),
+                    renditions: self.renditions.clone(),
+                    id,
+                };
+                let authorizer = auth::authorizer::Authorizer::new(
+                    title,
+                    path.to_string(),
+                    provider,
+                    consumer,
+                );
+                Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
+            },
+            m = r"^([a-zA-Z0-9]{6})/edits$" => {
+                // Like /meta, the Id30 in the URL is the ID in the pixurs table
+
+                let id = m[1].parse().map_err(|_| not_found())?;
+                let provider = auth_provider::CanEditProvider { db_pool: self.db_pool.clone() };
+                let consumer = pixur_edits::AuthorizationConsumer {
+                    title: title.clone(),
+                    db_pool: self.db_pool.clone(),
+                    renditions: self.renditions.clone(),
+                    id,
+                };
+                let authorizer = auth::authorizer::Authorizer::new(
+                    title,
+                    path.to_string(),
+                    provider,
+                    consumer,
+                );
+                Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
+            },
             _ = r"^$" => Ok(Box::new(
                 JwtCookieHandler::new(
                     self.key.clone(),
@@ -309,6 +393,7 @@ impl<S: Spawn + Clone + Send + Sync + 'static> Site<S> {
                         sender: self.sender.clone(),
                         spawn: self.spawn.clone(),
                     })),
+                    put: None,
                 })),
             _ = r"^verify_auth$" => Ok(Box::new(query_args::QueryArgsParser::new(VerifyAuthArgsConsumer {
                 title,
@@ -327,9 +412,99 @@ impl<S: Spawn + Clone + Send + Sync + 'static> Site<S> {
                     Box::new(JwtCookieHandler::new(self.key.clone(), authorizer))
                 })
             },
-            _ = r"^img/$" => {
-                let provider = auth_provider::CanEditProvider { db_pool: self.db_pool.clone() };
-                let consumer = ingest::AuthorizationConsumer { title: title.clone(), db_pool: self.db_pool.clone(), base_url: self.base_url.clone() };
+            _ = r"^img/$" => Ok(Box::new(query_args::QueryArgsParser::new(ingest::IngestArgsConsumer {
+                title,
+                key: self.key.clone(),
+                path: path.to_string(),
+                db_pool: self.db_pool.clone(),
+                base_url: self.base_url.clone(),
+                limits: self.limits,
+                ingest_queue: self.ingest_queue.clone(),
+            }))),
+            _ = r"^upload/$" => {
+                let provider = upload::AuthorizationProvider { db_pool: self.db_pool.clone() };
+                let consumer = upload::NewUploadAuthorizationConsumer { title: title.clone(), db_pool: self.db_pool.clone(), base_url: self.base_url.clone(), limits: self.limits };
+                let authorizer = auth::authorizer::Authorizer::new(
+                    title,
+                    path.to_string(),
+                    provider,
+                    consumer,
+                );
+                Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
+            },
+            m = r"^upload/([a-zA-Z0-9]{6})$" => {
+                canonicalize_id30(&m[1], |id| {
+                    let provider = upload::AuthorizationProvider { db_pool: self.db_pool.clone() };
+                    let consumer = upload::AuthorizationConsumer { title: title.clone(), db_pool: self.db_pool.clone(), base_url: self.base_url.clone(), limits: self.limits, ingest_queue: self.ingest_queue.clone(), id };
+                    let authorizer = auth::authorizer::Authorizer::new(
+                        title.clone(),
+                        path.to_string(),
+                        provider,
+                        consumer,
+                    );
+                    Box::new(JwtCookieHandler::new(self.key.clone(), authorizer))
+                })
+            },
+            m = r"^ingest/([a-zA-Z0-9]{6})$" => {
+                canonicalize_id30(&m[1], |id| {
+                    let provider = auth_provider::CanEditProvider { db_pool: self.db_pool.clone() };
+                    let consumer = ingest_status::AuthorizationConsumer { title: title.clone(), db_pool: self.db_pool.clone(), base_url: self.base_url.clone(), id };
+                    let authorizer = auth::authorizer::Authorizer::new(
+                        title.clone(),
+                        path.to_string(),
+                        provider,
+                        consumer,
+                    );
+                    Box::new(JwtCookieHandler::new(self.key.clone(), authorizer))
+                })
+            },
+            m = r"^download/([a-zA-Z0-9]{6})$" => {
+                canonicalize_id30(&m[1], |id| {
+                    let provider = download::AuthorizationProvider { db_pool: self.db_pool.clone(), id };
+                    let consumer = download::AuthorizationConsumer { title: title.clone(), db_pool: self.db_pool.clone(), id };
+                    let authorizer = auth::authorizer::Authorizer::new(
+                        title.clone(),
+                        path.to_string(),
+                        provider,
+                        consumer,
+                    );
+                    Box::new(JwtCookieHandler::new(self.key.clone(), authorizer))
+                })
+            },
+            m = r"^zoom/([a-zA-Z0-9]{6})\.dzi$" => {
+                // The Id30 is the ID in the pixurs table. The tiles are
+                // found relative to this URL, as laid out by DZI
+
+                let id = m[1].parse().map_err(|_| not_found())?;
+                let provider = deep_zoom::AuthorizationProvider { db_pool: self.db_pool.clone(), id };
+                let consumer = deep_zoom::AuthorizationConsumer {
+                    title: title.clone(),
+                    db_pool: self.db_pool.clone(),
+                    id,
+                    part: deep_zoom::Part::Descriptor,
+                };
+                let authorizer = auth::authorizer::Authorizer::new(
+                    title,
+                    path.to_string(),
+                    provider,
+                    consumer,
+                );
+                Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
+            },
+            m = r"^zoom/([a-zA-Z0-9]{6})_files/([0-9]{1,2})/([0-9]{1,5})_([0-9]{1,5})\.[a-z]+$" => {
+                let id = m[1].parse().map_err(|_| not_found())?;
+                let part = deep_zoom::Part::Tile {
+                    level: m[2].parse().map_err(|_| not_found())?,
+                    col: m[3].parse().map_err(|_| not_found())?,
+                    row: m[4].parse().map_err(|_| not_found())?,
+                };
+                let provider = deep_zoom::AuthorizationProvider { db_pool: self.db_pool.clone(), id };
+                let consumer = deep_zoom::AuthorizationConsumer {
+                    title: title.clone(),
+                    db_pool: self.db_pool.clone(),
+                    id,
+                    part,
+                };
                 let authorizer = auth::authorizer::Authorizer::new(
                     title,
                     path.to_string(),
@@ -351,6 +526,34 @@ impl<S: Spawn + Clone + Send + Sync + 'static> Site<S> {
                     Box::new(JwtCookieHandler::new(self.key.clone(), authorizer))
                 })
             },
+            m = r"^img/([a-zA-Z0-9]{6})/metadata$" => {
+                // Don't canonicalize URL, or else the trailing /metadata would disappear
+
+                let id = m[1].parse().map_err(|_| not_found())?;
+                let provider = image::AuthorizationProvider { db_pool: self.db_pool.clone(), id };
+                let consumer = image_metadata::AuthorizationConsumer { title: title.clone(), db_pool: self.db_pool.clone() };
+                let authorizer = auth::authorizer::Authorizer::new(
+                    title,
+                    path.to_string(),
+                    provider,
+                    consumer,
+                );
+                Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
+            },
+            m = r"^img/([a-zA-Z0-9]{6})/video$" => {
+                // Don't canonicalize URL, or else the trailing /video would disappear
+
+                let id = m[1].parse().map_err(|_| not_found())?;
+                let provider = image::AuthorizationProvider { db_pool: self.db_pool.clone(), id };
+                let consumer = motion_video::AuthorizationConsumer { title: title.clone(), db_pool: self.db_pool.clone() };
+                let authorizer = auth::authorizer::Authorizer::new(
+                    title,
+                    path.to_string(),
+                    provider,
+                    consumer,
+                );
+                Ok(Box::new(JwtCookieHandler::new(self.key.clone(), authorizer)))
+            },
             ! => Err(not_found())
         }
     }
@@ -0,0 +1,96 @@
+use diesel;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use web::{Get, MediaType, RepresentationBox, Resource, Response};
+
+use super::auth;
+use super::handling_error::HandlingError;
+use crate::db::schema::*;
+use crate::id30::Id30;
+
+/// The video clip of the motion photo that the given image is a rendition
+/// of, see `motion_photo`
+pub struct MotionVideo {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    id: Id30,
+}
+
+impl MotionVideo {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        // TODO Schedule IO operation on some kind of background thread
+        let (media_type, data): (String, Vec<u8>) = images_meta::table
+            .inner_join(
+                motion_videos::table.on(motion_videos::pixurs_id.eq(images_meta::pixurs_id)),
+            )
+            .filter(images_meta::id.eq(self.id))
+            .select((motion_videos::media_type, motion_videos::data))
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| HandlingError::InternalServerError)?
+            .ok_or(HandlingError::BadRequest("The photo has no video clip"))?;
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::parse(&media_type),
+                Box::new(move || Box::new(data) as RepresentationBox),
+            )],
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for MotionVideo {
+    fn cache_control(&self) -> web::CacheControl {
+        web::CacheControl {
+            cacheability: web::Cacheability {
+                private: true,
+                policy: web::CacheabilityPolicy::AllowCaching,
+            },
+            max_age: None,
+            revalidation: web::Revalidation {
+                must_revalidate: false,
+                proxy_revalidate: false,
+                immutable: true,
+            },
+        }
+    }
+
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// Access is authorized like for the image itself, by
+/// `image::AuthorizationProvider`
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = Id30;
+
+    fn authorization(self, id: Id30) -> Result<Resource, web::Error> {
+        Ok(Resource {
+            etag: None,
+            get: Some(Box::new(MotionVideo {
+                title: self.title,
+                db_pool: self.db_pool,
+                id,
+            })),
+            post: None,
+            put: None,
+        })
+    }
+}
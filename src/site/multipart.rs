@@ -0,0 +1,167 @@
+/// A part of a `multipart/form-data` body
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+    /// The field name, from the Content-Disposition header
+    pub name: Option<String>,
+    pub content_type: Option<String>,
+    pub data: &'a [u8],
+}
+
+/// The value of a parameter of a header value such as
+/// `form-data; name="photos"`, without quotes. Quoted strings with
+/// semicolons or escapes are not supported
+fn parameter<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    value.split(';').skip(1).find_map(|param| {
+        let mut kv = param.splitn(2, '=');
+        let key = kv.next()?.trim();
+        let value = kv.next()?.trim();
+
+        if key.eq_ignore_ascii_case(name) {
+            Some(value.trim_matches('"'))
+        } else {
+            None
+        }
+    })
+}
+
+/// The boundary of a multipart media type, given as the full Content-Type
+pub fn boundary(content_type: &str) -> Option<&str> {
+    parameter(content_type, "boundary").filter(|x| !x.is_empty())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+
+fn part<'a>(headers: &[u8], data: &'a [u8]) -> Part<'a> {
+    let headers = String::from_utf8_lossy(headers);
+
+    let mut name = None;
+    let mut content_type = None;
+    for line in headers.split("\r\n") {
+        let mut kv = line.splitn(2, ':');
+        let (key, value) = match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => continue,
+        };
+
+        if key.eq_ignore_ascii_case("content-disposition") {
+            name = parameter(value, "name").map(str::to_string);
+        } else if key.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.to_string());
+        }
+    }
+
+    Part {
+        name,
+        content_type,
+        data,
+    }
+}
+
+/// Split a multipart body into its parts, as described in RFC 2046. The
+/// data of the parts is borrowed from the body
+///
+/// Returns `None` if the body is malformed, including when it is cut short
+/// before the closing delimiter.
+pub fn parse<'a>(body: &'a [u8], boundary: &str) -> Option<Vec<Part<'a>>> {
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+    // The preamble before the first delimiter is ignored. The first
+    // delimiter may also come first, without a preceding line break
+    let mut pos = if body.starts_with(&delimiter[2..]) {
+        delimiter.len() - 2
+    } else {
+        find(body, &delimiter)? + delimiter.len()
+    };
+
+    let mut parts = vec![];
+    loop {
+        let rest = &body[pos..];
+        if rest.starts_with(b"--") {
+            return Some(parts);
+        }
+
+        // Skip past any whitespace after the delimiter
+        let start = pos + find(rest, b"\r\n")? + 2;
+
+        let (headers, data_start) = if body[start..].starts_with(b"\r\n") {
+            (&body[start..start], start + 2)
+        } else {
+            let headers_end = start + find(&body[start..], b"\r\n\r\n")?;
+            (&body[start..headers_end], headers_end + 4)
+        };
+
+        let data_end = data_start + find(&body[data_start..], &delimiter)?;
+        parts.push(part(headers, &body[data_start..data_end]));
+
+        pos = data_end + delimiter.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn boundary_from_content_type() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=----WebKitFormBoundaryx"),
+            Some("----WebKitFormBoundaryx")
+        );
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; Boundary=\"a b\""),
+            Some("a b")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+    }
+
+    #[test]
+    fn parse_form_data() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"order\"\r\n\
+            \r\n\
+            exif\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"photos\"; filename=\"a.jpg\"\r\n\
+            Content-Type: image/jpeg\r\n\
+            \r\n\
+            \xff\xd8\r\n--X\xff\xd9\r\n\
+            --XyZ\r\n\
+            \r\n\
+            no headers\r\n\
+            --XyZ--\r\n\
+            epilogue";
+
+        assert_eq!(
+            parse(body, "XyZ"),
+            Some(vec![
+                Part {
+                    name: Some("order".to_string()),
+                    content_type: None,
+                    data: b"exif",
+                },
+                Part {
+                    name: Some("photos".to_string()),
+                    content_type: Some("image/jpeg".to_string()),
+                    data: b"\xff\xd8\r\n--X\xff\xd9",
+                },
+                Part {
+                    name: None,
+                    content_type: None,
+                    data: b"no headers",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn malformed_bodies() {
+        assert_eq!(parse(b"--XyZ--", "XyZ"), Some(vec![]));
+        assert_eq!(parse(b"no delimiter", "XyZ"), None);
+
+        let truncated = b"--XyZ\r\nContent-Type: image/jpeg\r\n\r\n\xff\xd8";
+        assert_eq!(parse(truncated, "XyZ"), None);
+    }
+}
@@ -0,0 +1,143 @@
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use std::sync::Arc;
+use web::{Error, Get, MediaType, Post, RepresentationBox, Resource, Response};
+
+use super::auth;
+use super::auth_provider;
+use super::blocking;
+use super::handling_error::HandlingError;
+use super::ingest::read_body;
+use crate::id30::Id30;
+use crate::image;
+use crate::image_edit::{self, Edit};
+use crate::rendition_policy::RenditionPolicy;
+
+/// The edit stack of a pixur. Posting a new stack replaces the old one and
+/// regenerates the renditions from the original
+pub struct PixurEdits {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    renditions: Arc<RenditionPolicy>,
+    id: Id30,
+}
+
+impl PixurEdits {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let edits = image_edit::load(&*db_connection, self.id)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let json = serde_json::to_string(&edits).map_err(|_| HandlingError::InternalServerError)?;
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::new("application", "json", vec![]),
+                Box::new(move || Box::new(json) as RepresentationBox),
+            )],
+        ))
+    }
+
+    async fn try_post(
+        self: Box<Self>,
+        content_type: String,
+        body: hyper::Body,
+    ) -> Result<Response, HandlingError> {
+        if content_type != "application/json" {
+            return Err(HandlingError::BadRequest(
+                "Unacceptable Content-Type, must be application/json",
+            ));
+        }
+
+        // An edit stack is a short list of small objects
+        let body = read_body(body, 64 * 1024).await?;
+
+        let edits: Vec<Edit> =
+            serde_json::from_slice(&body).map_err(|_| HandlingError::BadRequest("Invalid data"))?;
+
+        if !edits.iter().all(Edit::is_valid) {
+            return Err(HandlingError::BadRequest("Edit out of range"));
+        }
+
+        let PixurEdits {
+            db_pool,
+            renditions,
+            id,
+            ..
+        } = *self;
+
+        blocking::run("edit", move || -> Result<(), String> {
+            let db_connection = db_pool.get().map_err(|e| e.to_string())?;
+            image::edit(id, edits, &renditions, &*db_connection).map_err(|e| e.to_string())
+        })
+        .await?
+        .map_err(|e| {
+            eprintln!("Unable to edit pixur {}: {}", id, e);
+            HandlingError::InternalServerError
+        })?;
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::new("text", "plain", vec!["charset=utf-8".to_string()]),
+                Box::new(move || Box::new("OK") as RepresentationBox),
+            )],
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for PixurEdits {
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+#[async_trait::async_trait]
+impl Post for PixurEdits {
+    async fn post(self: Box<Self>, content_type: String, body: hyper::Body) -> Response {
+        let title = self.title.clone();
+
+        self.try_post(content_type, body)
+            .await
+            .unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub renditions: Arc<RenditionPolicy>,
+    pub id: Id30,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = auth_provider::CanEdit;
+
+    fn authorization(self, _: Self::Authorization) -> Result<Resource, Error> {
+        Ok(Resource {
+            etag: None,
+            get: Some(Box::new(PixurEdits {
+                title: self.title.clone(),
+                db_pool: self.db_pool.clone(),
+                renditions: self.renditions.clone(),
+                id: self.id,
+            })),
+            post: Some(Box::new(PixurEdits {
+                title: self.title,
+                db_pool: self.db_pool,
+                renditions: self.renditions,
+                id: self.id,
+            })),
+            put: None,
+        })
+    }
+}
@@ -360,6 +360,7 @@ impl PixurMeta {
                         Box::new(move || Box::new("OK") as RepresentationBox),
                     )],
                     cookies: vec![],
+                    content_disposition: None,
                 })
             })
             .map_err(|e: diesel::result::Error| {
@@ -421,6 +422,7 @@ impl auth::authorizer::Consumer for AuthorizationConsumer {
                 mailer: self.mailer,
                 sender: self.sender,
             })),
+            put: None,
         })
     }
 }
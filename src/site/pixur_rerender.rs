@@ -0,0 +1,83 @@
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use std::sync::Arc;
+use web::{Error, MediaType, Post, RepresentationBox, Resource, Response};
+
+use super::auth;
+use super::auth_provider;
+use super::blocking;
+use super::handling_error::HandlingError;
+use crate::id30::Id30;
+use crate::image;
+use crate::rendition_policy::RenditionPolicy;
+
+pub struct PixurRerender {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    renditions: Arc<RenditionPolicy>,
+    id: Id30,
+}
+
+impl PixurRerender {
+    async fn try_post(self: Box<Self>) -> Result<Response, HandlingError> {
+        let PixurRerender {
+            db_pool,
+            renditions,
+            id,
+            ..
+        } = *self;
+
+        blocking::run("rerender", move || -> Result<(), String> {
+            let db_connection = db_pool.get().map_err(|e| e.to_string())?;
+            image::rerender(id, &renditions, &*db_connection).map_err(|e| e.to_string())
+        })
+        .await?
+        .map_err(|e| {
+            eprintln!("Unable to rerender pixur {}: {}", id, e);
+            HandlingError::InternalServerError
+        })?;
+
+        Ok(Response::new(
+            web::Status::Ok,
+            vec![(
+                MediaType::new("text", "plain", vec!["charset=utf-8".to_string()]),
+                Box::new(move || Box::new("OK") as RepresentationBox),
+            )],
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl Post for PixurRerender {
+    async fn post(self: Box<Self>, _content_type: String, _body: hyper::Body) -> Response {
+        let title = self.title.clone();
+
+        self.try_post().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub renditions: Arc<RenditionPolicy>,
+    pub id: Id30,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = auth_provider::CanEdit;
+
+    fn authorization(self, _: Self::Authorization) -> Result<Resource, Error> {
+        Ok(Resource {
+            etag: None,
+            get: None,
+            post: Some(Box::new(PixurRerender {
+                title: self.title,
+                db_pool: self.db_pool,
+                renditions: self.renditions,
+                id: self.id,
+            })),
+            put: None,
+        })
+    }
+}
@@ -7,9 +7,11 @@ use web::{Error, MediaType, RepresentationBox, Resource, Response};
 
 use super::auth;
 use super::handling_error::HandlingError;
+use crate::blurhash;
 use crate::comment_position::CommentPosition;
 use crate::db::schema::*;
 use crate::id30::Id30;
+use crate::rendition_policy::Role;
 
 pub struct Pixu {
     title: String,
@@ -18,9 +20,17 @@ pub struct Pixu {
 }
 
 struct Photo {
-    average_color: String,
-    thumb_url: String,
+    background_color: String,
+    top_color: String,
+    bottom_color: String,
+    placeholder_url: String,
     large_url: String,
+    metadata_url: String,
+    video_url: Option<String>,
+    zoom_url: Option<String>,
+    download_url: String,
+    srcset: String,
+    sizes: String,
 
     height: &'static str,
     max_height: Option<String>,
@@ -39,6 +49,7 @@ struct Get<'a> {
     bottom_color: &'a str,
 
     photos: &'a [Photo],
+    download_all_url: Option<String>,
 }
 
 #[derive(Queryable)]
@@ -54,7 +65,6 @@ struct PixurSeries {
 
 #[derive(Queryable)]
 struct Pixurs {
-    #[allow(unused)]
     id: Id30,
     average_color: i32,
     thumbs_id: Id30,
@@ -68,13 +78,22 @@ struct Pixurs {
     crop_right: f32,
     crop_top: f32,
     crop_bottom: f32,
+
+    #[allow(unused)]
+    taken_at: Option<chrono::NaiveDateTime>,
+    #[allow(unused)]
+    perceptual_hash: Option<i64>,
+    blurhash: Option<String>,
+    palette: Option<String>,
+    top_color: Option<i32>,
+    bottom_color: Option<i32>,
 }
 
 fn photo_from_pixurs(
     pix: Pixurs,
     comment: Option<String>,
     comment_position: CommentPosition,
-    large_id: Id30,
+    images: &[(Id30, i32)],
     vh_height: f32,
     vh_height_str: &'static str,
 ) -> Result<Photo, HandlingError> {
@@ -123,10 +142,55 @@ fn photo_from_pixurs(
         background_position_x, background_position_y
     );
 
+    // The image covers the photo element, which is at most 100vw wide and
+    // vh_height tall. Tall viewports cause horizontal cropping, so the image
+    // must be wider than the viewport in that case
+    let sizes = format!("max(100vw, {:.2}vh)", vh_height * aspect);
+
+    let srcset = images
+        .iter()
+        .map(|(id, width)| format!("img/{} {}w", id, width))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let (large_id, _) = images.last().ok_or(HandlingError::InternalServerError)?;
+
+    // The placeholder is inlined to avoid an extra request per photo. Fall
+    // back to the thumbnail for pixurs that have no placeholder
+    let placeholder_url = pix
+        .blurhash
+        .as_ref()
+        .and_then(|hash| blurhash::to_data_uri(hash, aspect))
+        .unwrap_or_else(|| format!("thumb/{}", pix.thumbs_id));
+
+    // The most common color is a better background than the average, which
+    // tends to be muddy. The edge colors continue the photo above and below
+    // it, both between the photos and at the ends of the series. Pixurs that
+    // have not been given a palette fall back to the average
+    let average_color = format!("#{:06x}", pix.average_color);
+    let background_color = pix
+        .palette
+        .as_ref()
+        .and_then(|x| serde_json::from_str::<Vec<String>>(x).ok())
+        .and_then(|x| x.into_iter().next())
+        .unwrap_or_else(|| average_color.clone());
+    let edge_color = |col: Option<i32>| {
+        col.map(|x| format!("#{:06x}", x))
+            .unwrap_or_else(|| average_color.clone())
+    };
+
     Ok(Photo {
-        average_color: format!("#{:06x}", pix.average_color),
-        thumb_url: format!("thumb/{}", pix.thumbs_id),
+        background_color,
+        top_color: edge_color(pix.top_color),
+        bottom_color: edge_color(pix.bottom_color),
+        placeholder_url,
         large_url: format!("img/{}", large_id),
+        metadata_url: format!("img/{}/metadata", large_id),
+        video_url: None,
+        zoom_url: None,
+        download_url: format!("download/{}", pix.id),
+        srcset,
+        sizes,
         height: vh_height_str,
         max_height,
         max_width,
@@ -138,6 +202,8 @@ fn photo_from_pixurs(
 
 impl Pixu {
     async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        use diesel::dsl::*;
+
         let db_connection = self
             .db_pool
             .get()
@@ -163,25 +229,60 @@ impl Pixu {
             .into_iter()
             .map(|(ps, pix)| {
                 // TODO Consolidate to one big query in parent scope, to avoid running O(n) queries
-                // TODO Load all sizes of images and allow client side to pick the best size
-                let large_id: Id30 = images_meta::table
+                let images: Vec<(Id30, i32)> = images_meta::table
                     .filter(images_meta::pixurs_id.eq(pix.id))
-                    .order(images_meta::width.desc())
-                    .select(images_meta::id)
-                    .first(&*db_connection)
+                    .filter(images_meta::role.eq(Role::Display))
+                    .order(images_meta::width.asc())
+                    .select((images_meta::id, images_meta::width))
+                    .load(&*db_connection)
                     .map_err(|_| HandlingError::InternalServerError)?;
 
-                photo_from_pixurs(
+                let has_video: bool = select(exists(
+                    motion_videos::table.filter(motion_videos::pixurs_id.eq(pix.id)),
+                ))
+                .first(&*db_connection)
+                .map_err(|_| HandlingError::InternalServerError)?;
+
+                let has_tiles: bool = select(exists(
+                    deep_zoom_images::table.filter(deep_zoom_images::pixurs_id.eq(pix.id)),
+                ))
+                .first(&*db_connection)
+                .map_err(|_| HandlingError::InternalServerError)?;
+
+                let zoom_url = if has_tiles {
+                    Some(format!("zoom/{}.dzi", pix.id))
+                } else {
+                    None
+                };
+
+                let photo = photo_from_pixurs(
                     pix,
                     ps.comment,
                     ps.comment_position,
-                    large_id,
+                    &images,
                     vh_height,
                     vh_height_str,
-                )
+                )?;
+
+                Ok(Photo {
+                    video_url: if has_video {
+                        Some(format!("{}/video", photo.large_url))
+                    } else {
+                        None
+                    },
+                    zoom_url,
+                    ..photo
+                })
             })
             .collect::<Result<Vec<_>, HandlingError>>()?;
 
+        // A single photo is better downloaded on its own
+        let download_all_url = if photos.len() > 1 {
+            Some(format!("{}/download.zip", self.id))
+        } else {
+            None
+        };
+
         Ok(Response::new(
             web::Status::Ok,
             vec![(
@@ -191,9 +292,10 @@ impl Pixu {
                         super::Layout {
                             title: &self.title,
                             body: &Get {
-                                top_color: &photos.first().unwrap().average_color,
-                                bottom_color: &photos.last().unwrap().average_color,
+                                top_color: &photos.first().unwrap().top_color,
+                                bottom_color: &photos.last().unwrap().bottom_color,
                                 photos: &photos,
+                                download_all_url: download_all_url.clone(),
                             },
                         }
                         .to_string(),
@@ -230,6 +332,7 @@ impl auth::authorizer::Consumer for AuthorizationConsumer {
                 id,
             })),
             post: None,
+            put: None,
         })
     }
 }
@@ -0,0 +1,254 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::{SinkExt, TryStreamExt};
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use std::io::{self, Write};
+use web::{ContentDisposition, Error, MediaType, Representation, Resource, Response};
+
+use super::auth;
+use super::download;
+use super::handling_error::HandlingError;
+use crate::archive::StreamingZip;
+use crate::db::schema::*;
+use crate::id30::Id30;
+
+/// Sent to the client as it is written, to keep memory use down
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// All the photos of a series as a ZIP archive, see `ZipBody`
+pub struct SeriesDownload {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    id: Id30,
+}
+
+#[derive(Queryable)]
+struct Entry {
+    pixurs_id: Id30,
+    created: chrono::NaiveDateTime,
+    taken_at: Option<chrono::NaiveDateTime>,
+    comment: Option<String>,
+}
+
+/// The names of the photo and its comment in the archive. They are numbered
+/// by the position in the series, so they sort in the same order as in the
+/// viewer
+fn names(
+    position: usize,
+    count: usize,
+    id: Id30,
+    taken_at: Option<chrono::NaiveDateTime>,
+    media_type: &str,
+) -> (String, String) {
+    let width = count.to_string().len();
+    let photo = format!(
+        "{:0width$}_{}",
+        position + 1,
+        download::filename(id, taken_at, media_type),
+        width = width
+    );
+    let comment = match photo.rfind('.') {
+        Some(dot) => format!("{}.txt", &photo[..dot]),
+        None => format!("{}.txt", photo),
+    };
+
+    (photo, comment)
+}
+
+fn write_zip(
+    db_pool: &Pool<ConnectionManager<SqliteConnection>>,
+    entries: &[Entry],
+    out: impl Write,
+) -> io::Result<()> {
+    let other = |e: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::Other, e.to_string());
+
+    let mut zip = StreamingZip::new(out);
+
+    for (position, entry) in entries.iter().enumerate() {
+        // Only one photo is loaded at a time
+        let (media_type, data) = {
+            let db_connection = db_pool.get().map_err(|e| other(&e))?;
+            download::largest_rendition(&*db_connection, entry.pixurs_id).map_err(|e| other(&e))?
+        };
+
+        let modified = entry.taken_at.unwrap_or(entry.created);
+        let (photo, comment) = names(
+            position,
+            entries.len(),
+            entry.pixurs_id,
+            entry.taken_at,
+            &media_type,
+        );
+
+        zip.add_file(&photo, modified, &data)?;
+
+        if let Some(text) = &entry.comment {
+            zip.add_file(&comment, modified, text.as_bytes())?;
+        }
+    }
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Passes the written data on to the response body, waiting while the client
+/// is behind
+struct BodyWriter(mpsc::Sender<io::Result<Vec<u8>>>);
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        block_on(self.0.send(Ok(buf.to_vec())))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The archive is written by a thread of its own as the response is sent,
+/// so neither the archive nor all the photos need to be kept in memory
+struct ZipBody {
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    entries: Vec<Entry>,
+}
+
+impl Representation for ZipBody {
+    fn body(self: Box<Self>) -> hyper::Body {
+        let (sender, receiver) = mpsc::channel(2);
+        let mut error_sender = sender.clone();
+
+        let spawned = std::thread::Builder::new()
+            .name("download.zip".to_string())
+            .spawn(move || {
+                let out = io::BufWriter::with_capacity(CHUNK_BYTES, BodyWriter(sender));
+                if let Err(e) = write_zip(&self.db_pool, &self.entries, out) {
+                    eprintln!("Unable to write series archive: {}", e);
+
+                    // Fail the body, so the client sees that the download is
+                    // incomplete
+                    let _ = block_on(error_sender.send(Err(e)));
+                }
+            });
+
+        if let Err(e) = spawned {
+            eprintln!("Unable to write series archive: {}", e);
+        }
+
+        hyper::Body::wrap_stream(receiver.compat())
+    }
+}
+
+impl SeriesDownload {
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let entries: Vec<Entry> = pixur_series::table
+            .inner_join(pixurs::table)
+            .filter(pixur_series::id.eq(self.id))
+            .order(pixur_series::order.asc())
+            .select((
+                pixurs::id,
+                pixurs::created,
+                pixurs::taken_at,
+                pixur_series::comment,
+            ))
+            .load(&*db_connection)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        if entries.is_empty() {
+            return Err(HandlingError::BadRequest("No such series"));
+        }
+
+        let body = ZipBody {
+            db_pool: self.db_pool,
+            entries,
+        };
+
+        Ok(Response {
+            status: web::Status::Ok,
+            representations: vec![(
+                MediaType::new("application", "zip", vec![]),
+                Box::new(move || Box::new(body) as web::RepresentationBox),
+            )],
+            cookies: vec![],
+            content_disposition: Some(ContentDisposition {
+                filename: format!("{}.zip", self.id),
+            }),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl web::Get for SeriesDownload {
+    fn cache_control(&self) -> web::CacheControl {
+        web::CacheControl {
+            cacheability: web::Cacheability {
+                private: true,
+                policy: web::CacheabilityPolicy::NoStore,
+            },
+            max_age: None,
+            revalidation: Default::default(),
+        }
+    }
+
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// Authorized by `pixur_series::AuthorizationProvider`
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = Id30;
+
+    fn authorization(self, id: Id30) -> Result<Resource, Error> {
+        Ok(Resource {
+            etag: None,
+            get: Some(Box::new(SeriesDownload {
+                title: self.title,
+                db_pool: self.db_pool,
+                id,
+            })),
+            post: None,
+            put: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_follow_the_series_order() {
+        let id: Id30 = "qnpsq4".parse().unwrap();
+        let taken_at = chrono::NaiveDate::from_ymd(2020, 9, 5).and_hms(10, 15, 0);
+
+        assert_eq!(
+            names(0, 12, id, Some(taken_at), "image/jpeg"),
+            (
+                "01_20200905_101500_qnpsq4.jpg".to_string(),
+                "01_20200905_101500_qnpsq4.txt".to_string()
+            )
+        );
+        assert_eq!(
+            names(8, 9, id, None, "image/webp"),
+            ("9_qnpsq4.webp".to_string(), "9_qnpsq4.txt".to_string())
+        );
+    }
+}
@@ -30,6 +30,7 @@ pub struct PixurSeriesMeta {
 struct Get<'a> {
     series: &'a [PixurSeriesRow],
     recipients: &'a [(String, bool)],
+    allow_original_download: bool,
 }
 
 #[derive(serde_derive::Deserialize)]
@@ -60,6 +61,9 @@ struct UpdateRequest<'a> {
 
     #[serde(borrow)]
     send_email: Option<EmailDetails<'a>>,
+
+    #[serde(default)]
+    allow_original_download: bool,
 }
 
 #[derive(Queryable)]
@@ -69,6 +73,7 @@ struct PixurSeriesRow {
     comment_position: CommentPosition,
     average_color: i32,
     thumbs_id: Id30,
+    sort_time: chrono::NaiveDateTime,
 }
 
 impl PixurSeriesRow {
@@ -76,6 +81,10 @@ impl PixurSeriesRow {
         format!("#{:06x}", self.average_color)
     }
 
+    fn sort_time(&self) -> String {
+        self.sort_time.format("%Y-%m-%dT%H:%M:%S").to_string()
+    }
+
     fn position_top(&self) -> bool {
         self.comment_position == CommentPosition::Top
     }
@@ -185,6 +194,7 @@ impl PixurSeriesMeta {
                 pixur_series::comment_position,
                 pixurs::average_color,
                 pixurs::thumbs_id,
+                super::pixurs_sort_time(),
             ))
             .filter(pixur_series::id.eq(self.id))
             .order(pixur_series::order.asc())
@@ -205,6 +215,14 @@ impl PixurSeriesMeta {
             .load(&*db_connection)
             .map_err(|_| HandlingError::InternalServerError)?;
 
+        let allow_original_download = pixur_series_settings::table
+            .filter(pixur_series_settings::pixur_series_id.eq(self.id))
+            .select(pixur_series_settings::allow_original_download)
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| HandlingError::InternalServerError)?
+            .unwrap_or(false);
+
         // TODO Could be expressed as JOIN in database:
         let mut i = recipients.into_iter().peekable();
         let mut recipients: Vec<(String, bool)> = vec![];
@@ -227,6 +245,7 @@ impl PixurSeriesMeta {
                             body: &Get {
                                 series: &series,
                                 recipients: &recipients,
+                                allow_original_download,
                             },
                         }
                         .to_string(),
@@ -318,6 +337,13 @@ impl PixurSeriesMeta {
 
                 let new_recipients = delta_update_authorizations(&*db_connection, self.id, update_request.recipients)?;
 
+                diesel::replace_into(pixur_series_settings::table)
+                    .values((
+                        pixur_series_settings::pixur_series_id.eq(self.id),
+                        pixur_series_settings::allow_original_download.eq(update_request.allow_original_download),
+                    ))
+                    .execute(&*db_connection)?;
+
                 if let Some(email_details) = update_request.send_email {
                     self.send_email_notification(
                         &email_details,
@@ -332,6 +358,7 @@ impl PixurSeriesMeta {
                         Box::new(move || Box::new("OK") as RepresentationBox),
                     )],
                     cookies: vec![],
+                    content_disposition: None,
                 })
             })
             .map_err(|e: diesel::result::Error| {
@@ -393,6 +420,7 @@ impl auth::authorizer::Consumer for AuthorizationConsumer {
                 mailer: self.mailer,
                 sender: self.sender,
             })),
+            put: None,
         })
     }
 }
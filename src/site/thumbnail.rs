@@ -3,10 +3,11 @@ use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use r2d2::Pool;
 use r2d2_diesel::ConnectionManager;
-use web::{Get, MediaType, RepresentationBox, Resource, Response};
+use web::{Get, Resource, Response};
 
 use super::auth;
 use super::handling_error::HandlingError;
+use super::image::rendition_representations;
 use crate::db::schema::*;
 use crate::id30::Id30;
 
@@ -23,43 +24,58 @@ impl Thumbnail {
             .get()
             .map_err(|_| HandlingError::InternalServerError)?;
 
-        #[derive(Queryable)]
-        struct Thumbnail {
-            #[allow(unused)]
-            id: i32,
-            media_type: String,
-            data: Vec<u8>,
-        }
-
         // TODO Schedule IO operation on some kind of background thread
-        let pix: Thumbnail = thumbs::table
+        let media_type: String = thumbs::table
             .filter(thumbs::id.eq(self.id))
+            .select(thumbs::media_type)
             .first(&*db_connection)
             .map_err(|_| HandlingError::InternalServerError)?;
 
-        Ok(Response::new(
-            web::Status::Ok,
-            vec![(
-                MediaType::parse(&pix.media_type),
-                Box::new(move || Box::new(pix.data) as RepresentationBox),
-            )],
-        ))
+        let alternates: Vec<String> = thumb_encodings::table
+            .filter(thumb_encodings::thumbs_id.eq(self.id))
+            .select(thumb_encodings::media_type)
+            .load(&*db_connection)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let id = self.id;
+        let db_pool = self.db_pool.clone();
+        let load_primary = move || {
+            thumbs::table
+                .filter(thumbs::id.eq(id))
+                .select(thumbs::data)
+                .first(&*db_pool.get().map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())
+        };
+
+        let db_pool = self.db_pool.clone();
+        let load_alternate = move |media_type: String| {
+            thumb_encodings::table
+                .filter(thumb_encodings::thumbs_id.eq(id))
+                .filter(thumb_encodings::media_type.eq(media_type))
+                .select(thumb_encodings::data)
+                .first(&*db_pool.get().map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())
+        };
+
+        let representations =
+            rendition_representations(media_type, alternates, load_primary, load_alternate);
+
+        Ok(Response::new(web::Status::Ok, representations))
     }
 }
 
 #[async_trait::async_trait]
 impl Get for Thumbnail {
     fn cache_control(&self) -> web::CacheControl {
+        // Not immutable, since the rendition is replaced in place when the
+        // pixur is rerendered. Clients revalidate against the version ETag
         web::CacheControl {
             cacheability: web::Cacheability {
                 private: true,
                 policy: web::CacheabilityPolicy::AllowCaching,
             },
-            revalidation: web::Revalidation {
-                must_revalidate: false,
-                proxy_revalidate: false,
-                immutable: true,
-            },
+            max_age: Some(60),
+            revalidation: Default::default(),
         }
     }
 
@@ -78,14 +94,29 @@ impl auth::authorizer::Consumer for AuthorizationConsumer {
     type Authorization = Id30;
 
     fn authorization<'a>(self, id: Id30) -> Result<Resource, web::Error> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        let version: Option<i32> = thumbs::table
+            .filter(thumbs::id.eq(id))
+            .select(thumbs::version)
+            .first(&*db_connection)
+            .optional()
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        drop(db_connection);
+
         Ok(Resource {
-            etag: None,
+            etag: version.map(|x| web::ETag::Weak(x.to_string())),
             get: Some(Box::new(Thumbnail {
                 title: self.title,
                 db_pool: self.db_pool,
                 id,
             })),
             post: None,
+            put: None,
         })
     }
 }
@@ -0,0 +1,452 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use r2d2::Pool;
+use r2d2_diesel::ConnectionManager;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use web::{Get, Post, Put, Resource, Response};
+
+use super::auth;
+use super::handling_error::HandlingError;
+use super::ingest::{check_limits, json_response, read_body};
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::image;
+use crate::upload_session::{self, UploadSession};
+
+/// A byte range as given by `Content-Range: bytes 0-1023/4096`. The end is
+/// exclusive, unlike in the header
+#[derive(Debug, PartialEq)]
+struct ContentRange {
+    start: i64,
+    end: i64,
+    size: i64,
+}
+
+fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let value = value.trim();
+    if !value.starts_with("bytes ") {
+        return None;
+    }
+
+    let mut range_size = value[6..].splitn(2, '/');
+    let mut range = range_size.next()?.splitn(2, '-');
+    let start: i64 = range.next()?.parse().ok()?;
+    let last: i64 = range.next()?.parse().ok()?;
+    let size: i64 = range_size.next()?.parse().ok()?;
+
+    if start > last || last >= size {
+        return None;
+    }
+
+    Some(ContentRange {
+        start,
+        end: last + 1,
+        size,
+    })
+}
+
+/// The state of an upload session, as reported to the uploader. When
+/// `offset` falls short of `size`, the upload continues from `offset`
+#[derive(serde_derive::Serialize)]
+struct Progress {
+    offset: i64,
+    size: i64,
+
+    /// When complete, see `ingest_status`
+    status_url: Option<String>,
+}
+
+fn progress_response(
+    status: web::Status,
+    session: &UploadSession,
+    base_url: &str,
+) -> Result<Response, HandlingError> {
+    let json = serde_json::to_string(&Progress {
+        offset: session.received,
+        size: session.size,
+        status_url: session
+            .ingest_jobs_id
+            .map(|id| format!("{}ingest/{}", base_url, id)),
+    })
+    .map_err(|_| HandlingError::InternalServerError)?;
+
+    Ok(json_response(status, json))
+}
+
+/// Starts a resumable upload, for when a single request to `Ingest` is
+/// likely to be interrupted. The request body is JSON with the
+/// `media_type` and `size` of the file
+///
+/// The file is then sent to the returned session URL, see `Upload`. Stale
+/// sessions are cleaned up here, see `upload_session::delete_stale`.
+pub struct NewUpload {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    base_url: String,
+    limits: image::Limits,
+    sub: String,
+}
+
+impl NewUpload {
+    async fn try_post(
+        self: Box<Self>,
+        content_type: String,
+        body: hyper::Body,
+    ) -> Result<Response, HandlingError> {
+        // TODO Real parsing of media type syntax
+        if content_type.split(';').next().unwrap().trim() != "application/json" {
+            return Err(HandlingError::BadRequest(
+                "Unacceptable Content-Type, must be application/json",
+            ));
+        }
+
+        #[derive(serde_derive::Deserialize)]
+        struct Args {
+            media_type: String,
+            size: i64,
+        }
+
+        let body = read_body(body, 4096).await?;
+        let args: Args = serde_json::from_slice(&body).map_err(|_| {
+            HandlingError::BadRequest("Malformed JSON, must have media_type and size")
+        })?;
+
+        image::image_format(&args.media_type).ok_or(HandlingError::BadRequest(
+            "Unacceptable media_type, must be one of image/jpeg, image/png, image/gif or image/tiff",
+        ))?;
+
+        if args.size <= 0 {
+            return Err(HandlingError::BadRequest("The size must be positive"));
+        }
+        if args.size > self.limits.max_upload_bytes as i64 {
+            return Err(HandlingError::PayloadTooLarge(
+                "Upload exceeds the size limit",
+            ));
+        }
+
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        upload_session::delete_stale(&*db_connection)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let id = upload_session::create(&*db_connection, &self.sub, &args.media_type, args.size)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        let session = UploadSession {
+            media_type: args.media_type,
+            size: args.size,
+            received: 0,
+            ingest_jobs_id: None,
+        };
+
+        progress_response(
+            web::Status::Created(format!("{}upload/{}", self.base_url, id)),
+            &session,
+            &self.base_url,
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl Post for NewUpload {
+    async fn post(self: Box<Self>, content_type: String, body: hyper::Body) -> Response {
+        let title = self.title.clone();
+
+        self.try_post(content_type, body)
+            .await
+            .unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// An upload session, see `upload_session`. GET reports how much has been
+/// received, and PUT continues the upload from there with a byte range
+/// given by `Content-Range`
+///
+/// A PUT that starts anywhere else is refused with 409 Conflict, so the
+/// uploader can resume from the reported offset. When the last byte is
+/// received, the file is handed over to `ingest_job`, and the response is
+/// 202 Accepted with the status URL of the ingest job. Only the uploader
+/// who started the session has access to it.
+pub struct Upload {
+    title: String,
+    db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    base_url: String,
+    limits: image::Limits,
+    ingest_queue: Arc<Mutex<Sender<Id30>>>,
+    id: Id30,
+    sub: String,
+}
+
+impl Upload {
+    fn session(&self) -> Result<UploadSession, HandlingError> {
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        upload_session::get(&*db_connection, self.id, &self.sub)
+            .map_err(|_| HandlingError::InternalServerError)?
+            .ok_or(HandlingError::BadRequest("No such upload"))
+    }
+
+    async fn try_get(self: Box<Self>) -> Result<Response, HandlingError> {
+        progress_response(web::Status::Ok, &self.session()?, &self.base_url)
+    }
+
+    async fn try_put(
+        self: Box<Self>,
+        headers: hyper::HeaderMap,
+        body: hyper::Body,
+    ) -> Result<Response, HandlingError> {
+        let range = headers
+            .get(hyper::header::CONTENT_RANGE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(parse_content_range)
+            .ok_or(HandlingError::BadRequest(
+                "Missing or malformed Content-Range, must be like bytes 0-1023/4096",
+            ))?;
+
+        let mut session = self.session()?;
+        if range.size != session.size {
+            return Err(HandlingError::BadRequest(
+                "The size in Content-Range does not match the upload",
+            ));
+        }
+        if range.start != session.received || session.ingest_jobs_id.is_some() {
+            return progress_response(web::Status::Conflict, &session, &self.base_url);
+        }
+
+        let len = (range.end - range.start) as usize;
+        let chunk = read_body(body, len).await?;
+        if chunk.len() != len {
+            return Err(HandlingError::BadRequest(
+                "The body is shorter than given by Content-Range",
+            ));
+        }
+
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        if range.end < session.size {
+            let appended =
+                upload_session::append(&*db_connection, self.id, &self.sub, range.start, &chunk);
+            match appended {
+                Ok(received) => {
+                    session.received = received;
+                    return progress_response(web::Status::Ok, &session, &self.base_url);
+                }
+                Err(upload_session::Error::OutOfOrder { received }) => {
+                    session.received = received;
+                    return progress_response(web::Status::Conflict, &session, &self.base_url);
+                }
+                Err(upload_session::Error::NoSuchUpload) => {
+                    return Err(HandlingError::BadRequest("No such upload"));
+                }
+                Err(upload_session::Error::Db(err)) => {
+                    eprintln!("upload_session::append({}): {}", self.id, err);
+                    return Err(HandlingError::InternalServerError);
+                }
+            }
+        }
+
+        let mut data = upload_session::received_data(&*db_connection, self.id)
+            .map_err(|_| HandlingError::InternalServerError)?;
+        data.extend_from_slice(&chunk);
+
+        let format =
+            image::image_format(&session.media_type).ok_or(HandlingError::InternalServerError)?;
+        check_limits(&data, format, &self.limits)?;
+
+        let completed =
+            upload_session::complete(&*db_connection, self.id, &self.sub, range.start, &data);
+        let job_id = match completed {
+            Ok(job_id) => job_id,
+            Err(upload_session::Error::OutOfOrder { received }) => {
+                session.received = received;
+                return progress_response(web::Status::Conflict, &session, &self.base_url);
+            }
+            Err(upload_session::Error::NoSuchUpload) => {
+                return Err(HandlingError::BadRequest("No such upload"));
+            }
+            Err(upload_session::Error::Db(err)) => {
+                eprintln!("upload_session::complete({}): {}", self.id, err);
+                return Err(HandlingError::InternalServerError);
+            }
+        };
+
+        self.ingest_queue
+            .lock()
+            .unwrap()
+            .send(job_id)
+            .map_err(|_| HandlingError::InternalServerError)?;
+
+        session.received = session.size;
+        session.ingest_jobs_id = Some(job_id);
+
+        let status_url = format!("{}ingest/{}", self.base_url, job_id);
+        progress_response(web::Status::Accepted(status_url), &session, &self.base_url)
+    }
+}
+
+#[async_trait::async_trait]
+impl Get for Upload {
+    fn cache_control(&self) -> web::CacheControl {
+        web::CacheControl {
+            cacheability: web::Cacheability {
+                private: true,
+                policy: web::CacheabilityPolicy::NoStore,
+            },
+            max_age: None,
+            revalidation: Default::default(),
+        }
+    }
+
+    async fn representations(self: Box<Self>) -> Response {
+        let title = self.title.clone();
+
+        self.try_get().await.unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+#[async_trait::async_trait]
+impl Put for Upload {
+    async fn put(self: Box<Self>, headers: hyper::HeaderMap, body: hyper::Body) -> Response {
+        let title = self.title.clone();
+
+        self.try_put(headers, body)
+            .await
+            .unwrap_or_else(|e| e.render(&title))
+    }
+}
+
+/// The `sub` of an uploader, who has access to the upload sessions they
+/// started
+pub struct Uploader(String);
+
+/// Like `auth_provider::CanEditProvider`, but keeps the `sub`, see `Uploader`
+pub struct AuthorizationProvider {
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+}
+
+impl auth::authorizer::Provider for AuthorizationProvider {
+    type Authorization = Uploader;
+
+    fn get_authorization(&self, sub: &str) -> Result<Option<Self::Authorization>, web::Error> {
+        use diesel::dsl::*;
+
+        let db_connection = self
+            .db_pool
+            .get()
+            .map_err(|_| web::Error::InternalServerError)?;
+
+        let authorized: bool = select(exists(uploaders::table.filter(uploaders::sub.eq(sub))))
+            .first::<bool>(&*db_connection)
+            .expect("Query must return 1 result");
+
+        if authorized {
+            Ok(Some(Uploader(sub.to_string())))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+pub struct NewUploadAuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub base_url: String,
+    pub limits: image::Limits,
+}
+
+impl auth::authorizer::Consumer for NewUploadAuthorizationConsumer {
+    type Authorization = Uploader;
+
+    fn authorization(self, Uploader(sub): Self::Authorization) -> Result<Resource, web::Error> {
+        Ok(Resource {
+            etag: None,
+            get: None,
+            post: Some(Box::new(NewUpload {
+                title: self.title,
+                db_pool: self.db_pool,
+                base_url: self.base_url,
+                limits: self.limits,
+                sub,
+            })),
+            put: None,
+        })
+    }
+}
+
+pub struct AuthorizationConsumer {
+    pub title: String,
+    pub db_pool: Pool<ConnectionManager<SqliteConnection>>,
+    pub base_url: String,
+    pub limits: image::Limits,
+    pub ingest_queue: Arc<Mutex<Sender<Id30>>>,
+    pub id: Id30,
+}
+
+impl auth::authorizer::Consumer for AuthorizationConsumer {
+    type Authorization = Uploader;
+
+    fn authorization(self, Uploader(sub): Self::Authorization) -> Result<Resource, web::Error> {
+        Ok(Resource {
+            etag: None,
+            get: Some(Box::new(Upload {
+                title: self.title.clone(),
+                db_pool: self.db_pool.clone(),
+                base_url: self.base_url.clone(),
+                limits: self.limits,
+                ingest_queue: self.ingest_queue.clone(),
+                id: self.id,
+                sub: sub.clone(),
+            })),
+            post: None,
+            put: Some(Box::new(Upload {
+                title: self.title,
+                db_pool: self.db_pool,
+                base_url: self.base_url,
+                limits: self.limits,
+                ingest_queue: self.ingest_queue,
+                id: self.id,
+                sub,
+            })),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn content_range() {
+        assert_eq!(
+            parse_content_range("bytes 0-1023/4096"),
+            Some(ContentRange {
+                start: 0,
+                end: 1024,
+                size: 4096,
+            })
+        );
+        assert_eq!(
+            parse_content_range("bytes 4095-4095/4096"),
+            Some(ContentRange {
+                start: 4095,
+                end: 4096,
+                size: 4096,
+            })
+        );
+        assert_eq!(parse_content_range("bytes 0-4096/4096"), None);
+        assert_eq!(parse_content_range("bytes 10-9/4096"), None);
+        assert_eq!(parse_content_range("bytes 0-1023/*"), None);
+        assert_eq!(parse_content_range("bytes */4096"), None);
+        assert_eq!(parse_content_range("items 0-1023/4096"), None);
+    }
+}
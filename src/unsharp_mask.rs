@@ -0,0 +1,130 @@
+use image::{ImageBuffer, Rgb};
+use rayon::prelude::*;
+use serde_derive::Deserialize;
+
+type RgbImageF32 = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+/// Settings for sharpening a rendition after downscaling
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnsharpMask {
+    /// Standard deviation of the Gaussian blur, in pixels of the rendition
+    pub radius: f32,
+
+    /// How much of the difference from the blurred image to add back. 0
+    /// disables sharpening
+    pub amount: f32,
+}
+
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let half = (sigma * 3.).ceil().max(1.) as i32;
+
+    let kernel: Vec<f32> = (-half..=half)
+        .map(|x| (-(x * x) as f32 / (2. * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+
+    kernel.into_iter().map(|x| x / sum).collect()
+}
+
+/// Convolve with the kernel along one axis, clamping at the edges. Each
+/// output row is computed independently, in parallel
+fn convolve(
+    src: &[f32],
+    width: usize,
+    height: usize,
+    kernel: &[f32],
+    horizontal: bool,
+) -> Vec<f32> {
+    let half = (kernel.len() / 2) as isize;
+    let mut dest = vec![0f32; src.len()];
+
+    dest.par_chunks_mut(width * 3)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for x in 0..width {
+                let mut acc = [0f32; 3];
+
+                for (i, &k) in kernel.iter().enumerate() {
+                    let offset = i as isize - half;
+                    let (sx, sy) = if horizontal {
+                        (
+                            (x as isize + offset).max(0).min(width as isize - 1),
+                            y as isize,
+                        )
+                    } else {
+                        (
+                            x as isize,
+                            (y as isize + offset).max(0).min(height as isize - 1),
+                        )
+                    };
+
+                    let index = (sy as usize * width + sx as usize) * 3;
+                    for c in 0..3 {
+                        acc[c] += k * src[index + c];
+                    }
+                }
+
+                row[x * 3..x * 3 + 3].copy_from_slice(&acc);
+            }
+        });
+
+    dest
+}
+
+/// Sharpen an image in linear light by adding back the difference from a
+/// blurred version of it
+pub fn unsharp_mask(img: RgbImageF32, settings: UnsharpMask) -> RgbImageF32 {
+    if settings.amount == 0. || settings.radius <= 0. {
+        return img;
+    }
+
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let kernel = gaussian_kernel(settings.radius);
+
+    let mut data = img.into_raw();
+    let blurred = convolve(&data, w, h, &kernel, true);
+    let blurred = convolve(&blurred, w, h, &kernel, false);
+
+    data.par_iter_mut()
+        .zip(blurred.par_iter())
+        .for_each(|(x, &blurred)| *x += settings.amount * (*x - blurred));
+
+    RgbImageF32::from_raw(width, height, data).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SETTINGS: UnsharpMask = UnsharpMask {
+        radius: 1.,
+        amount: 0.5,
+    };
+
+    #[test]
+    fn flat_areas_are_unchanged() {
+        let img = ImageBuffer::from_pixel(16, 8, Rgb([0.25, 0.5, 0.75]));
+
+        let sharpened = unsharp_mask(img, SETTINGS);
+        for px in sharpened.pixels() {
+            for (&a, &b) in px.data.iter().zip(&[0.25, 0.5, 0.75]) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn edges_get_more_contrast() {
+        let img = ImageBuffer::from_fn(16, 8, |x, _| {
+            let v = if x < 8 { 0.25 } else { 0.75 };
+            Rgb([v, v, v])
+        });
+
+        let sharpened = unsharp_mask(img, SETTINGS);
+        assert!(sharpened.get_pixel(7, 4)[0] < 0.25);
+        assert!(sharpened.get_pixel(8, 4)[0] > 0.75);
+        assert!((sharpened.get_pixel(0, 4)[0] - 0.25).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,285 @@
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use crate::db::schema::*;
+use crate::id30::Id30;
+use crate::ingest_job;
+
+/// Sessions that have not received any data for this long are deleted by
+/// `delete_stale`, which runs at startup and when a new session is started.
+/// An abandoned session is therefore kept until the next upload or restart
+/// after this time
+const EXPIRY_HOURS: i64 = 24;
+
+#[derive(Debug)]
+pub enum Error {
+    /// There is no session with the given ID that was started by the given
+    /// uploader
+    NoSuchUpload,
+
+    /// The chunk does not start where the data received so far ends
+    OutOfOrder {
+        received: i64,
+    },
+    Db(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(err: diesel::result::Error) -> Self {
+        Error::Db(err)
+    }
+}
+
+#[derive(Queryable, Debug)]
+pub struct UploadSession {
+    pub media_type: String,
+    pub size: i64,
+    pub received: i64,
+
+    /// Set when the upload is complete
+    pub ingest_jobs_id: Option<Id30>,
+}
+
+/// Start a resumable upload of a file of the given size. The data is
+/// received in order, in chunks of any size, see `append` and `complete`.
+/// Only the uploader given by `sub` has access to the session
+pub fn create(
+    db_connection: &SqliteConnection,
+    sub: &str,
+    media_type: &str,
+    size: i64,
+) -> Result<Id30, diesel::result::Error> {
+    use rand::{rngs::SmallRng, SeedableRng};
+    let mut rng = SmallRng::from_entropy();
+
+    let id = Id30::new_random(&mut rng);
+
+    diesel::insert_into(upload_sessions::table)
+        .values((
+            upload_sessions::id.eq(id),
+            upload_sessions::sub.eq(sub),
+            upload_sessions::media_type.eq(media_type),
+            upload_sessions::size.eq(size),
+        ))
+        .execute(db_connection)?;
+
+    Ok(id)
+}
+
+/// The session with the given ID, if it was started by the given uploader
+pub fn get(
+    db_connection: &SqliteConnection,
+    id: Id30,
+    sub: &str,
+) -> Result<Option<UploadSession>, diesel::result::Error> {
+    upload_sessions::table
+        .filter(upload_sessions::id.eq(id))
+        .filter(upload_sessions::sub.eq(sub))
+        .select((
+            upload_sessions::media_type,
+            upload_sessions::size,
+            upload_sessions::received,
+            upload_sessions::ingest_jobs_id,
+        ))
+        .first(db_connection)
+        .optional()
+}
+
+fn check_offset(
+    db_connection: &SqliteConnection,
+    id: Id30,
+    sub: &str,
+    start: i64,
+) -> Result<(), Error> {
+    let received: i64 = upload_sessions::table
+        .filter(upload_sessions::id.eq(id))
+        .filter(upload_sessions::sub.eq(sub))
+        .select(upload_sessions::received)
+        .first(db_connection)
+        .optional()?
+        .ok_or(Error::NoSuchUpload)?;
+
+    if received != start {
+        return Err(Error::OutOfOrder { received });
+    }
+
+    Ok(())
+}
+
+/// Store a chunk of an upload, returning the number of bytes received so
+/// far. The last chunk is given to `complete` instead
+pub fn append(
+    db_connection: &SqliteConnection,
+    id: Id30,
+    sub: &str,
+    start: i64,
+    data: &[u8],
+) -> Result<i64, Error> {
+    db_connection.transaction(|| {
+        check_offset(db_connection, id, sub, start)?;
+
+        diesel::insert_into(upload_chunks::table)
+            .values((
+                upload_chunks::upload_sessions_id.eq(id),
+                upload_chunks::start.eq(start),
+                upload_chunks::data.eq(data),
+            ))
+            .execute(db_connection)?;
+
+        let received = start + data.len() as i64;
+
+        diesel::update(upload_sessions::table.filter(upload_sessions::id.eq(id)))
+            .set((
+                upload_sessions::received.eq(received),
+                upload_sessions::updated.eq(diesel::dsl::now),
+            ))
+            .execute(db_connection)?;
+
+        Ok(received)
+    })
+}
+
+/// The data stored by `append` so far. The caller is responsible for
+/// checking the uploader with `get` first
+pub fn received_data(
+    db_connection: &SqliteConnection,
+    id: Id30,
+) -> Result<Vec<u8>, diesel::result::Error> {
+    let chunks: Vec<Vec<u8>> = upload_chunks::table
+        .filter(upload_chunks::upload_sessions_id.eq(id))
+        .order(upload_chunks::start.asc())
+        .select(upload_chunks::data)
+        .load(db_connection)?;
+
+    Ok(chunks.concat())
+}
+
+/// Hand a finished upload over to `ingest_job::enqueue`. The given data is
+/// the whole file, that is, `received_data` followed by the last chunk,
+/// which starts at `start`. The caller is responsible for notifying the
+/// worker of the returned job
+pub fn complete(
+    db_connection: &SqliteConnection,
+    id: Id30,
+    sub: &str,
+    start: i64,
+    data: &[u8],
+) -> Result<Id30, Error> {
+    db_connection.transaction(|| {
+        check_offset(db_connection, id, sub, start)?;
+
+        let media_type: String = upload_sessions::table
+            .filter(upload_sessions::id.eq(id))
+            .select(upload_sessions::media_type)
+            .first(db_connection)?;
+
+        let job_id = ingest_job::enqueue(db_connection, &media_type, data)?;
+
+        diesel::delete(upload_chunks::table.filter(upload_chunks::upload_sessions_id.eq(id)))
+            .execute(db_connection)?;
+
+        diesel::update(upload_sessions::table.filter(upload_sessions::id.eq(id)))
+            .set((
+                upload_sessions::received.eq(data.len() as i64),
+                upload_sessions::updated.eq(diesel::dsl::now),
+                upload_sessions::ingest_jobs_id.eq(job_id),
+            ))
+            .execute(db_connection)?;
+
+        Ok(job_id)
+    })
+}
+
+/// Delete the sessions that have not received any data for a while, along
+/// with their chunks, see `EXPIRY_HOURS`. Complete sessions are also deleted
+/// eventually, as the ingest job keeps track of the upload from then on
+pub fn delete_stale(db_connection: &SqliteConnection) -> Result<usize, diesel::result::Error> {
+    let cutoff = chrono::Utc::now().naive_utc() - chrono::Duration::hours(EXPIRY_HOURS);
+
+    db_connection.transaction(|| {
+        let stale = upload_sessions::table
+            .filter(upload_sessions::updated.lt(cutoff))
+            .select(upload_sessions::id);
+
+        diesel::delete(
+            upload_chunks::table.filter(upload_chunks::upload_sessions_id.eq_any(stale)),
+        )
+        .execute(db_connection)?;
+
+        diesel::delete(upload_sessions::table.filter(upload_sessions::updated.lt(cutoff)))
+            .execute(db_connection)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::test_connection;
+
+    #[test]
+    fn upload_in_chunks() {
+        let conn = test_connection();
+        let id = create(&conn, "alice", "image/jpeg", 10).unwrap();
+
+        assert_eq!(append(&conn, id, "alice", 0, b"0123").unwrap(), 4);
+        match append(&conn, id, "alice", 2, b"2345") {
+            Err(Error::OutOfOrder { received: 4 }) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+        assert_eq!(append(&conn, id, "alice", 4, b"456").unwrap(), 7);
+
+        let mut data = received_data(&conn, id).unwrap();
+        assert_eq!(data, b"0123456");
+
+        data.extend_from_slice(b"789");
+        let job_id = complete(&conn, id, "alice", 7, &data).unwrap();
+
+        let session = get(&conn, id, "alice").unwrap().unwrap();
+        assert_eq!(session.received, 10);
+        assert_eq!(session.ingest_jobs_id, Some(job_id));
+        assert!(received_data(&conn, id).unwrap().is_empty());
+
+        let stored: Option<Vec<u8>> = ingest_jobs::table
+            .filter(ingest_jobs::id.eq(job_id))
+            .select(ingest_jobs::data)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(stored.as_deref(), Some(&b"0123456789"[..]));
+    }
+
+    #[test]
+    fn sessions_belong_to_their_uploader() {
+        let conn = test_connection();
+        let id = create(&conn, "alice", "image/jpeg", 10).unwrap();
+
+        assert!(get(&conn, id, "mallory").unwrap().is_none());
+        match append(&conn, id, "mallory", 0, b"0123") {
+            Err(Error::NoSuchUpload) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+        match complete(&conn, id, "mallory", 0, b"0123456789") {
+            Err(Error::NoSuchUpload) => (),
+            x => panic!("Unexpected {:?}", x),
+        }
+
+        assert_eq!(get(&conn, id, "alice").unwrap().unwrap().received, 0);
+    }
+
+    #[test]
+    fn stale_sessions_are_deleted() {
+        let conn = test_connection();
+        let stale = create(&conn, "alice", "image/jpeg", 10).unwrap();
+        let fresh = create(&conn, "alice", "image/jpeg", 10).unwrap();
+        append(&conn, stale, "alice", 0, b"0123").unwrap();
+
+        let long_ago = chrono::NaiveDate::from_ymd(2020, 11, 1).and_hms(12, 0, 0);
+        diesel::update(upload_sessions::table.filter(upload_sessions::id.eq(stale)))
+            .set(upload_sessions::updated.eq(long_ago))
+            .execute(&conn)
+            .unwrap();
+
+        assert_eq!(delete_stale(&conn).unwrap(), 1);
+        assert!(get(&conn, stale, "alice").unwrap().is_none());
+        assert!(get(&conn, fresh, "alice").unwrap().is_some());
+    }
+}